@@ -0,0 +1,46 @@
+#![no_main]
+
+use brainfuck::{
+    differential::compare,
+    generator::{Generator, GeneratorOptions},
+    machine::MachineConfig,
+    source_file::UcSourceFile,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // the first 8 bytes seed a generated program, which always terminates, the rest is
+    // its input
+    let Some((seed, input)) = data.split_first_chunk::<8>() else {
+        return;
+    };
+    let input = input.iter().map(|&b| char::from(b)).collect::<String>();
+    let options = GeneratorOptions {
+        comments: true,
+        ..GeneratorOptions::default()
+    };
+    let program = Generator::new(u64::from_le_bytes(*seed), options).program();
+    let src_file = UcSourceFile::from_str(&program, "generated.bf");
+    let comparison = compare(
+        &src_file,
+        options.config(),
+        &input,
+        Some(options.max_steps()),
+    )
+    .unwrap();
+    assert_eq!(comparison.reference().error, None, "{}", program);
+    assert_eq!(comparison.divergences(), vec![], "{}", program);
+
+    // anything else may run off the tape or never stop, but engines still agree
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    let src_file = UcSourceFile::from_str(s, "fuzz.bf");
+    let config = MachineConfig {
+        tape_size: 64,
+        ..MachineConfig::default()
+    };
+    if let Ok(comparison) = compare(&src_file, config, "input", Some(100_000)) {
+        assert_eq!(comparison.divergences(), vec![], "{}", s);
+    }
+});
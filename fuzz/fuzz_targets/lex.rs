@@ -0,0 +1,34 @@
+#![no_main]
+
+use brainfuck::source_file::{Dialect, UcSourceFile};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    for dialect in [Dialect::Standard, Dialect::Extended] {
+        let src_file = UcSourceFile::from_str_with_dialect(s, "fuzz.bf", dialect);
+
+        // tokens are the code split into graphemes, nothing is lost or added
+        let code = match (dialect, s.find('!')) {
+            (Dialect::Extended, Some(bang)) => &s[..bang],
+            _ => s,
+        };
+        assert!(src_file.iter().all(|t| !t.as_str().is_empty()));
+        assert_eq!(
+            src_file.iter().map(|t| t.as_str()).collect::<String>(),
+            code
+        );
+        assert_eq!(src_file.len(), src_file.iter().count());
+
+        // one line for every line break, plus the last one
+        let lines = (1..).map_while(|n| src_file.line(n)).count();
+        assert_eq!(lines, s.matches('\n').count() + 1);
+
+        // positions of unmatched parens are computed from graphemes
+        if let Err(e) = src_file.check_parens() {
+            let _ = e.to_string();
+        }
+    }
+});
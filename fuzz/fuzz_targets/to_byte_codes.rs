@@ -0,0 +1,42 @@
+#![no_main]
+
+use brainfuck::{byte_code::OptLevel, source_file::UcSourceFile, source_map::SourceMap};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    let src_file = UcSourceFile::from_str(s, "fuzz.bf");
+    let commands = src_file
+        .iter()
+        .filter(|t| "+-<>.,[]".contains(t.as_str()) && t.as_str().len() == 1)
+        .count();
+
+    let mut lens = Vec::new();
+    for &opt_level in OptLevel::all() {
+        let Ok(byte_codes) = src_file.to_byte_codes_with(opt_level) else {
+            assert!(src_file.check_parens().is_err());
+            return;
+        };
+        lens.push(byte_codes.len());
+
+        // byte codes cover the commands in order, each covers one or more of the same
+        let source_map = SourceMap::from_byte_codes(&src_file, &byte_codes);
+        let mut covered = 0;
+        let mut last_end = 0;
+        for m in &source_map.mappings {
+            assert!(last_end <= m.raw_start && m.raw_start < m.raw_end);
+            assert!(m.start < m.end);
+            let raw = &s[m.raw_start..m.raw_end];
+            assert!(raw.chars().all(|c| raw.starts_with(c)));
+            assert!("+-<>.,[]".contains(&raw[..1]));
+            assert!(raw.len() == 1 || opt_level != OptLevel::O0);
+            covered += raw.len();
+            last_end = m.raw_end;
+        }
+        assert_eq!(covered, commands);
+    }
+    // merging never adds byte codes
+    assert!(lens.windows(2).all(|w| w[0] >= w[1]));
+});
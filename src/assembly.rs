@@ -0,0 +1,237 @@
+//! a textual listing of byte codes, one per line, and the parser of it
+//!
+//! ```text
+//! 0000  inc      3        ; 1:1
+//! 0001  jz       -> 0003  ; 1:4
+//! 0002  dec      1        ; 1:5
+//! 0003  jnz      -> 0001  ; 1:6
+//! ```
+//!
+//! the address is optional when assembling, but must be right if it is there. Jumps take
+//! their target with or without `->`, other byte codes a count that is 1 if it is left out.
+//! Everything after `;` is a comment, so source positions are not assembled back
+
+use crate::{
+    byte_code::{check_jumps, ByteCode, ByteCodeKind},
+    source_file::{RawContentIndex, UcSourceFile},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum AssemblyError {
+    UnknownOpcode {
+        line: usize,
+        opcode: String,
+    },
+    /// missing, not a number, zero for a count, too large, or followed by something else
+    BadArgument {
+        line: usize,
+    },
+    /// the address given is not the index of the byte code
+    WrongAddress {
+        line: usize,
+        expected: usize,
+    },
+    /// a jump does not go to its matching one, or has none
+    BadJump {
+        line: usize,
+    },
+}
+
+impl std::fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownOpcode { line, opcode } => {
+                write!(f, "line {}: unknown opcode `{}`", line, opcode)
+            }
+            Self::BadArgument { line } => write!(f, "line {}: bad argument", line),
+            Self::WrongAddress { line, expected } => {
+                write!(f, "line {}: address should be {:04}", line, expected)
+            }
+            Self::BadJump { line } => {
+                write!(f, "line {}: jump does not match its loop", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+const OPCODES: [(ByteCodeKind, &str); 9] = [
+    (ByteCodeKind::IncPtr, "inc_ptr"),
+    (ByteCodeKind::DecPtr, "dec_ptr"),
+    (ByteCodeKind::IncData, "inc"),
+    (ByteCodeKind::DecData, "dec"),
+    (ByteCodeKind::Read, "read"),
+    (ByteCodeKind::Write, "write"),
+    (ByteCodeKind::LoopStartJumpIfDataZero, "jz"),
+    (ByteCodeKind::LoopEndJumpIfDataNotZero, "jnz"),
+    (ByteCodeKind::DebugDump, "dump"),
+];
+
+fn opcode_of(kind: ByteCodeKind) -> &'static str {
+    OPCODES.iter().find(|(k, _)| *k == kind).unwrap().1
+}
+
+/// lists `byte_codes`, with the line and column each one comes from if `src_file` is given
+pub fn disassemble(byte_codes: &[ByteCode], src_file: Option<&UcSourceFile>) -> String {
+    let mut listing = String::new();
+    for (addr, bc) in byte_codes.iter().enumerate() {
+        let arg = if bc.kind.is_jump() {
+            format!("-> {:04}", bc.arg)
+        } else {
+            bc.arg.to_string()
+        };
+        let line = format!("{:04}  {:<8} {:<8}", addr, opcode_of(bc.kind), arg);
+        match src_file {
+            Some(src_file) => listing.push_str(&format!(
+                "{} ; {}\n",
+                line,
+                src_file.pos_of(bc.range_in_raw.0)
+            )),
+            None => {
+                listing.push_str(line.trim_end());
+                listing.push('\n');
+            }
+        }
+    }
+    listing
+}
+
+/// parses a listing of `disassemble`, source ranges of the byte codes are all empty
+pub fn assemble(listing: &str) -> Result<Vec<ByteCode>, AssemblyError> {
+    let mut byte_codes = Vec::new();
+    let mut lines = Vec::new();
+    for (line, text) in listing.lines().enumerate() {
+        let line = line + 1;
+        let text = text.split_once(';').map_or(text, |(code, _)| code);
+        let mut tokens = text.split_whitespace().peekable();
+        let Some(first) = tokens.peek() else {
+            continue;
+        };
+        if first.bytes().all(|b| b.is_ascii_digit()) {
+            let expected = byte_codes.len();
+            if first.parse::<usize>().ok() != Some(expected) {
+                return Err(AssemblyError::WrongAddress { line, expected });
+            }
+            tokens.next();
+        }
+
+        let opcode = tokens.next().ok_or(AssemblyError::BadArgument { line })?;
+        let kind = OPCODES
+            .iter()
+            .find(|(_, name)| *name == opcode)
+            .map(|(kind, _)| *kind)
+            .ok_or_else(|| AssemblyError::UnknownOpcode {
+                line,
+                opcode: opcode.to_owned(),
+            })?;
+        if kind.is_jump() {
+            tokens.next_if_eq(&"->");
+        }
+        let arg = match tokens.next() {
+            None if !kind.is_jump() => 1,
+            arg => arg
+                .and_then(|arg| arg.parse::<usize>().ok())
+                .ok_or(AssemblyError::BadArgument { line })?,
+        };
+        if tokens.next().is_some() {
+            return Err(AssemblyError::BadArgument { line });
+        }
+
+        let empty = RawContentIndex::new(0);
+        let bc = ByteCode::new(kind, arg, (empty, empty))
+            .map_err(|_| AssemblyError::BadArgument { line })?;
+        byte_codes.push(bc);
+        lines.push(line);
+    }
+    check_jumps(&byte_codes).map_err(|e| AssemblyError::BadJump { line: lines[e.idx] })?;
+    Ok(byte_codes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{byte_code::OptLevel, source_file::Dialect};
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_big_error(&AssemblyError::BadJump { line: 1 });
+    }
+
+    #[test]
+    fn listing() {
+        let src_file = UcSourceFile::from_str("+++[\n-]>#.", "");
+        let byte_codes = src_file.to_byte_codes().unwrap();
+        assert_eq!(
+            disassemble(&byte_codes, Some(&src_file)),
+            "\
+0000  inc      3        ; 1:1
+0001  jz       -> 0003  ; 1:4
+0002  dec      1        ; 2:1
+0003  jnz      -> 0001  ; 2:2
+0004  inc_ptr  1        ; 2:3
+0005  write    1        ; 2:5
+"
+        );
+        assert_eq!(
+            disassemble(&byte_codes, None).lines().nth(1),
+            Some("0001  jz       -> 0003")
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = "+[>[<-[]>+[>+++>[+++++++++++>][>]-[<]>-]]++++++++++<]>>>>>>----.<<+++.,#";
+        for &opt_level in OptLevel::all() {
+            let src_file = UcSourceFile::from_str_with_dialect(src, "", Dialect::Extended);
+            let byte_codes = src_file.to_byte_codes_with(opt_level).unwrap();
+            let assembled = assemble(&disassemble(&byte_codes, Some(&src_file))).unwrap();
+            assert_eq!(assembled.len(), byte_codes.len());
+            for (a, b) in assembled.iter().zip(&byte_codes) {
+                assert_eq!((a.kind, a.arg), (b.kind, b.arg));
+            }
+        }
+    }
+
+    #[test]
+    fn hand_written() {
+        let byte_codes = assemble("; clear a cell\ninc 3\njz 3\n  dec\njnz -> 1 ; back\n").unwrap();
+        assert_eq!(
+            byte_codes.iter().map(|bc| bc.arg).collect::<Vec<_>>(),
+            vec![3, 3, 1, 1]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let test_data = [
+            (
+                "inc\nnop",
+                AssemblyError::UnknownOpcode {
+                    line: 2,
+                    opcode: String::from("nop"),
+                },
+            ),
+            ("inc 0", AssemblyError::BadArgument { line: 1 }),
+            ("inc x", AssemblyError::BadArgument { line: 1 }),
+            ("inc 4294967296", AssemblyError::BadArgument { line: 1 }),
+            ("inc 1 2", AssemblyError::BadArgument { line: 1 }),
+            ("jz", AssemblyError::BadArgument { line: 1 }),
+            (
+                "0000 inc\n0002 dec",
+                AssemblyError::WrongAddress {
+                    line: 2,
+                    expected: 1,
+                },
+            ),
+            ("jz 1\njnz 0\njnz 0", AssemblyError::BadJump { line: 3 }),
+            ("jz 1\ninc\njnz 0", AssemblyError::BadJump { line: 1 }),
+            ("jz 1\njnz 1", AssemblyError::BadJump { line: 2 }),
+        ];
+        for (listing, expected) in test_data {
+            assert_eq!(assemble(listing), Err(expected), "{}", listing);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! times the programs of a golden test manifest on every engine, saves the numbers as
+//! json and compares them with a saved baseline
+//!
+//! only evaluation is timed, loading files is not, and every sample runs on a fresh
+//! machine
+
+use crate::{
+    differential::Variant,
+    golden::{GoldenTest, Manifest},
+    machine::Machine,
+    machine_io::BufferedMachineIO,
+};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// timings of one program on one variant
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash, Serialize, Deserialize)]
+pub struct Measurement {
+    pub name: String,
+    /// e.g. `byte-code -O1`
+    pub variant: String,
+    /// executed by each run
+    pub commands: u64,
+    /// nanoseconds of each run, fastest first
+    pub samples: Vec<u64>,
+}
+
+impl Measurement {
+    pub fn fastest(&self) -> Duration {
+        Duration::from_nanos(self.samples.first().copied().unwrap_or_default())
+    }
+
+    /// the upper one of the two in the middle for an even number of samples
+    pub fn median(&self) -> Duration {
+        Duration::from_nanos(
+            self.samples
+                .get(self.samples.len() / 2)
+                .copied()
+                .unwrap_or_default(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash, Serialize, Deserialize)]
+pub struct BenchResults {
+    pub version: u32,
+    pub measurements: Vec<Measurement>,
+}
+
+impl Default for BenchResults {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            measurements: Vec::new(),
+        }
+    }
+}
+
+impl BenchResults {
+    pub const VERSION: u32 = 1;
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn find(&self, name: &str, variant: &str) -> Option<&Measurement> {
+        self.measurements
+            .iter()
+            .find(|m| m.name == name && m.variant == variant)
+    }
+
+    /// medians of measurements in both, in the order of `self`
+    pub fn changes_from(&self, baseline: &Self) -> Vec<Change> {
+        self.measurements
+            .iter()
+            .filter_map(|m| {
+                baseline.find(&m.name, &m.variant).map(|b| Change {
+                    name: m.name.clone(),
+                    variant: m.variant.clone(),
+                    baseline: b.median(),
+                    current: m.median(),
+                })
+            })
+            .collect()
+    }
+
+    /// one line per measurement, with the change from `baseline` if it has the same
+    pub fn report(&self, baseline: Option<&Self>) -> String {
+        let mut s = format!(
+            "{:<24} {:<14} {:>14} {:>14} {:>14} {:>9}\n",
+            "program", "variant", "median", "fastest", "commands", "change"
+        );
+        for m in &self.measurements {
+            let change = baseline
+                .and_then(|b| b.find(&m.name, &m.variant))
+                .map_or(String::from("-"), |b| {
+                    format!("{:+.1}%", (ratio(m.median(), b.median()) - 1.0) * 100.0)
+                });
+            s.push_str(&format!(
+                "{:<24} {:<14} {:>12.3}ms {:>12.3}ms {:>14} {:>9}\n",
+                m.name,
+                m.variant,
+                m.median().as_secs_f64() * 1000.0,
+                m.fastest().as_secs_f64() * 1000.0,
+                m.commands,
+                change
+            ));
+        }
+        s.pop();
+        s
+    }
+}
+
+fn ratio(current: Duration, baseline: Duration) -> f64 {
+    current.as_secs_f64() / baseline.as_secs_f64().max(f64::MIN_POSITIVE)
+}
+
+/// median of one measurement against the baseline
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Change {
+    pub name: String,
+    pub variant: String,
+    pub baseline: Duration,
+    pub current: Duration,
+}
+
+impl Change {
+    /// 1.1 for 10% slower
+    pub fn ratio(&self) -> f64 {
+        ratio(self.current, self.baseline)
+    }
+
+    /// slower by more than `threshold`, e.g. 0.1 for 10%
+    pub fn is_regression(&self, threshold: f64) -> bool {
+        self.ratio() > 1.0 + threshold
+    }
+}
+
+/// runs `test` on `variant` `samples` times, fails if it does not succeed with the
+/// expected output
+pub fn measure(
+    manifest: &Manifest,
+    test: &GoldenTest,
+    variant: Variant,
+    samples: usize,
+) -> Result<Measurement, String> {
+    let src_file = manifest.source_file(test)?;
+    let config = test.config()?;
+    let input = manifest.input(test, &src_file)?;
+    let expected_output = manifest.expected_output(test)?;
+
+    let mut measurement = Measurement {
+        name: test.name(),
+        variant: variant.to_string(),
+        commands: 0,
+        samples: Vec::with_capacity(samples),
+    };
+    for _ in 0..samples {
+        let mut machine = Machine::with_config(config, BufferedMachineIO::new(&input));
+        machine.set_step_limit(test.step_limit);
+        let start = Instant::now();
+        let result = machine.eval(&src_file, variant.engine, variant.opt_level);
+        let elapsed = start.elapsed();
+
+        result.map_err(|e| e.to_string())?;
+        if expected_output
+            .as_ref()
+            .is_some_and(|expected| expected != machine.io().output())
+        {
+            return Err(String::from("output differs from the expected"));
+        }
+        measurement.commands = machine.executed();
+        measurement.samples.push(elapsed.as_nanos() as u64);
+    }
+    measurement.samples.sort_unstable();
+    Ok(measurement)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn measurement(name: &str, samples: &[u64]) -> Measurement {
+        Measurement {
+            name: name.to_owned(),
+            variant: String::from("source"),
+            commands: 10,
+            samples: samples.to_vec(),
+        }
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_default_debug(&BenchResults::default());
+        is_big_value_struct_but_no_default(&measurement("a", &[]));
+    }
+
+    #[test]
+    fn statistics() {
+        let m = measurement("a", &[1, 2, 30]);
+        assert_eq!(m.fastest(), Duration::from_nanos(1));
+        assert_eq!(m.median(), Duration::from_nanos(2));
+        assert_eq!(measurement("a", &[]).median(), Duration::ZERO);
+    }
+
+    #[test]
+    fn changes_from_baseline() {
+        let baseline = BenchResults {
+            measurements: vec![measurement("a", &[100]), measurement("b", &[100])],
+            ..BenchResults::default()
+        };
+        let current = BenchResults {
+            measurements: vec![measurement("a", &[120]), measurement("c", &[100])],
+            ..BenchResults::default()
+        };
+        let changes = current.changes_from(&baseline);
+        assert_eq!(changes.len(), 1);
+        assert!((changes[0].ratio() - 1.2).abs() < 1e-9);
+        assert!(changes[0].is_regression(0.1));
+        assert!(!changes[0].is_regression(0.3));
+
+        let report = current.report(Some(&baseline));
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("a "));
+        assert!(lines[1].ends_with(" +20.0%"));
+        assert!(lines[2].ends_with(" -"));
+
+        assert_eq!(
+            BenchResults::from_json(&current.to_json()).unwrap(),
+            current
+        );
+    }
+
+    #[test]
+    fn measure_checks_output() {
+        let manifest = Manifest::from_json(
+            r#"[{"src_file": "hello_world_1.bf", "output": "hello_world_1.bf.out"},
+                {"src_file": "hello_world_1.bf", "output": "print_12345.bf.out"}]"#,
+            "tests/artifacts",
+        )
+        .unwrap();
+        let variant = Variant::all()[0];
+        let m = measure(&manifest, &manifest.tests()[0], variant, 3).unwrap();
+        assert_eq!(m.samples.len(), 3);
+        assert!(m.samples.windows(2).all(|w| w[0] <= w[1]));
+        assert!(m.commands > 0);
+        assert!(measure(&manifest, &manifest.tests()[1], variant, 1).is_err());
+    }
+}
@@ -0,0 +1,15 @@
+use brainfuck::dap::DapServer;
+use std::process::ExitCode;
+
+/// debug adapter on stdio, launched by editors rather than by hand
+fn main() -> ExitCode {
+    let stdout = std::io::stdout();
+    let mut server = DapServer::new(stdout.lock());
+    match server.serve(std::io::BufReader::new(std::io::stdin())) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(3)
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use brainfuck::{
+    debugger::{Command, Debugger},
+    machine::{CellWidth, EofBehavior, Machine, MachineConfig},
+    machine_io::{DefaultMachineIO, EmbeddedInputIO, MachineIO},
+    source_file::UcSourceFile,
+};
+use clap::Parser;
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+/// the same as of `bfi`
+mod exit_code {
+    pub use brainfuck::golden::exit_status::SOURCE_ERROR;
+
+    /// source or input file cannot be read
+    pub const IO_ERROR: u8 = 3;
+}
+
+/// brainfuck debugger, type `help` at the prompt for commands
+#[derive(Debug, Parser)]
+#[command(name = "bfdb", version)]
+struct Cli {
+    /// number of cells
+    #[arg(long, default_value_t = MachineConfig::default().tape_size)]
+    tape_size: usize,
+
+    /// bits per cell, 8, 16 or 32
+    #[arg(long, default_value_t = CellWidth::default())]
+    cell_width: CellWidth,
+
+    /// value `,` stores on end of input, zero, unchanged or max
+    #[arg(long, default_value_t = EofBehavior::default())]
+    eof: EofBehavior,
+
+    /// read program input from this file, stdin is taken by debugger commands, so the
+    /// program reads nothing without one
+    #[arg(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    src_file: PathBuf,
+}
+
+fn debug<IO: MachineIO>(src_file: &UcSourceFile, machine: Machine<IO>) -> ExitCode {
+    let mut debugger = match Debugger::new(src_file, machine) {
+        Ok(debugger) => debugger,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(exit_code::SOURCE_ERROR);
+        }
+    };
+    println!("{}", debugger.list());
+
+    let mut last = None;
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("(bfdb) ");
+        let _ = std::io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            return ExitCode::SUCCESS;
+        };
+        // an empty line repeats the last command
+        let command = match (line.trim().is_empty(), last) {
+            (true, Some(command)) => command,
+            (true, None) => continue,
+            (false, _) => match line.parse::<Command>() {
+                Ok(command) => command,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            },
+        };
+        if command == Command::Quit {
+            return ExitCode::SUCCESS;
+        }
+        last = Some(command);
+        let report = debugger.execute(&command);
+        let runs = matches!(
+            command,
+            Command::Run | Command::Continue | Command::Step(_) | Command::Next | Command::Finish
+        );
+        if runs {
+            // program output may not end with a line break
+            println!();
+        }
+        println!("{}", report);
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let src_file = match UcSourceFile::new(&cli.src_file) {
+        Ok(src_file) => src_file,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(exit_code::IO_ERROR);
+        }
+    };
+    let config = MachineConfig {
+        tape_size: cli.tape_size,
+        cell_width: cli.cell_width,
+        eof: cli.eof,
+    };
+    match &cli.input {
+        None => {
+            let io = EmbeddedInputIO::new("", DefaultMachineIO::new());
+            debug(&src_file, Machine::with_config(config, io))
+        }
+        Some(path) => match std::fs::File::open(path) {
+            Ok(file) => {
+                let io = DefaultMachineIO::with_input_file(file);
+                debug(&src_file, Machine::with_config(config, io))
+            }
+            Err(e) => {
+                eprintln!("failed to read {}, {}", path.display(), e);
+                ExitCode::from(exit_code::IO_ERROR)
+            }
+        },
+    }
+}
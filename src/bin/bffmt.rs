@@ -0,0 +1,108 @@
+use brainfuck::{
+    formatter::{format, FormatOptions},
+    minifier::{minify, MinifyOptions},
+    source_file::{UcSourceFile, UcSourceFileError},
+};
+
+/// the same as of `bfi`
+mod exit_code {
+    pub use brainfuck::golden::exit_status::SOURCE_ERROR;
+
+    /// `--check` finds the file not formatted
+    pub const NOT_FORMATTED: i32 = 1;
+    pub const BAD_COMMAND_LINE: i32 = 2;
+    /// source file cannot be read or written
+    pub const IO_ERROR: i32 = 3;
+}
+
+fn usage() -> ! {
+    eprintln!("usage: bffmt [--indent <n>] [--width <n>] [--check | --write] <source file>");
+    eprintln!(
+        "       bffmt --minify [--cancel-pairs] [--cancel-pointer-pairs] [--dead-loops] \
+         <source file>"
+    );
+    std::process::exit(exit_code::BAD_COMMAND_LINE);
+}
+
+fn source_error(e: UcSourceFileError<'_>) -> ! {
+    let code = match &e {
+        UcSourceFileError::FileFailToRead { .. } => exit_code::IO_ERROR,
+        UcSourceFileError::UnmatchedParen { src_file, .. } => {
+            eprint!("{}: ", src_file.filename().display());
+            i32::from(exit_code::SOURCE_ERROR)
+        }
+    };
+    eprintln!("{}", e);
+    std::process::exit(code);
+}
+
+fn parse_num(v: Option<String>) -> usize {
+    v.and_then(|v| v.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let mut options = FormatOptions::default();
+    let mut check = false;
+    let mut write = false;
+    let mut minify_options = None;
+    let mut src_file = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--indent" => options.indent = parse_num(args.next()),
+            "--width" => options.line_width = parse_num(args.next()),
+            "--check" => check = true,
+            "--write" => write = true,
+            "--minify" => minify_options = Some(MinifyOptions::default()),
+            "--cancel-pairs" => {
+                minify_options.get_or_insert_with(|| usage()).cancel_pairs = true;
+            }
+            "--cancel-pointer-pairs" => {
+                minify_options
+                    .get_or_insert_with(|| usage())
+                    .cancel_pointer_pairs = true;
+            }
+            "--dead-loops" => {
+                minify_options.get_or_insert_with(|| usage()).dead_loops = true;
+            }
+            _ if src_file.is_none() && !arg.starts_with("--") => src_file = Some(arg),
+            _ => usage(),
+        }
+    }
+    let src_file = src_file.unwrap_or_else(|| usage());
+    if check && write {
+        usage();
+    }
+
+    let src_file = UcSourceFile::new(src_file).unwrap_or_else(|e| source_error(e));
+
+    if let Some(minify_options) = minify_options {
+        if check || write {
+            usage();
+        }
+        let minified = minify(&src_file, &minify_options).unwrap_or_else(|e| source_error(e));
+        print!("{}", minified.code);
+        eprintln!("{}: {}", src_file.filename().display(), minified);
+        return;
+    }
+
+    let formatted = format(&src_file, &options).unwrap_or_else(|e| source_error(e));
+
+    let original = src_file.raw_content();
+    if check {
+        if formatted != original {
+            eprintln!("{} is not formatted", src_file.filename().display());
+            std::process::exit(exit_code::NOT_FORMATTED);
+        }
+    } else if write {
+        if formatted != original {
+            if let Err(e) = std::fs::write(src_file.filename(), formatted) {
+                eprintln!("failed to write {}, {}", src_file.filename().display(), e);
+                std::process::exit(exit_code::IO_ERROR);
+            }
+        }
+    } else {
+        print!("{}", formatted);
+    }
+}
@@ -1,21 +1,1083 @@
-use brainfuck::{machine::create_default_machine, source_file::UcSourceFile};
+use brainfuck::{
+    assembly::disassemble,
+    bench::{measure, BenchResults},
+    byte_code::OptLevel,
+    coverage::CoverageObserver,
+    differential::{compare, Variant},
+    golden::Manifest,
+    lint::{lint, LintCode},
+    machine::{CellWidth, Engine, EofBehavior, Machine, MachineConfig, MachineError},
+    machine_io::{DefaultMachineIO, EmbeddedInputIO, MachineIO, StderrDebugSink},
+    object::ObjectFile,
+    observer::{CountingObserver, Observer, TimingObserver},
+    profiler::{Profile, Profiler},
+    repl::{Feed, Repl},
+    source_file::{Dialect, UcSourceFile, UcSourceFileError},
+    source_map::SourceMap,
+    trace::{Replay, TraceHeader, TraceRecorder},
+};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, process::ExitCode};
 
-fn main() {
-    let mut args = std::env::args();
-    let src_file = args.nth(1).unwrap_or_else(|| {
-        panic!("expecting a source file");
-    });
-    assert!(args.next().is_none());
+/// exit codes other than 0 (success)
+mod exit_code {
+    pub use brainfuck::golden::exit_status::{RUNTIME_ERROR, SOURCE_ERROR};
 
-    let mut machine = create_default_machine();
+    /// `lint` found warnings
+    pub const LINT_WARNINGS: u8 = 1;
+    /// `test` has failures
+    pub const TEST_FAILURES: u8 = 1;
+    /// `diff` finds engines disagreeing
+    pub const DIVERGENCES: u8 = 1;
+    /// `bench` finds programs slower than the baseline
+    pub const REGRESSIONS: u8 = 1;
+    /// options that cannot be used together, most are reported by clap with the same code
+    pub const BAD_COMMAND_LINE: u8 = 2;
+    /// source or input file cannot be read, or an output file cannot be written
+    pub const IO_ERROR: u8 = 3;
+}
+
+/// brainfuck interpreter
+#[derive(Debug, Parser)]
+#[command(name = "bfi", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+impl Cli {
+    /// `bfi [OPTIONS] <SRC_FILE>` is short for `bfi run [OPTIONS] <SRC_FILE>`
+    fn parse_with_default_subcommand() -> Self {
+        let mut args = std::env::args_os().collect::<Vec<_>>();
+        let cmd = Self::command();
+        let is_explicit = args.get(1).is_none_or(|arg| {
+            let arg = arg.to_string_lossy();
+            ["-h", "--help", "-V", "--version", "help"].contains(&arg.as_ref())
+                || cmd.get_subcommands().any(|sub| sub.get_name() == arg)
+        });
+        if !is_explicit {
+            args.insert(1, "run".into());
+        }
+        Self::parse_from(args)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// run a source file or a compiled `.bfc` program, the default when no subcommand is
+    /// given
+    Run(RunArgs),
+    /// compile a source file with the machine configuration into a `.bfc` program
+    Compile(CompileArgs),
+    /// list the byte codes of a source file or a `.bfc` program
+    Disasm(DisasmArgs),
+    /// report suspicious constructs, exits with 1 if there is any
+    Lint(LintArgs),
+    /// run lines as they are typed on the same tape, `:reset` clears the tape
+    Repl(ReplArgs),
+    /// step forwards and backwards through a trace written by `run --record`
+    Replay(ReplayArgs),
+    /// run the programs of a json manifest on every engine and compare their output and
+    /// exit status, exits with 1 if any fails
+    Test(TestArgs),
+    /// run a program on every engine with the same input and report where they disagree,
+    /// exits with 1 if any does
+    Diff(DiffArgs),
+    /// time the programs of a json manifest on every engine and optimization level,
+    /// exits with 1 if any is slower than the baseline
+    Bench(BenchArgs),
+}
+
+#[derive(Debug, Args)]
+struct MachineArgs {
+    /// number of cells
+    #[arg(long, default_value_t = MachineConfig::default().tape_size)]
+    tape_size: usize,
+
+    /// bits per cell, 8, 16 or 32
+    #[arg(long, default_value_t = CellWidth::default())]
+    cell_width: CellWidth,
+
+    /// value `,` stores on end of input, zero, unchanged or max
+    #[arg(long, default_value_t = EofBehavior::default())]
+    eof: EofBehavior,
+}
+
+impl MachineArgs {
+    fn config(&self) -> MachineConfig {
+        MachineConfig {
+            tape_size: self.tape_size,
+            cell_width: self.cell_width,
+            eof: self.eof,
+        }
+    }
+}
+
+/// what `--trace` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Trace {
+    /// executions of each instruction
+    Counts,
+    /// time taken by each instruction
+    Timing,
+}
+
+#[derive(Debug, Args)]
+struct RunArgs {
+    /// source, byte-code, or closure for byte codes compiled into closures, byte-code by
+    /// default
+    #[arg(long)]
+    engine: Option<Engine>,
+
+    /// byte code optimization level, 0 or 1
+    #[arg(short = 'O', long, default_value_t = OptLevel::default())]
+    opt_level: OptLevel,
+
+    #[command(flatten)]
+    machine: MachineArgs,
+
+    /// standard, or extended with `#` dumping the tape to stderr and input embedded after `!`
+    #[arg(long, default_value_t = Dialect::default())]
+    dialect: Dialect,
+
+    /// read input from this file instead of stdin, unless it is embedded in the source
+    #[arg(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// write the byte code source map as json to this file
+    #[arg(long, value_name = "FILE")]
+    source_map: Option<PathBuf>,
+
+    /// fail with a runtime error once more than N commands are executed
+    #[arg(long, value_name = "N")]
+    step_limit: Option<u64>,
+
+    /// print how long parsing and running take to stderr
+    #[arg(long)]
+    time: bool,
+
+    /// print the number of executed commands to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// write every executed instruction, data pointer move, cell write and io to this
+    /// file, for `replay`
+    #[arg(long, value_name = "FILE", conflicts_with = "trace")]
+    record: Option<PathBuf>,
+
+    /// print the source annotated with executions and the coverage summary to stderr
+    #[arg(long, conflicts_with_all = ["record", "trace"])]
+    coverage: bool,
+
+    /// write the coverage as an lcov tracefile to this file
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["record", "trace"])]
+    lcov: Option<PathBuf>,
+
+    /// print executions or timings of each instruction to stderr
+    #[arg(long, value_name = "WHAT", num_args = 0..=1, require_equals = true, default_missing_value = "counts")]
+    trace: Option<Trace>,
+
+    /// print the N hottest loops with their lines and columns to stderr, runs byte codes
+    #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true, default_missing_value = "10")]
+    profile: Option<usize>,
+
+    /// write the profile as collapsed stacks for flamegraphs to this file, runs byte codes
+    #[arg(long, value_name = "FILE")]
+    collapsed_stacks: Option<PathBuf>,
+
+    /// write the profile as Chrome `trace_event` json to this file, runs byte codes
+    #[arg(long, value_name = "FILE")]
+    chrome_trace: Option<PathBuf>,
+
+    /// source file, or a program written by `compile`, which runs with its own machine
+    /// configuration
+    src_file: PathBuf,
+}
 
+#[derive(Debug, Args)]
+struct CompileArgs {
+    /// byte code optimization level, 0 or 1
+    #[arg(short = 'O', long, default_value_t = OptLevel::default())]
+    opt_level: OptLevel,
+
+    #[command(flatten)]
+    machine: MachineArgs,
+
+    /// standard, or extended with `#` dumping the tape to stderr and input embedded after `!`
+    #[arg(long, default_value_t = Dialect::default())]
+    dialect: Dialect,
+
+    /// leave out the source map, runtime errors are then reported without lines and columns
+    #[arg(long)]
+    no_source_map: bool,
+
+    /// the source file with the extension `bfc` by default
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    src_file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct DisasmArgs {
+    /// byte code optimization level of a source file, 0 or 1
+    #[arg(short = 'O', long, default_value_t = OptLevel::default())]
+    opt_level: OptLevel,
+
+    /// standard, or extended with `#` dumping the tape to stderr and input embedded after `!`
+    #[arg(long, default_value_t = Dialect::default())]
+    dialect: Dialect,
+
+    /// source file, byte codes are listed with their lines and columns, or a program
+    /// written by `compile`
+    src_file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct LintArgs {
+    /// suppress warnings with this code, can be repeated
+    #[arg(long, value_name = "CODE")]
+    allow: Vec<LintCode>,
+
+    /// bits per cell, 8, 16 or 32, decides when a run of `+` or `-` wraps around
+    #[arg(long, default_value_t = CellWidth::default())]
+    cell_width: CellWidth,
+
+    /// standard, or extended with `#` and input embedded after `!`
+    #[arg(long, default_value_t = Dialect::default())]
+    dialect: Dialect,
+
+    src_file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct ReplArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+
+    /// number of cells shown on each side of the data pointer
+    #[arg(long, default_value_t = 5)]
+    window: usize,
+}
+
+#[derive(Debug, Args)]
+struct ReplayArgs {
+    /// number of cells shown on each side of the data pointer
+    #[arg(long, default_value_t = 5)]
+    window: usize,
+
+    /// written by `run --record`
+    trace: PathBuf,
+
+    /// the program that was recorded
+    src_file: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct TestArgs {
+    /// step limit of tests without their own
+    #[arg(long, value_name = "N")]
+    step_limit: Option<u64>,
+
+    /// only run tests whose names contain this
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// paths in it are relative to it, e.g. tests/artifacts/oracles.json
+    manifest: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct BenchArgs {
+    /// runs of each program on each variant, the median is compared
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    samples: usize,
+
+    /// only time programs whose names contain this
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// write the results as json
+    #[arg(long, value_name = "FILE")]
+    save: Option<PathBuf>,
+
+    /// results saved before with `--save`
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
+
+    /// percent slower than the baseline that counts as a regression
+    #[arg(long, value_name = "PCT", default_value_t = 10.0)]
+    threshold: f64,
+
+    /// paths in it are relative to it, e.g. benches/benchmarks.json
+    manifest: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct DiffArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+
+    /// standard, or extended with input embedded after `!`
+    #[arg(long, default_value_t = Dialect::default())]
+    dialect: Dialect,
+
+    /// input of the program, nothing is read without one
+    #[arg(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// stop every engine once more than N commands are executed
+    #[arg(long, value_name = "N")]
+    step_limit: Option<u64>,
+
+    src_file: PathBuf,
+}
+
+fn source_error(e: UcSourceFileError<'_>) -> ExitCode {
+    eprintln!("{}", e);
+    match e {
+        UcSourceFileError::FileFailToRead { .. } => ExitCode::from(exit_code::IO_ERROR),
+        UcSourceFileError::UnmatchedParen { .. } => ExitCode::from(exit_code::SOURCE_ERROR),
+    }
+}
+
+fn load(path: &PathBuf, dialect: Dialect) -> Result<UcSourceFile, ExitCode> {
+    UcSourceFile::new_with_dialect(path, dialect).map_err(source_error)
+}
+
+fn run_lint(args: &LintArgs) -> ExitCode {
+    let src_file = match load(&args.src_file, args.dialect) {
+        Ok(src_file) => src_file,
+        Err(code) => return code,
+    };
+    let warnings = match lint(&src_file, args.cell_width, &args.allow) {
+        Ok(warnings) => warnings,
+        Err(e) => return source_error(e),
+    };
+    for w in &warnings {
+        println!("{}:{}", src_file.filename().display(), w);
+    }
+    if warnings.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(exit_code::LINT_WARNINGS)
+    }
+}
+
+impl RunArgs {
+    fn profiling(&self) -> bool {
+        self.profile.is_some() || self.collapsed_stacks.is_some() || self.chrome_trace.is_some()
+    }
+
+    /// the engine that actually runs, profiling runs byte codes
+    fn effective_engine(&self) -> Engine {
+        if self.profiling() {
+            Engine::ByteCode
+        } else {
+            self.engine.unwrap_or_default()
+        }
+    }
+}
+
+/// runs byte codes with a profiler, reports and exports the profile even if it fails
+fn profile<'src_file, IO: MachineIO, O: Observer>(
+    args: &RunArgs,
+    machine: &mut Machine<IO, O>,
+    src_file: &'src_file UcSourceFile,
+) -> Result<Result<(), MachineError<'src_file>>, ExitCode> {
+    let byte_codes = match src_file.to_byte_codes_with(args.opt_level) {
+        Ok(byte_codes) => byte_codes,
+        Err(e) => return Ok(Err(e.into())),
+    };
+    let mut profiler = Profiler::new();
+    let result = profiler.run(machine, &byte_codes);
+    let profile = profiler.profile(src_file, &byte_codes);
+
+    if let Some(n) = args.profile {
+        // program output may not end with a line break
+        eprintln!("\n{}", profile.hot_loop_report(n));
+    }
+    let exports = [
+        (
+            &args.collapsed_stacks,
+            Profile::to_collapsed_stacks as fn(&Profile) -> String,
+        ),
+        (&args.chrome_trace, Profile::to_chrome_trace),
+    ];
+    for (path, export) in exports {
+        if let Some(path) = path {
+            if let Err(e) = std::fs::write(path, export(&profile)) {
+                eprintln!("failed to write {}, {}", path.display(), e);
+                return Err(ExitCode::from(exit_code::IO_ERROR));
+            }
+        }
+    }
+    Ok(result.map_err(MachineError::from))
+}
+
+fn run<IO: MachineIO>(
+    args: &RunArgs,
+    src_file: &UcSourceFile,
+    loaded: std::time::Duration,
+    io: IO,
+) -> ExitCode {
+    let config = args.machine.config();
+    if let Some(path) = &args.record {
+        let file = match std::fs::File::create(path) {
+            Ok(file) => std::io::BufWriter::new(file),
+            Err(e) => {
+                eprintln!("failed to write {}, {}", path.display(), e);
+                return ExitCode::from(exit_code::IO_ERROR);
+            }
+        };
+        let header = TraceHeader {
+            config,
+            engine: args.effective_engine(),
+            opt_level: args.opt_level,
+            dialect: src_file.dialect(),
+            source_crc: TraceHeader::source_crc(src_file),
+        };
+        let machine = Machine::with_observer(config, io, TraceRecorder::new(file, &header));
+        return run_observed(args, src_file, loaded, machine, |recorder| {
+            recorder.finish().map(|_| ()).map_err(|e| {
+                eprintln!("failed to write {}, {}", path.display(), e);
+                ExitCode::from(exit_code::IO_ERROR)
+            })
+        });
+    }
+    if args.coverage || args.lcov.is_some() {
+        let machine = Machine::with_observer(config, io, CoverageObserver::new());
+        return run_observed(args, src_file, loaded, machine, |observer| {
+            let coverage = observer
+                .coverage(src_file, args.effective_engine(), args.opt_level)
+                .map_err(source_error)?;
+            if args.coverage {
+                let colored = console::colors_enabled_stderr();
+                eprint!("\n{}", coverage.annotate(src_file, colored));
+                eprintln!("{}", coverage.summary());
+            }
+            if let Some(path) = &args.lcov {
+                if let Err(e) = std::fs::write(path, coverage.to_lcov(src_file)) {
+                    eprintln!("failed to write {}, {}", path.display(), e);
+                    return Err(ExitCode::from(exit_code::IO_ERROR));
+                }
+            }
+            Ok(())
+        });
+    }
+    // program output may not end with a line break
+    match args.trace {
+        None => run_observed(
+            args,
+            src_file,
+            loaded,
+            Machine::with_config(config, io),
+            |_| Ok(()),
+        ),
+        Some(Trace::Counts) => {
+            let machine = Machine::with_observer(config, io, CountingObserver::new());
+            run_observed(args, src_file, loaded, machine, |o| {
+                eprintln!("\n{}", o.report());
+                Ok(())
+            })
+        }
+        Some(Trace::Timing) => {
+            let machine = Machine::with_observer(config, io, TimingObserver::new());
+            run_observed(args, src_file, loaded, machine, |o| {
+                eprintln!("\n{}", o.report());
+                Ok(())
+            })
+        }
+    }
+}
+
+fn run_observed<IO: MachineIO, O: Observer>(
+    args: &RunArgs,
+    src_file: &UcSourceFile,
+    loaded: std::time::Duration,
+    mut machine: Machine<IO, O>,
+    finish: impl FnOnce(O) -> Result<(), ExitCode>,
+) -> ExitCode {
+    if src_file.dialect() == Dialect::Extended {
+        machine.set_debug_sink(Box::new(StderrDebugSink));
+    }
+    machine.set_step_limit(args.step_limit);
     let start = std::time::Instant::now();
-    let src_file = UcSourceFile::new(src_file).unwrap();
-    machine.eval_source_file(&src_file);
-    eprintln!("eval source file: {}", start.elapsed().as_secs());
+    let result = if args.profiling() {
+        match profile(args, &mut machine, src_file) {
+            Ok(result) => result,
+            Err(code) => return code,
+        }
+    } else {
+        machine.eval(src_file, args.effective_engine(), args.opt_level)
+    };
+    let elapsed = start.elapsed();
 
+    let executed = machine.executed();
+    if let Err(code) = finish(machine.into_observer()) {
+        return code;
+    }
+    if args.time {
+        eprintln!("load: {:.3}ms", loaded.as_secs_f64() * 1000.0);
+        eprintln!(
+            "eval ({}): {:.3}ms",
+            args.effective_engine(),
+            elapsed.as_secs_f64() * 1000.0
+        );
+    }
+    if args.stats {
+        eprintln!("executed commands: {}", executed);
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(MachineError::Source(e)) => source_error(e),
+        Err(MachineError::Runtime(e)) => {
+            let pos = args
+                .effective_engine()
+                .locations(src_file, args.opt_level)
+                .ok()
+                .and_then(|locations| locations.get(e.instr_ptr()).copied());
+            match pos {
+                Some(pos) => eprintln!(
+                    "{}:{}:{}: {}",
+                    src_file.filename().display(),
+                    pos.line,
+                    pos.col,
+                    e
+                ),
+                None => eprintln!("{}", e),
+            }
+            ExitCode::from(exit_code::RUNTIME_ERROR)
+        }
+    }
+}
+
+fn run_with_input(args: &RunArgs) -> ExitCode {
+    if let Some(engine) = args
+        .engine
+        .filter(|&e| args.profiling() && e != Engine::ByteCode)
+    {
+        eprintln!(
+            "--profile, --collapsed-stacks and --chrome-trace run byte codes, not --engine {}",
+            engine
+        );
+        return ExitCode::from(exit_code::BAD_COMMAND_LINE);
+    }
+    if is_object_file(&args.src_file) {
+        return run_object(args);
+    }
     let start = std::time::Instant::now();
-    let byte_codes = src_file.to_byte_codes().unwrap();
-    machine.eval_byte_codes(&byte_codes);
-    eprintln!("eval byte codes: {}", start.elapsed().as_secs());
+    let src_file = match load(&args.src_file, args.dialect) {
+        Ok(src_file) => src_file,
+        Err(code) => return code,
+    };
+    if let Some(path) = &args.source_map {
+        let byte_codes = match src_file.to_byte_codes_with(args.opt_level) {
+            Ok(byte_codes) => byte_codes,
+            Err(e) => return source_error(e),
+        };
+        let json = SourceMap::from_byte_codes(&src_file, &byte_codes).to_json();
+        if let Err(e) = std::fs::write(path, json) {
+            eprintln!("failed to write {}, {}", path.display(), e);
+            return ExitCode::from(exit_code::IO_ERROR);
+        }
+    }
+    let loaded = start.elapsed();
+
+    if let Some(input) = src_file.embedded_input() {
+        let io = EmbeddedInputIO::new(input, DefaultMachineIO::new());
+        return run(args, &src_file, loaded, io);
+    }
+    match &args.input {
+        None => run(args, &src_file, loaded, DefaultMachineIO::new()),
+        Some(path) => match std::fs::File::open(path) {
+            Ok(file) => run(
+                args,
+                &src_file,
+                loaded,
+                DefaultMachineIO::with_input_file(file),
+            ),
+            Err(e) => {
+                eprintln!("failed to read {}, {}", path.display(), e);
+                ExitCode::from(exit_code::IO_ERROR)
+            }
+        },
+    }
+}
+
+fn is_object_file(path: &PathBuf) -> bool {
+    use std::io::Read;
+
+    let mut magic = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|()| ObjectFile::is_object(&magic))
+}
+
+fn run_compile(args: &CompileArgs) -> ExitCode {
+    let src_file = match load(&args.src_file, args.dialect) {
+        Ok(src_file) => src_file,
+        Err(code) => return code,
+    };
+    let object = match ObjectFile::compile(
+        &src_file,
+        args.machine.config(),
+        args.opt_level,
+        !args.no_source_map,
+    ) {
+        Ok(object) => object,
+        Err(e) => return source_error(e),
+    };
+    let path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| args.src_file.with_extension("bfc"));
+    if let Err(e) = std::fs::write(&path, object.to_bytes()) {
+        eprintln!("failed to write {}, {}", path.display(), e);
+        return ExitCode::from(exit_code::IO_ERROR);
+    }
+    ExitCode::SUCCESS
+}
+
+fn run_disasm(args: &DisasmArgs) -> ExitCode {
+    if is_object_file(&args.src_file) {
+        let object = match load_object(&args.src_file) {
+            Ok(object) => object,
+            Err(code) => return code,
+        };
+        let byte_codes = (0..object.byte_codes.len())
+            .filter_map(|idx| object.byte_codes.get(idx))
+            .collect::<Vec<_>>();
+        print!("{}", disassemble(&byte_codes, None));
+        return ExitCode::SUCCESS;
+    }
+    let src_file = match load(&args.src_file, args.dialect) {
+        Ok(src_file) => src_file,
+        Err(code) => return code,
+    };
+    match src_file.to_byte_codes_with(args.opt_level) {
+        Ok(byte_codes) => {
+            print!("{}", disassemble(&byte_codes, Some(&src_file)));
+            ExitCode::SUCCESS
+        }
+        Err(e) => source_error(e),
+    }
+}
+
+fn load_object(path: &PathBuf) -> Result<ObjectFile, ExitCode> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        eprintln!("failed to read {}, {}", path.display(), e);
+        ExitCode::from(exit_code::IO_ERROR)
+    })?;
+    ObjectFile::from_bytes(&bytes).map_err(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        ExitCode::from(exit_code::SOURCE_ERROR)
+    })
+}
+
+/// runs a program written by `compile`, options that need the source are refused
+fn run_object(args: &RunArgs) -> ExitCode {
+    let needs_source = [
+        (args.engine == Some(Engine::Source), "--engine source"),
+        (args.source_map.is_some(), "--source-map"),
+        (args.record.is_some(), "--record"),
+        (args.coverage, "--coverage"),
+        (args.lcov.is_some(), "--lcov"),
+        (args.trace.is_some(), "--trace"),
+        (args.profiling(), "--profile"),
+    ];
+    if let Some((_, option)) = needs_source.iter().find(|(used, _)| *used) {
+        eprintln!(
+            "{} needs a source file rather than a compiled program",
+            option
+        );
+        return ExitCode::from(exit_code::BAD_COMMAND_LINE);
+    }
+
+    let start = std::time::Instant::now();
+    let object = match load_object(&args.src_file) {
+        Ok(object) => object,
+        Err(code) => return code,
+    };
+    let loaded = start.elapsed();
+
+    if let Some(input) = &object.input {
+        let io = EmbeddedInputIO::new(input, DefaultMachineIO::new());
+        return run_object_with(args, &object, loaded, io);
+    }
+    match &args.input {
+        None => run_object_with(args, &object, loaded, DefaultMachineIO::new()),
+        Some(path) => match std::fs::File::open(path) {
+            Ok(file) => run_object_with(
+                args,
+                &object,
+                loaded,
+                DefaultMachineIO::with_input_file(file),
+            ),
+            Err(e) => {
+                eprintln!("failed to read {}, {}", path.display(), e);
+                ExitCode::from(exit_code::IO_ERROR)
+            }
+        },
+    }
+}
+
+fn run_object_with<IO: MachineIO>(
+    args: &RunArgs,
+    object: &ObjectFile,
+    loaded: std::time::Duration,
+    io: IO,
+) -> ExitCode {
+    let mut machine = Machine::with_config(object.config, io);
+    machine.set_debug_sink(Box::new(StderrDebugSink));
+    machine.set_step_limit(args.step_limit);
+    let start = std::time::Instant::now();
+    let result = match args.effective_engine() {
+        Engine::Closure => machine.eval_dense_closures(&object.byte_codes),
+        _ => machine.eval_dense_byte_codes(&object.byte_codes),
+    };
+    let elapsed = start.elapsed();
+
+    if args.time {
+        eprintln!("load: {:.3}ms", loaded.as_secs_f64() * 1000.0);
+        eprintln!(
+            "eval ({}): {:.3}ms",
+            args.effective_engine(),
+            elapsed.as_secs_f64() * 1000.0
+        );
+    }
+    if args.stats {
+        eprintln!("executed commands: {}", machine.executed());
+    }
+
+    let Err(e) = result else {
+        return ExitCode::SUCCESS;
+    };
+    let instr_ptr = e.instr_ptr();
+    match object.source_map.as_ref().and_then(|source_map| {
+        let m = source_map.lookup(instr_ptr)?;
+        Some((&source_map.source, m.start))
+    }) {
+        Some((source, pos)) => eprintln!("{}:{}:{}: {}", source, pos.line, pos.col, e),
+        None => eprintln!("{}", e),
+    }
+    ExitCode::from(exit_code::RUNTIME_ERROR)
+}
+
+fn run_repl(args: &ReplArgs) -> ExitCode {
+    use std::io::Write;
+
+    let machine = Machine::with_config(args.machine.config(), DefaultMachineIO::new());
+    let mut repl = Repl::new(machine);
+    loop {
+        print!("{}", if repl.is_pending() { "... " } else { "bf> " });
+        let _ = std::io::stdout().flush();
+        // stdin is only locked while reading a line, `,` reads from it as well
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => return ExitCode::SUCCESS,
+            Ok(_) => (),
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::from(exit_code::IO_ERROR);
+            }
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.trim() == ":reset" {
+            repl.reset();
+        } else {
+            match repl.feed_line(line) {
+                Ok(Feed::Pending) => continue,
+                Ok(Feed::Executed) => println!(),
+                Err(e) => eprintln!("error: {}", e),
+            }
+        }
+        println!("{}", repl.tape_window(args.window));
+    }
+}
+
+fn run_tests(args: &TestArgs) -> ExitCode {
+    let manifest = match Manifest::load(&args.manifest) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(exit_code::IO_ERROR);
+        }
+    };
+    let tests = manifest
+        .tests()
+        .iter()
+        .filter(|t| args.filter.as_ref().is_none_or(|f| t.name().contains(f)))
+        .collect::<Vec<_>>();
+
+    println!("running {} tests", tests.len());
+    let mut failed = Vec::new();
+    for t in tests.iter() {
+        let report = manifest.run(t, args.step_limit);
+        println!(
+            "test {} ... {}",
+            report.name,
+            if report.passed() { "ok" } else { "FAILED" }
+        );
+        if !report.passed() {
+            failed.push(report);
+        }
+    }
+
+    if !failed.is_empty() {
+        println!("\nfailures:\n");
+        for report in &failed {
+            print!("{}", report);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed; {} filtered out",
+        if failed.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failed.len(),
+        failed.len(),
+        manifest.tests().len() - tests.len()
+    );
+    if failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(exit_code::TEST_FAILURES)
+    }
+}
+
+fn run_diff(args: &DiffArgs) -> ExitCode {
+    let src_file = match load(&args.src_file, args.dialect) {
+        Ok(src_file) => src_file,
+        Err(code) => return code,
+    };
+    // read byte by byte, the same as `run`
+    let input = match &args.input {
+        None => String::new(),
+        Some(path) => match std::fs::read(path) {
+            Ok(bytes) => bytes.into_iter().map(char::from).collect(),
+            Err(e) => {
+                eprintln!("failed to read {}, {}", path.display(), e);
+                return ExitCode::from(exit_code::IO_ERROR);
+            }
+        },
+    };
+    let comparison = match compare(&src_file, args.machine.config(), &input, args.step_limit) {
+        Ok(comparison) => comparison,
+        Err(e) => return source_error(e),
+    };
+
+    let reference = comparison.reference();
+    println!(
+        "{}: {} commands, {} chars of output, {}",
+        reference.variant,
+        reference.steps,
+        reference.output.chars().count(),
+        reference
+            .error
+            .map_or(String::from("success"), |e| e.to_string())
+    );
+    if comparison.is_inconclusive() {
+        println!("every engine stops at the step limit, nothing to compare");
+        return ExitCode::SUCCESS;
+    }
+    let divergences = comparison.divergences();
+    for outcome in &comparison.outcomes[1..] {
+        match divergences.iter().find(|(v, _)| *v == outcome.variant) {
+            None => println!("{}: agrees", outcome.variant),
+            Some((_, d)) => println!("{}: {}", outcome.variant, d),
+        }
+    }
+    if divergences.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(exit_code::DIVERGENCES)
+    }
+}
+
+fn run_bench(args: &BenchArgs) -> ExitCode {
+    let manifest = match Manifest::load(&args.manifest) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(exit_code::IO_ERROR);
+        }
+    };
+    let baseline = match &args.baseline {
+        None => None,
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| BenchResults::from_json(&json).map_err(|e| e.to_string()))
+        {
+            Ok(baseline) => Some(baseline),
+            Err(e) => {
+                eprintln!("failed to read {}, {}", path.display(), e);
+                return ExitCode::from(exit_code::IO_ERROR);
+            }
+        },
+    };
+
+    let mut results = BenchResults::default();
+    for t in manifest.tests() {
+        if args.filter.as_ref().is_some_and(|f| !t.name().contains(f)) {
+            continue;
+        }
+        for variant in Variant::all() {
+            eprintln!("timing {} on {}", t.name(), variant);
+            match measure(&manifest, t, variant, args.samples.max(1)) {
+                Ok(m) => results.measurements.push(m),
+                Err(e) => {
+                    eprintln!("{} fails on {}, {}", t.name(), variant, e);
+                    return ExitCode::from(exit_code::RUNTIME_ERROR);
+                }
+            }
+        }
+    }
+    println!("{}", results.report(baseline.as_ref()));
+
+    if let Some(path) = &args.save {
+        if let Err(e) = std::fs::write(path, results.to_json() + "\n") {
+            eprintln!("failed to write {}, {}", path.display(), e);
+            return ExitCode::from(exit_code::IO_ERROR);
+        }
+    }
+    let Some(baseline) = baseline else {
+        return ExitCode::SUCCESS;
+    };
+    let regressions = results
+        .changes_from(&baseline)
+        .into_iter()
+        .filter(|c| c.is_regression(args.threshold / 100.0))
+        .collect::<Vec<_>>();
+    if regressions.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+    println!("\nregressions:");
+    for c in &regressions {
+        println!(
+            "{} on {}: {:.3}ms -> {:.3}ms",
+            c.name,
+            c.variant,
+            c.baseline.as_secs_f64() * 1000.0,
+            c.current.as_secs_f64() * 1000.0
+        );
+    }
+    ExitCode::from(exit_code::REGRESSIONS)
+}
+
+const REPLAY_HELP: &str = "\
+s, step [N]   run N instructions forwards, 1 by default
+b, back [N]   undo N instructions, 1 by default
+g, goto N     move to just after the Nth instruction
+start, end    move to the start or the end of the trace
+o, output     print the output written so far
+input         print all input the program read
+q, quit       exit";
+
+fn run_replay(args: &ReplayArgs) -> ExitCode {
+    use std::io::{BufRead, Write};
+
+    let replay = std::fs::read(&args.trace)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| Replay::from_bytes(&bytes).map_err(|e| e.to_string()));
+    let mut replay = match replay {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("failed to read {}, {}", args.trace.display(), e);
+            return ExitCode::from(exit_code::IO_ERROR);
+        }
+    };
+    let src_file = match load(&args.src_file, replay.header().dialect) {
+        Ok(src_file) => src_file,
+        Err(code) => return code,
+    };
+    if let Err(e) = replay.header().check_source(&src_file) {
+        eprintln!("{}: {}", args.src_file.display(), e);
+        return ExitCode::from(exit_code::SOURCE_ERROR);
+    }
+    let locations = match replay.locations(&src_file) {
+        Ok(locations) => locations,
+        Err(e) => return source_error(e),
+    };
+    let steps = replay.steps();
+    if let Some(step) = steps.iter().find(|s| s.instr_ptr >= locations.len()) {
+        eprintln!(
+            "{}: instruction {} of the trace is not in the program",
+            args.src_file.display(),
+            step.instr_ptr
+        );
+        return ExitCode::from(exit_code::SOURCE_ERROR);
+    }
+
+    let mut lines = std::io::stdin().lock().lines();
+    let mut show = true;
+    loop {
+        if show {
+            let location = match replay.last_step() {
+                Some(step) => format!("after {}", locations[step.instr_ptr]),
+                None => String::from("start"),
+            };
+            println!(
+                "step {}/{}, {}\n{}",
+                replay.pos(),
+                replay.steps().len(),
+                location,
+                replay.tape_window(args.window)
+            );
+        }
+        print!("(replay) ");
+        let _ = std::io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                return ExitCode::from(exit_code::IO_ERROR);
+            }
+            None => return ExitCode::SUCCESS,
+        };
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let n = match words.get(1).map(|n| n.parse::<usize>()) {
+            None => None,
+            Some(Ok(n)) => Some(n),
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                show = false;
+                continue;
+            }
+        };
+        show = true;
+        match (words.first().copied(), n) {
+            (Some("s" | "step"), n) => replay.seek(replay.pos().saturating_add(n.unwrap_or(1))),
+            (Some("b" | "back"), n) => replay.seek(replay.pos().saturating_sub(n.unwrap_or(1))),
+            (Some("g" | "goto"), Some(n)) => replay.seek(n),
+            (Some("start"), None) => replay.seek(0),
+            (Some("end"), None) => replay.seek(usize::MAX),
+            (Some("o" | "output"), None) => {
+                println!("{}", replay.output());
+                show = false;
+            }
+            (Some("input"), None) => {
+                println!("{:?}", replay.input());
+                show = false;
+            }
+            (Some("q" | "quit"), None) => return ExitCode::SUCCESS,
+            _ => {
+                println!("{}", REPLAY_HELP);
+                show = false;
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match Cli::parse_with_default_subcommand().command {
+        Command::Run(args) => run_with_input(&args),
+        Command::Compile(args) => run_compile(&args),
+        Command::Disasm(args) => run_disasm(&args),
+        Command::Lint(args) => run_lint(&args),
+        Command::Repl(args) => run_repl(&args),
+        Command::Replay(args) => run_replay(&args),
+        Command::Test(args) => run_tests(&args),
+        Command::Diff(args) => run_diff(&args),
+        Command::Bench(args) => run_bench(&args),
+    }
 }
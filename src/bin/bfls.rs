@@ -0,0 +1,17 @@
+use brainfuck::lsp::LspServer;
+use std::process::ExitCode;
+
+/// language server on stdio, launched by editors rather than by hand
+fn main() -> ExitCode {
+    let stdout = std::io::stdout();
+    let mut server = LspServer::new(stdout.lock());
+    match server.serve(&mut std::io::stdin().lock()) {
+        Ok(true) => ExitCode::SUCCESS,
+        // exiting without a `shutdown` request
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(3)
+        }
+    }
+}
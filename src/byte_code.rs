@@ -1,15 +1,121 @@
-use crate::source_file::{RawContentIndex, UcContentIndex};
+use crate::{
+    source_file::{RawContentIndex, UcContentIndex},
+    utility::{populate_loop_boundaries, ExtraParen},
+};
 
+/// what a byte code does, all but the jumps repeat their command `ByteCode::arg` times
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
-pub(crate) enum ByteCodeKind {
+#[repr(u8)]
+#[non_exhaustive]
+pub enum ByteCodeKind {
     IncPtr,
     DecPtr,
     IncData,
     DecData,
     Read,
     Write,
+    /// `[`, jumps past its matching `]` if the cell is zero
     LoopStartJumpIfDataZero,
+    /// `]`, jumps back to its matching `[` if the cell is not zero
     LoopEndJumpIfDataNotZero,
+    /// `#` of `Dialect::Extended`
+    DebugDump,
+}
+
+impl ByteCodeKind {
+    pub fn is_jump(self) -> bool {
+        matches!(
+            self,
+            Self::LoopStartJumpIfDataZero | Self::LoopEndJumpIfDataNotZero
+        )
+    }
+}
+
+/// why a byte code or a program of them cannot run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum ByteCodeError {
+    /// a command repeated zero times
+    ZeroCount,
+    /// more than `u32::MAX`, machines cannot run it
+    ArgTooLarge(usize),
+    /// the source range ends before it starts
+    ReversedRange,
+    /// a `[` without its `]`
+    UnmatchedLoopStart,
+    /// a `]` without its `[`
+    UnmatchedLoopEnd,
+    /// a `[` or `]` that does not jump to its matching one
+    BadJump,
+}
+
+impl std::fmt::Display for ByteCodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroCount => f.write_str("command is repeated zero times"),
+            Self::ArgTooLarge(arg) => write!(f, "argument {} is larger than {}", arg, u32::MAX),
+            Self::ReversedRange => f.write_str("source range ends before it starts"),
+            Self::UnmatchedLoopStart => f.write_str("`[` has no matching `]`"),
+            Self::UnmatchedLoopEnd => f.write_str("`]` has no matching `[`"),
+            Self::BadJump => f.write_str("jump does not go to its matching loop boundary"),
+        }
+    }
+}
+
+impl std::error::Error for ByteCodeError {}
+
+/// a `ByteCodeError` of the byte code at `idx` of a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct ProgramError {
+    pub idx: usize,
+    pub error: ByteCodeError,
+}
+
+impl std::fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "byte code {}: {}", self.idx, self.error)
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+/// how hard `UcSourceFile::to_byte_codes_with` tries
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum OptLevel {
+    /// one byte code for each command
+    O0,
+    /// consecutive identical commands are merged into one byte code, e.g. `+++` to `3+`
+    #[default]
+    O1,
+}
+
+impl OptLevel {
+    pub fn all() -> &'static [OptLevel] {
+        &[Self::O0, Self::O1]
+    }
+}
+
+impl std::fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::O0 => f.write_str("0"),
+            Self::O1 => f.write_str("1"),
+        }
+    }
+}
+
+impl std::str::FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::O0),
+            "1" => Ok(Self::O1),
+            _ => Err(format!(
+                "unknown optimization level `{}`, expecting 0 or 1",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
@@ -28,6 +134,43 @@ fn make_mock_byte_code() -> ByteCode {
 }
 
 impl ByteCode {
+    /// `arg` is the number of times the command repeats, or the index a jump goes to,
+    /// which only `validate` can check
+    pub fn new(
+        kind: ByteCodeKind,
+        arg: usize,
+        range_in_raw: (RawContentIndex, RawContentIndex),
+    ) -> Result<Self, ByteCodeError> {
+        if arg == 0 && !kind.is_jump() {
+            return Err(ByteCodeError::ZeroCount);
+        }
+        if u32::try_from(arg).is_err() {
+            return Err(ByteCodeError::ArgTooLarge(arg));
+        }
+        if range_in_raw.1 < range_in_raw.0 {
+            return Err(ByteCodeError::ReversedRange);
+        }
+        Ok(Self {
+            kind,
+            arg,
+            range_in_raw,
+        })
+    }
+
+    pub fn kind(&self) -> ByteCodeKind {
+        self.kind
+    }
+
+    /// the number of times the command repeats, or the index a jump goes to
+    pub fn arg(&self) -> usize {
+        self.arg
+    }
+
+    /// the commands it comes from, see `UcSourceFile::pos_of`
+    pub fn range_in_raw(&self) -> (RawContentIndex, RawContentIndex) {
+        self.range_in_raw
+    }
+
     pub(crate) fn make_non_jump_code(
         kind: ByteCodeKind,
         idx_in_raw: RawContentIndex,
@@ -51,6 +194,208 @@ impl ByteCode {
     pub(crate) fn correct_jump(&mut self, jump_to: UcContentIndex) {
         self.arg = jump_to.get();
     }
+
+    /// panics if `arg` does not fit in `u32`, i.e. more than 4 billion byte codes or a
+    /// run of the same command that long
+    pub fn to_dense(&self) -> DenseByteCode {
+        DenseByteCode {
+            kind: self.kind,
+            arg: u32::try_from(self.arg).expect("byte code argument overflows u32"),
+        }
+    }
+}
+
+/// checks what machines trust, every byte code is one `ByteCode::new` accepts and every
+/// `[` jumps to its matching `]` and back
+pub fn validate(byte_codes: &[ByteCode]) -> Result<(), ProgramError> {
+    for (idx, bc) in byte_codes.iter().enumerate() {
+        ByteCode::new(bc.kind, bc.arg, bc.range_in_raw)
+            .map_err(|error| ProgramError { idx, error })?;
+    }
+    check_jumps(byte_codes)
+}
+
+/// every `[` jumps to its matching `]` and back
+pub(crate) fn check_jumps(byte_codes: &[ByteCode]) -> Result<(), ProgramError> {
+    check_jump_targets(byte_codes.iter().map(|bc| (bc.kind, bc.arg)))
+}
+
+fn check_jump_targets<I>(kinds_and_args: I) -> Result<(), ProgramError>
+where
+    I: Iterator<Item = (ByteCodeKind, usize)> + Clone,
+{
+    let kinds = kinds_and_args.clone().map(|(kind, _)| kind);
+    let loop_matches = populate_loop_boundaries(kinds).map_err(|extra_parens| {
+        let error = match extra_parens[0] {
+            ExtraParen::Open { .. } => ByteCodeError::UnmatchedLoopStart,
+            ExtraParen::Close { .. } => ByteCodeError::UnmatchedLoopEnd,
+        };
+        ProgramError {
+            idx: extra_parens[0].idx(),
+            error,
+        }
+    })?;
+    for (idx, (kind, arg)) in kinds_and_args.enumerate() {
+        let target = match kind {
+            ByteCodeKind::LoopStartJumpIfDataZero => loop_matches.get_matching_end(idx),
+            ByteCodeKind::LoopEndJumpIfDataNotZero => loop_matches.get_matching_start(idx),
+            _ => continue,
+        };
+        if arg != target {
+            return Err(ProgramError {
+                idx,
+                error: ByteCodeError::BadJump,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// builds a program one command at a time, jumps are filled in when their loops close
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct ProgramBuilder {
+    byte_codes: Vec<ByteCode>,
+    /// indices of `[` not closed yet
+    open_loops: Vec<usize>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.byte_codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.byte_codes.is_empty()
+    }
+
+    /// appends `count` of a command that comes from nowhere in a source file
+    pub fn push(&mut self, kind: ByteCodeKind, count: usize) -> Result<&mut Self, ProgramError> {
+        let empty = RawContentIndex::new(0);
+        self.push_at(kind, count, (empty, empty))
+    }
+
+    /// appends `count` of a command, a jump becomes `count` byte codes of its own
+    pub fn push_at(
+        &mut self,
+        kind: ByteCodeKind,
+        count: usize,
+        range_in_raw: (RawContentIndex, RawContentIndex),
+    ) -> Result<&mut Self, ProgramError> {
+        let idx = self.byte_codes.len();
+        let err = |error| ProgramError { idx, error };
+        if !kind.is_jump() {
+            let bc = ByteCode::new(kind, count, range_in_raw).map_err(err)?;
+            self.byte_codes.push(bc);
+            return Ok(self);
+        }
+
+        if count == 0 {
+            return Err(err(ByteCodeError::ZeroCount));
+        }
+        if kind == ByteCodeKind::LoopEndJumpIfDataNotZero && self.open_loops.len() < count {
+            let idx = idx + self.open_loops.len();
+            return Err(ProgramError {
+                idx,
+                error: ByteCodeError::UnmatchedLoopEnd,
+            });
+        }
+        for _ in 0..count {
+            let idx = self.byte_codes.len();
+            // filled in when the loop closes
+            let mut bc = ByteCode::new(kind, 0, range_in_raw).map_err(err)?;
+            if kind == ByteCodeKind::LoopStartJumpIfDataZero {
+                self.open_loops.push(idx);
+            } else {
+                let start = self.open_loops.pop().unwrap();
+                bc.arg = start;
+                self.byte_codes[start].arg = idx;
+            }
+            self.byte_codes.push(bc);
+        }
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<Vec<ByteCode>, ProgramError> {
+        match self.open_loops.last() {
+            Some(&idx) => Err(ProgramError {
+                idx,
+                error: ByteCodeError::UnmatchedLoopStart,
+            }),
+            None => Ok(self.byte_codes),
+        }
+    }
+}
+
+/// what `Machine` executes, a `ByteCode` without its source range, in 8 bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct DenseByteCode {
+    pub(crate) kind: ByteCodeKind,
+    pub(crate) arg: u32,
+}
+
+impl DenseByteCode {
+    pub fn kind(&self) -> ByteCodeKind {
+        self.kind
+    }
+
+    /// the number of times the command repeats, or the index a jump goes to
+    pub fn arg(&self) -> u32 {
+        self.arg
+    }
+}
+
+/// byte codes split into what is executed and where each one comes from
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct DenseByteCodes {
+    codes: Vec<DenseByteCode>,
+    /// source range of each of `codes`
+    ranges_in_raw: Vec<(RawContentIndex, RawContentIndex)>,
+}
+
+impl DenseByteCodes {
+    pub fn codes(&self) -> &[DenseByteCode] {
+        &self.codes
+    }
+
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    /// the same checks as `validate`
+    pub fn validate(&self) -> Result<(), ProgramError> {
+        for (idx, (code, range_in_raw)) in self.codes.iter().zip(&self.ranges_in_raw).enumerate() {
+            ByteCode::new(code.kind, code.arg as usize, *range_in_raw)
+                .map_err(|error| ProgramError { idx, error })?;
+        }
+        check_jump_targets(self.codes.iter().map(|code| (code.kind, code.arg as usize)))
+    }
+
+    /// the byte code at `idx` with its source range back
+    pub fn get(&self, idx: usize) -> Option<ByteCode> {
+        let code = self.codes.get(idx)?;
+        Some(ByteCode {
+            kind: code.kind,
+            arg: code.arg as usize,
+            range_in_raw: self.ranges_in_raw[idx],
+        })
+    }
+}
+
+impl From<&[ByteCode]> for DenseByteCodes {
+    fn from(byte_codes: &[ByteCode]) -> Self {
+        Self {
+            codes: byte_codes.iter().map(ByteCode::to_dense).collect(),
+            ranges_in_raw: byte_codes.iter().map(|bc| bc.range_in_raw).collect(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -62,7 +407,181 @@ mod test {
         use crate::utility::traits::*;
 
         is_small_value_enum(&ByteCodeKind::DecData);
+        is_small_value_enum(&OptLevel::O0);
+        is_default_debug(&OptLevel::default());
 
         is_big_value_struct_but_no_default(&make_mock_byte_code());
+        is_small_value_struct_but_no_default(&make_mock_byte_code().to_dense());
+        is_default_debug(&DenseByteCodes::default());
+
+        is_big_error(&ByteCodeError::ZeroCount);
+        is_big_error(&ProgramError {
+            idx: 0,
+            error: ByteCodeError::BadJump,
+        });
+        is_big_value_struct(&ProgramBuilder::new());
+    }
+
+    fn range(start: usize, end: usize) -> (RawContentIndex, RawContentIndex) {
+        (RawContentIndex::new(start), RawContentIndex::new(end))
+    }
+
+    #[test]
+    fn byte_codes_are_validated() {
+        let bc = ByteCode::new(ByteCodeKind::IncData, 3, range(1, 4)).unwrap();
+        assert_eq!(
+            (bc.kind(), bc.arg(), bc.range_in_raw()),
+            (ByteCodeKind::IncData, 3, range(1, 4))
+        );
+        assert_eq!(bc.to_dense().arg(), 3);
+        assert!(ByteCode::new(ByteCodeKind::LoopStartJumpIfDataZero, 0, range(0, 1)).is_ok());
+
+        let test_data = [
+            (
+                ByteCodeKind::Write,
+                0,
+                range(0, 0),
+                ByteCodeError::ZeroCount,
+            ),
+            (
+                ByteCodeKind::IncPtr,
+                u32::MAX as usize + 1,
+                range(0, 0),
+                ByteCodeError::ArgTooLarge(u32::MAX as usize + 1),
+            ),
+            (
+                ByteCodeKind::Read,
+                1,
+                range(2, 1),
+                ByteCodeError::ReversedRange,
+            ),
+        ];
+        for (kind, arg, range, expected) in test_data {
+            assert_eq!(ByteCode::new(kind, arg, range), Err(expected));
+        }
+    }
+
+    #[test]
+    fn programs_are_validated() {
+        let byte_codes = crate::source_file::UcSourceFile::from_str("+[->[-]<]", "")
+            .to_byte_codes()
+            .unwrap();
+        assert_eq!(validate(&byte_codes), Ok(()));
+
+        let mut bad_jump = byte_codes.clone();
+        bad_jump[4].arg = 1;
+        assert_eq!(
+            validate(&bad_jump),
+            Err(ProgramError {
+                idx: 4,
+                error: ByteCodeError::BadJump
+            })
+        );
+        assert_eq!(
+            DenseByteCodes::from(bad_jump.as_slice()).validate(),
+            validate(&bad_jump)
+        );
+        let mut zero = byte_codes.clone();
+        zero[2].arg = 0;
+        assert_eq!(
+            validate(&zero),
+            Err(ProgramError {
+                idx: 2,
+                error: ByteCodeError::ZeroCount
+            })
+        );
+        assert_eq!(
+            DenseByteCodes::from(zero.as_slice()).validate(),
+            validate(&zero)
+        );
+        assert_eq!(
+            DenseByteCodes::from(byte_codes.as_slice()).validate(),
+            Ok(())
+        );
+        assert_eq!(
+            validate(&byte_codes[..8]).map_err(|e| e.error),
+            Err(ByteCodeError::UnmatchedLoopStart)
+        );
+        assert_eq!(
+            validate(&byte_codes[2..]).map_err(|e| e.error),
+            Err(ByteCodeError::UnmatchedLoopEnd)
+        );
+    }
+
+    #[test]
+    fn builder() {
+        let mut builder = ProgramBuilder::new();
+        builder
+            .push(ByteCodeKind::IncData, 2)
+            .unwrap()
+            .push(ByteCodeKind::LoopStartJumpIfDataZero, 2)
+            .unwrap()
+            .push_at(ByteCodeKind::DecData, 1, range(3, 4))
+            .unwrap()
+            .push(ByteCodeKind::LoopEndJumpIfDataNotZero, 2)
+            .unwrap();
+        assert_eq!(builder.len(), 6);
+        let byte_codes = builder.build().unwrap();
+        assert_eq!(validate(&byte_codes), Ok(()));
+        assert_eq!(
+            byte_codes.iter().map(ByteCode::arg).collect::<Vec<_>>(),
+            vec![2, 5, 4, 1, 2, 1]
+        );
+        assert_eq!(byte_codes[3].range_in_raw(), range(3, 4));
+
+        let mut builder = ProgramBuilder::new();
+        builder
+            .push(ByteCodeKind::LoopStartJumpIfDataZero, 1)
+            .unwrap();
+        assert_eq!(
+            builder.push(ByteCodeKind::LoopEndJumpIfDataNotZero, 2),
+            Err(ProgramError {
+                idx: 2,
+                error: ByteCodeError::UnmatchedLoopEnd
+            })
+        );
+        assert_eq!(
+            builder.push(ByteCodeKind::Write, 0).map(|_| ()),
+            Err(ProgramError {
+                idx: 1,
+                error: ByteCodeError::ZeroCount
+            })
+        );
+        assert_eq!(
+            builder.build(),
+            Err(ProgramError {
+                idx: 0,
+                error: ByteCodeError::UnmatchedLoopStart
+            })
+        );
+    }
+
+    #[test]
+    fn dense_byte_codes_are_small() {
+        assert_eq!(std::mem::size_of::<DenseByteCode>(), 8);
+        assert!(std::mem::size_of::<ByteCode>() >= 24);
+    }
+
+    #[test]
+    fn dense_byte_codes_round_trip() {
+        let byte_codes = crate::source_file::UcSourceFile::from_str("+++[->>+<<]x.", "")
+            .to_byte_codes()
+            .unwrap();
+        let dense = DenseByteCodes::from(byte_codes.as_slice());
+        assert_eq!(dense.len(), byte_codes.len());
+        assert_eq!(dense.codes()[0].arg, 3);
+        let back = (0..dense.len())
+            .map(|idx| dense.get(idx).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(back, byte_codes);
+        assert_eq!(dense.get(dense.len()), None);
+    }
+
+    #[test]
+    fn opt_level_round_trip() {
+        for opt_level in [OptLevel::O0, OptLevel::O1] {
+            assert_eq!(opt_level.to_string().parse(), Ok(opt_level));
+        }
+        assert!("2".parse::<OptLevel>().is_err());
     }
 }
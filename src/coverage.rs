@@ -0,0 +1,387 @@
+//! which commands run and which way each `[` goes, reported as lcov or an annotated
+//! source
+//!
+//! numbers are kept per command grapheme, so runs of either engine and any optimization
+//! level are reported the same way, a merged `+++` counts as executed for each `+`
+
+use crate::{
+    byte_code::OptLevel,
+    machine::Engine,
+    observer::{Observer, Op},
+    source_file::{Dialect, SourcePos, UcSourceFile, UcSourceFileError, UcToken},
+};
+use console::Style;
+use std::collections::HashMap;
+
+/// collects raw numbers while running, indexed by instruction pointer
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct CoverageObserver {
+    /// executions and loop entries
+    counts: Vec<(u64, u64)>,
+}
+
+impl CoverageObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn slot(&mut self, instr_ptr: usize) -> &mut (u64, u64) {
+        if instr_ptr >= self.counts.len() {
+            self.counts.resize(instr_ptr + 1, (0, 0));
+        }
+        &mut self.counts[instr_ptr]
+    }
+
+    /// `engine` and `opt_level` must be the ones `src_file` ran with
+    pub fn coverage<'src_file>(
+        &self,
+        src_file: &'src_file UcSourceFile,
+        engine: Engine,
+        opt_level: OptLevel,
+    ) -> Result<Coverage, UcSourceFileError<'src_file>> {
+        let byte_codes = if engine.runs_byte_codes() {
+            Some(src_file.to_byte_codes_with(opt_level)?)
+        } else {
+            None
+        };
+
+        let mut coverage = Coverage::default();
+        let mut bc_idx = 0;
+        for (token_idx, token) in src_file.iter().enumerate() {
+            if !is_executable(src_file, token) {
+                continue;
+            }
+            let instr_ptr = match &byte_codes {
+                None => token_idx,
+                Some(byte_codes) => {
+                    // a merged byte code covers several tokens
+                    while byte_codes[bc_idx].range_in_raw.1 <= token.idx_in_raw {
+                        bc_idx += 1;
+                    }
+                    bc_idx
+                }
+            };
+            let (hits, entered) = self.counts.get(instr_ptr).copied().unwrap_or_default();
+            let pos = src_file.pos_of(token.idx_in_raw);
+            coverage.commands.push(CommandCoverage { pos, hits });
+            if token.uc == "[" {
+                coverage.branches.push(BranchCoverage {
+                    pos,
+                    entered,
+                    skipped: hits - entered,
+                });
+            }
+        }
+        Ok(coverage)
+    }
+}
+
+impl Observer for CoverageObserver {
+    fn before(&mut self, _op: Op, _arg: usize, instr_ptr: usize) {
+        self.slot(instr_ptr).0 += 1;
+    }
+
+    fn on_loop_entry(&mut self, instr_ptr: usize) {
+        self.slot(instr_ptr).1 += 1;
+    }
+}
+
+fn is_executable(src_file: &UcSourceFile, token: &UcToken) -> bool {
+    token.is_command() || (src_file.dialect() == Dialect::Extended && token.uc == "#")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct CommandCoverage {
+    pub pos: SourcePos,
+    pub hits: u64,
+}
+
+/// the two ways out of a `[`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct BranchCoverage {
+    /// position of `[`
+    pub pos: SourcePos,
+    /// times the body runs
+    pub entered: u64,
+    /// times it jumps past `]`
+    pub skipped: u64,
+}
+
+impl BranchCoverage {
+    /// both ways are taken at least once
+    pub fn is_covered(&self) -> bool {
+        self.entered > 0 && self.skipped > 0
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Coverage {
+    /// in source order
+    pub commands: Vec<CommandCoverage>,
+    /// in the order of `[`
+    pub branches: Vec<BranchCoverage>,
+}
+
+impl Coverage {
+    /// adds up the numbers of another run of the same program
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(
+            self.commands.len(),
+            other.commands.len(),
+            "different programs"
+        );
+        for (c, o) in self.commands.iter_mut().zip(&other.commands) {
+            c.hits += o.hits;
+        }
+        for (b, o) in self.branches.iter_mut().zip(&other.branches) {
+            b.entered += o.entered;
+            b.skipped += o.skipped;
+        }
+    }
+
+    pub fn covered_commands(&self) -> usize {
+        self.commands.iter().filter(|c| c.hits > 0).count()
+    }
+
+    /// each `[` has two branches
+    pub fn covered_branches(&self) -> usize {
+        self.branches
+            .iter()
+            .map(|b| usize::from(b.entered > 0) + usize::from(b.skipped > 0))
+            .sum()
+    }
+
+    /// e.g. `commands: 9/10 (90.0%), branches: 3/4 (75.0%)`
+    pub fn summary(&self) -> String {
+        fn ratio(covered: usize, total: usize) -> String {
+            let percent = if total == 0 {
+                100.0
+            } else {
+                covered as f64 * 100.0 / total as f64
+            };
+            format!("{}/{} ({:.1}%)", covered, total, percent)
+        }
+        format!(
+            "commands: {}, branches: {}",
+            ratio(self.covered_commands(), self.commands.len()),
+            ratio(self.covered_branches(), self.branches.len() * 2)
+        )
+    }
+
+    /// highest hits of each line that has commands
+    fn lines(&self) -> Vec<(usize, u64)> {
+        let mut lines = Vec::<(usize, u64)>::new();
+        for c in &self.commands {
+            match lines.last_mut() {
+                Some((line, hits)) if *line == c.pos.line => *hits = (*hits).max(c.hits),
+                _ => lines.push((c.pos.line, c.hits)),
+            }
+        }
+        lines
+    }
+
+    /// lcov tracefile of one source file, `src_file` must be the covered program
+    ///
+    /// branch 0 of a `[` is entering the loop, branch 1 is skipping it
+    pub fn to_lcov(&self, src_file: &UcSourceFile) -> String {
+        let mut s = format!("TN:\nSF:{}\n", src_file.filename().display());
+        for (block, b) in self.branches.iter().enumerate() {
+            for (branch, taken) in [b.entered, b.skipped].into_iter().enumerate() {
+                let taken = if b.entered + b.skipped == 0 {
+                    String::from("-")
+                } else {
+                    taken.to_string()
+                };
+                s.push_str(&format!(
+                    "BRDA:{},{},{},{}\n",
+                    b.pos.line, block, branch, taken
+                ));
+            }
+        }
+        s.push_str(&format!("BRF:{}\n", self.branches.len() * 2));
+        s.push_str(&format!("BRH:{}\n", self.covered_branches()));
+
+        let lines = self.lines();
+        for (line, hits) in &lines {
+            s.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        s.push_str(&format!("LF:{}\n", lines.len()));
+        s.push_str(&format!(
+            "LH:{}\n",
+            lines.iter().filter(|(_, hits)| *hits > 0).count()
+        ));
+        s.push_str("end_of_record\n");
+        s
+    }
+
+    /// source lines prefixed with their hits, `-` without commands and `#####` never
+    /// executed
+    ///
+    /// with `colored`, executed commands are green, the others red, and `[` taking only
+    /// one way yellow
+    pub fn annotate(&self, src_file: &UcSourceFile, colored: bool) -> String {
+        let hit = Style::new().green().force_styling(colored);
+        let missed = Style::new().red().force_styling(colored);
+        let partial = Style::new().yellow().force_styling(colored);
+
+        let mut styles = self
+            .commands
+            .iter()
+            .map(|c| (c.pos, if c.hits > 0 { &hit } else { &missed }))
+            .collect::<HashMap<_, _>>();
+        for b in &self.branches {
+            if (b.entered > 0) != (b.skipped > 0) {
+                styles.insert(b.pos, &partial);
+            }
+        }
+        let lines = self.lines().into_iter().collect::<HashMap<_, _>>();
+
+        let mut s = String::new();
+        let mut line = 1;
+        while let Some(text) = src_file.line(line) {
+            // nothing after the last line break
+            if text.is_empty() && src_file.line(line + 1).is_none() {
+                break;
+            }
+            let hits = match lines.get(&line) {
+                None => String::from("-"),
+                Some(0) => String::from("#####"),
+                Some(hits) => hits.to_string(),
+            };
+            s.push_str(&format!("{:>9} | ", hits));
+            for (idx, g) in
+                unicode_segmentation::UnicodeSegmentation::graphemes(text, true).enumerate()
+            {
+                let pos = SourcePos { line, col: idx + 1 };
+                match styles.get(&pos) {
+                    Some(style) => s.push_str(&style.apply_to(g).to_string()),
+                    None => s.push_str(g),
+                }
+            }
+            s.push('\n');
+            line += 1;
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{machine::Machine, machine_io::BufferedMachineIO};
+
+    fn coverage(src: &str, input: &str, engine: Engine, opt_level: OptLevel) -> Coverage {
+        let src_file = UcSourceFile::from_str(src, "prog.bf");
+        let mut machine = Machine::with_observer(
+            Default::default(),
+            BufferedMachineIO::new(input),
+            CoverageObserver::new(),
+        );
+        let _ = machine.eval(&src_file, engine, opt_level);
+        machine
+            .observer()
+            .coverage(&src_file, engine, opt_level)
+            .unwrap()
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_default_debug(&CoverageObserver::new());
+        is_default_debug(&Coverage::default());
+        is_small_value_struct_but_no_default(&CommandCoverage {
+            pos: SourcePos { line: 1, col: 1 },
+            hits: 0,
+        });
+    }
+
+    #[test]
+    fn all_engines_agree() {
+        let src = "++ [>+<-] comment\n[never]>.";
+        let expected = coverage(src, "", Engine::Source, OptLevel::O0);
+        assert_eq!(expected.commands.len(), 12);
+        assert_eq!(expected.commands[0].hits, 1);
+        assert_eq!(expected.commands[3].hits, 2);
+        assert_eq!(expected.commands[9].hits, 0);
+        assert_eq!(
+            expected.branches,
+            vec![
+                BranchCoverage {
+                    pos: SourcePos { line: 1, col: 4 },
+                    entered: 2,
+                    skipped: 0
+                },
+                BranchCoverage {
+                    pos: SourcePos { line: 2, col: 1 },
+                    entered: 0,
+                    skipped: 1
+                },
+            ]
+        );
+        for opt_level in [OptLevel::O0, OptLevel::O1] {
+            assert_eq!(
+                coverage(src, "", Engine::ByteCode, opt_level).covered_commands(),
+                expected.covered_commands()
+            );
+            assert_eq!(
+                coverage(src, "", Engine::ByteCode, opt_level).branches,
+                expected.branches
+            );
+        }
+        assert_eq!(
+            expected.summary(),
+            "commands: 11/12 (91.7%), branches: 2/4 (50.0%)"
+        );
+    }
+
+    #[test]
+    fn merging_runs() {
+        let src = ",[.,]";
+        let mut merged = coverage(src, "", Engine::Source, OptLevel::O1);
+        assert_eq!(merged.covered_branches(), 1);
+        merged.merge(&coverage(src, "ab", Engine::Source, OptLevel::O1));
+        assert_eq!(merged.covered_branches(), 2);
+        assert_eq!(merged.commands[0].hits, 2);
+        assert_eq!(merged.commands[2].hits, 2);
+    }
+
+    #[test]
+    fn lcov() {
+        let src = "+[-]\n\n[+]";
+        let src_file = UcSourceFile::from_str(src, "prog.bf");
+        let lcov = coverage(src, "", Engine::ByteCode, OptLevel::O1).to_lcov(&src_file);
+        assert_eq!(
+            lcov,
+            "TN:\nSF:prog.bf\n\
+             BRDA:1,0,0,1\nBRDA:1,0,1,0\nBRDA:3,1,0,0\nBRDA:3,1,1,1\nBRF:4\nBRH:2\n\
+             DA:1,1\nDA:3,1\nLF:2\nLH:2\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn annotation() {
+        let src = "+[-] x\n\nx\n>[+]<\n";
+        let src_file = UcSourceFile::from_str(src, "prog.bf");
+        let coverage = coverage(src, "", Engine::Source, OptLevel::O1);
+        assert_eq!(
+            coverage.annotate(&src_file, false),
+            "        1 | +[-] x\n        - | \n        - | x\n        1 | >[+]<\n"
+        );
+        let colored = coverage.annotate(&src_file, true);
+        assert!(colored.contains(
+            &Style::new()
+                .red()
+                .force_styling(true)
+                .apply_to("+")
+                .to_string()
+        ));
+        assert!(colored.contains(
+            &Style::new()
+                .yellow()
+                .force_styling(true)
+                .apply_to("[")
+                .to_string()
+        ));
+    }
+}
@@ -0,0 +1,753 @@
+//! a debug adapter speaking the Debug Adapter Protocol, so editors can debug programs
+//! through `Debugger`
+//!
+//! there is a single thread whose only frame is the whole program, its scopes are the
+//! registers, and the cells around the data pointer. Cells can be watched with data
+//! breakpoints. Program output is sent as `output` events, program input is given by the
+//! `input` launch argument
+//!
+//! programs run in slices, requests are read on their own thread and handled between
+//! slices, so a program that never ends can still be paused or disconnected from
+
+use crate::{
+    debugger::{Breakpoint, Debugger, Stop, Target},
+    machine::{CellWidth, EofBehavior, Machine, MachineConfig},
+    machine_io::BufferedMachineIO,
+    protocol::{read_message, write_message},
+    source_file::{Dialect, UcSourceFile},
+};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{channel, TryRecvError};
+
+const THREAD_ID: u64 = 1;
+const REGISTERS_REF: u64 = 1;
+const TAPE_REF: u64 = 2;
+/// cells on each side of the data pointer in the tape scope
+const TAPE_RADIUS: usize = 8;
+/// commands run between checks for new requests
+const SLICE: usize = 100_000;
+
+#[derive(Debug)]
+struct Session {
+    debugger: Debugger<'static, BufferedMachineIO>,
+    /// ids of breakpoints set by `setBreakpoints`
+    locations: Vec<usize>,
+    /// ids of breakpoints set by `setDataBreakpoints`
+    watches: Vec<usize>,
+    stop_on_entry: bool,
+    /// where the program is heading, `None` while it is stopped
+    running: Option<Target>,
+}
+
+#[derive(Debug)]
+pub struct DapServer<W> {
+    out: W,
+    seq: u64,
+    session: Option<Session>,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            seq: 0,
+            session: None,
+        }
+    }
+
+    /// handle requests from `input` until it is closed or the client disconnects
+    pub fn serve<R: BufRead + Send + 'static>(&mut self, mut input: R) -> std::io::Result<()> {
+        let (sender, requests) = channel();
+        // not joined, it may be blocked on `input` after the client disconnects
+        std::thread::spawn(move || loop {
+            let msg = read_message(&mut input).transpose();
+            let last = !matches!(msg, Some(Ok(_)));
+            if msg.is_some_and(|msg| sender.send(msg).is_err()) || last {
+                break;
+            }
+        });
+
+        loop {
+            let running = self.session.as_ref().is_some_and(|s| s.running.is_some());
+            let msg = if running {
+                match requests.try_recv() {
+                    Ok(msg) => msg,
+                    Err(TryRecvError::Empty) => {
+                        self.run_slice()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match requests.recv() {
+                    Ok(msg) => msg,
+                    Err(_) => break,
+                }
+            };
+            if !self.handle(&msg?)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut msg: Value) -> std::io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        write_message(&mut self.out, &msg)
+    }
+
+    fn respond(&mut self, request: &Value, body: Result<Value, String>) -> std::io::Result<()> {
+        let mut msg = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => msg["body"] = body,
+            Err(message) => msg["message"] = json!(message),
+        }
+        self.send(msg)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> std::io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+
+    /// returns `false` once the client disconnects
+    pub fn handle(&mut self, request: &Value) -> std::io::Result<bool> {
+        if request["type"] != "request" {
+            return Ok(true);
+        }
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        match command {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsDataBreakpoints": true,
+                });
+                self.respond(request, Ok(capabilities))?;
+                self.event("initialized", json!({}))?;
+            }
+            "launch" => {
+                let result = self.launch(args);
+                self.respond(request, result.map(|_| json!({})))?;
+            }
+            "setBreakpoints" => {
+                let result = self.set_breakpoints(args);
+                self.respond(request, result)?;
+            }
+            "dataBreakpointInfo" => {
+                let body = match args["name"].as_str().and_then(|n| n.parse::<usize>().ok()) {
+                    Some(cell) => json!({
+                        "dataId": cell.to_string(),
+                        "description": format!("cell {}", cell),
+                        "accessTypes": ["write"],
+                    }),
+                    None => json!({"dataId": null, "description": "not a cell"}),
+                };
+                self.respond(request, Ok(body))?;
+            }
+            "setDataBreakpoints" => {
+                let result = self.set_data_breakpoints(args);
+                self.respond(request, result)?;
+            }
+            "configurationDone" => {
+                self.respond(request, Ok(json!({})))?;
+                match &self.session {
+                    Some(session) if session.stop_on_entry => {
+                        self.stopped("entry", None)?;
+                    }
+                    Some(_) => self.start(Target::End)?,
+                    None => (),
+                }
+            }
+            "threads" => {
+                let threads = json!({"threads": [{"id": THREAD_ID, "name": "main"}]});
+                self.respond(request, Ok(threads))?;
+            }
+            "stackTrace" => {
+                let result = self.stack_trace();
+                self.respond(request, result)?;
+            }
+            "scopes" => {
+                let scopes = json!({"scopes": [
+                    {"name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false},
+                    {"name": "Tape", "variablesReference": TAPE_REF, "expensive": false},
+                ]});
+                self.respond(request, Ok(scopes))?;
+            }
+            "variables" => {
+                let result = self.variables(args["variablesReference"].as_u64());
+                self.respond(request, result)?;
+            }
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                let Some(session) = self.session.as_ref() else {
+                    self.respond(request, Err(String::from("not launched")))?;
+                    return Ok(true);
+                };
+                let target = match command {
+                    "continue" => Target::End,
+                    "next" => session.debugger.step_over_target(),
+                    "stepIn" => Target::Steps(1),
+                    _ => session.debugger.finish_target().unwrap_or(Target::End),
+                };
+                let body = match command {
+                    "continue" => json!({"allThreadsContinued": true}),
+                    _ => json!({}),
+                };
+                self.respond(request, Ok(body))?;
+                self.start(target)?;
+            }
+            "pause" => {
+                let paused = self
+                    .session
+                    .as_mut()
+                    .and_then(|session| session.running.take());
+                match paused {
+                    Some(_) => {
+                        self.respond(request, Ok(json!({})))?;
+                        self.stopped("pause", None)?;
+                    }
+                    None => self.respond(request, Err(String::from("not running")))?,
+                }
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(json!({})))?;
+                return Ok(false);
+            }
+            _ => {
+                let message = format!("unsupported request `{}`", command);
+                self.respond(request, Err(message))?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// arguments are named like the flags of `bfi run`, and take the same values
+    fn launch(&mut self, args: &Value) -> Result<(), String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or_else(|| String::from("`program` is missing"))?;
+        let dialect = match string_arg(args, "dialect")? {
+            Some(dialect) => dialect.parse::<Dialect>()?,
+            None => Dialect::default(),
+        };
+        let mut config = MachineConfig::default();
+        if let Some(tape_size) = number_arg(args, "tapeSize")? {
+            config.tape_size = usize::try_from(tape_size)
+                .ok()
+                .filter(|&n| n > 0 && n <= MachineConfig::MAX_TAPE_SIZE)
+                .ok_or_else(|| format!("`tapeSize` {} is out of range", tape_size))?;
+        }
+        if let Some(bits) = number_arg(args, "cellWidth")? {
+            config.cell_width = u32::try_from(bits)
+                .ok()
+                .and_then(CellWidth::from_bits)
+                .ok_or_else(|| format!("unknown `cellWidth` {}, expecting 8, 16 or 32", bits))?;
+        }
+        if let Some(eof) = string_arg(args, "eof")? {
+            config.eof = eof.parse::<EofBehavior>()?;
+        }
+        let step_limit = number_arg(args, "stepLimit")?;
+        let stop_on_entry = match &args["stopOnEntry"] {
+            Value::Null => false,
+            value => value
+                .as_bool()
+                .ok_or_else(|| String::from("`stopOnEntry` must be a boolean"))?,
+        };
+
+        let src_file =
+            UcSourceFile::new_with_dialect(program, dialect).map_err(|e| e.to_string())?;
+        let input = match string_arg(args, "input")? {
+            Some(input) => input,
+            None => src_file.embedded_input().unwrap_or_default(),
+        };
+        let mut machine = Machine::with_config(config, BufferedMachineIO::new(input));
+        machine.set_step_limit(step_limit);
+        self.session = Some(Session {
+            debugger: Debugger::from_owned(src_file, machine)?,
+            locations: Vec::new(),
+            watches: Vec::new(),
+            stop_on_entry,
+            running: None,
+        });
+        Ok(())
+    }
+
+    fn session(&mut self) -> Result<&mut Session, String> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| String::from("not launched"))
+    }
+
+    /// replaces all location breakpoints, only the launched program has them
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        for id in session.locations.drain(..) {
+            session.debugger.delete_breakpoint(id);
+        }
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut breakpoints = Vec::new();
+        for bp in requested {
+            let line = bp["line"].as_u64().unwrap_or_default() as usize;
+            let col = bp["column"].as_u64().map(|col| col as usize);
+            if !session.debugger.has_command_at(line, col) {
+                breakpoints.push(json!({"verified": false, "line": line}));
+                continue;
+            }
+            let id = session
+                .debugger
+                .add_breakpoint(Breakpoint::Location { line, col });
+            session.locations.push(id);
+            breakpoints.push(json!({"id": id, "verified": true, "line": line}));
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_data_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        for id in session.watches.drain(..) {
+            session.debugger.delete_breakpoint(id);
+        }
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut breakpoints = Vec::new();
+        for bp in requested {
+            match bp["dataId"].as_str().and_then(|d| d.parse::<usize>().ok()) {
+                Some(cell) => {
+                    let id = session.debugger.add_breakpoint(Breakpoint::Watch { cell });
+                    session.watches.push(id);
+                    breakpoints.push(json!({"id": id, "verified": true}));
+                }
+                None => breakpoints.push(json!({"verified": false})),
+            }
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let debugger = &self.session()?.debugger;
+        let src_file = debugger.src_file();
+        let source = json!({
+            "name": src_file.filename().file_name().map(|n| n.to_string_lossy()),
+            "path": src_file.filename().display().to_string(),
+        });
+        let frames = match debugger.position() {
+            Some(pos) => json!([{
+                "id": 0,
+                "name": "main",
+                "source": source,
+                "line": pos.line,
+                "column": pos.col,
+            }]),
+            None => json!([]),
+        };
+        let total = frames.as_array().map_or(0, Vec::len);
+        Ok(json!({"stackFrames": frames, "totalFrames": total}))
+    }
+
+    fn variables(&mut self, reference: Option<u64>) -> Result<Value, String> {
+        let machine = self.session()?.debugger.machine();
+        let var = |name: String, value: String| json!({"name": name, "value": value, "variablesReference": 0});
+        let variables = match reference {
+            Some(REGISTERS_REF) => {
+                let cell = machine.cells().get(machine.data_ptr()).copied();
+                vec![
+                    var(String::from("data_ptr"), machine.data_ptr().to_string()),
+                    var(String::from("instr_ptr"), machine.instr_ptr().to_string()),
+                    var(
+                        String::from("cell"),
+                        cell.map_or_else(|| String::from("out of the tape"), |c| c.to_string()),
+                    ),
+                    var(String::from("executed"), machine.executed().to_string()),
+                ]
+            }
+            Some(TAPE_REF) => {
+                let window = machine.tape_window(TAPE_RADIUS);
+                window
+                    .cells
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| var((window.start + i).to_string(), v.to_string()))
+                    .collect()
+            }
+            _ => return Err(String::from("unknown variables reference")),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// sets the program running towards `target`, the first slice is run right away
+    fn start(&mut self, target: Target) -> std::io::Result<()> {
+        if let Some(session) = self.session.as_mut() {
+            session.running = Some(target);
+        }
+        self.run_slice()
+    }
+
+    /// runs the program a bit further, and reports if it stopped
+    fn run_slice(&mut self) -> std::io::Result<()> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(());
+        };
+        let Some(target) = session.running.as_mut() else {
+            return Ok(());
+        };
+        let stop = session.debugger.run_for(target, SLICE);
+        if stop.is_some() {
+            session.running = None;
+        }
+        let output = session.debugger.io_mut().take_output();
+        if !output.is_empty() {
+            self.event("output", json!({"category": "stdout", "output": output}))?;
+        }
+        let Some(stop) = stop else {
+            return Ok(());
+        };
+        match stop {
+            Stop::Stepped => self.stopped("step", None),
+            Stop::Breakpoint { id } => {
+                let reason = if self
+                    .session
+                    .as_ref()
+                    .is_some_and(|s| s.watches.contains(&id))
+                {
+                    "data breakpoint"
+                } else {
+                    "breakpoint"
+                };
+                self.stopped(reason, Some(id))
+            }
+            Stop::Watchpoint { id, .. } => self.stopped("data breakpoint", Some(id)),
+            Stop::Error(e) => {
+                let body = json!({
+                    "reason": "exception",
+                    "threadId": THREAD_ID,
+                    "text": e.to_string(),
+                    "allThreadsStopped": true,
+                });
+                self.event("stopped", body)
+            }
+            Stop::Finished => {
+                self.event("exited", json!({"exitCode": 0}))?;
+                self.event("terminated", json!({}))
+            }
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, breakpoint: Option<usize>) -> std::io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(id) = breakpoint {
+            body["hitBreakpointIds"] = json!([id]);
+        }
+        self.event("stopped", body)
+    }
+}
+
+/// `None` if `name` is not given
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<Option<&'a str>, String> {
+    match &args[name] {
+        Value::Null => Ok(None),
+        value => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| format!("`{}` must be a string", name)),
+    }
+}
+
+/// `None` if `name` is not given
+fn number_arg(args: &Value, name: &str) -> Result<Option<u64>, String> {
+    match &args[name] {
+        Value::Null => Ok(None),
+        value => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("`{}` must be a non-negative integer", name)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// runs `requests` and returns all messages sent back
+    fn drive(requests: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            write_message(&mut input, &request).unwrap();
+        }
+        let mut output = Vec::new();
+        DapServer::new(&mut output)
+            .serve(std::io::Cursor::new(input))
+            .unwrap();
+
+        let mut r = output.as_slice();
+        let mut messages = Vec::new();
+        while let Some(msg) = read_message(&mut r).unwrap() {
+            messages.push(msg);
+        }
+        messages
+    }
+
+    fn program(name: &str, src: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, src).unwrap();
+        path.display().to_string()
+    }
+
+    fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+        messages.iter().filter(|m| m["event"] == event).collect()
+    }
+
+    fn response<'a>(messages: &'a [Value], command: &str) -> &'a Value {
+        messages
+            .iter()
+            .find(|m| m["type"] == "response" && m["command"] == command)
+            .unwrap()
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_debug(&DapServer::new(Vec::<u8>::new()));
+    }
+
+    #[test]
+    fn run_to_end_without_breakpoints() {
+        let path = program("brainfuck_dap_run_to_end.bf", ",+.,+.");
+        let messages = drive(&[
+            json!({"command": "initialize", "arguments": {"adapterID": "bf"}}),
+            json!({"command": "launch", "arguments": {"program": path, "input": "ab"}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "disconnect"}),
+        ]);
+
+        assert_eq!(messages[0]["command"], "initialize");
+        assert_eq!(messages[0]["body"]["supportsDataBreakpoints"], true);
+        assert_eq!(messages[1]["event"], "initialized");
+        assert_eq!(response(&messages, "launch")["success"], true);
+        assert_eq!(events(&messages, "output")[0]["body"]["output"], "bc");
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+        assert_eq!(events(&messages, "terminated").len(), 1);
+        // seq numbers of the adapter are increasing from 1
+        for (i, m) in messages.iter().enumerate() {
+            assert_eq!(m["seq"], i + 1);
+        }
+    }
+
+    #[test]
+    fn breakpoints_stepping_and_variables() {
+        let path = program("brainfuck_dap_breakpoints.bf", "++\n[>+<-]\n>.");
+        let messages = drive(&[
+            json!({"command": "initialize"}),
+            json!({"command": "launch", "arguments": {"program": path, "tapeSize": 10}}),
+            json!({"command": "setBreakpoints", "arguments": {
+                "source": {"path": path},
+                "breakpoints": [{"line": 2, "column": 3}, {"line": 9}],
+            }}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+            json!({"command": "variables", "arguments": {"variablesReference": 1}}),
+            json!({"command": "next", "arguments": {"threadId": 1}}),
+            json!({"command": "stackTrace", "arguments": {"threadId": 1}}),
+            json!({"command": "stepOut", "arguments": {"threadId": 1}}),
+            json!({"command": "variables", "arguments": {"variablesReference": 2}}),
+            json!({"command": "continue", "arguments": {"threadId": 1}}),
+        ]);
+
+        let breakpoints = &response(&messages, "setBreakpoints")["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[1]["verified"], false);
+        assert_eq!(breakpoints[1]["id"], Value::Null);
+
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+        assert_eq!(stopped[0]["body"]["hitBreakpointIds"], json!([1]));
+        assert_eq!(stopped[1]["body"]["reason"], "step");
+        // `stepOut` runs until the loop exits, but the breakpoint is hit first
+        assert_eq!(stopped[2]["body"]["reason"], "breakpoint");
+
+        let traces = messages
+            .iter()
+            .filter(|m| m["command"] == "stackTrace")
+            .map(|m| &m["body"]["stackFrames"][0])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            (&traces[0]["line"], &traces[0]["column"]),
+            (&json!(2), &json!(3))
+        );
+        assert_eq!(
+            (&traces[1]["line"], &traces[1]["column"]),
+            (&json!(2), &json!(4))
+        );
+
+        let variables = messages
+            .iter()
+            .filter(|m| m["command"] == "variables")
+            .map(|m| &m["body"]["variables"])
+            .collect::<Vec<_>>();
+        assert_eq!(
+            variables[0][0],
+            json!({"name": "data_ptr", "value": "6", "variablesReference": 0})
+        );
+        assert_eq!(variables[0][2]["value"], "0");
+        let tape = variables[1].as_array().unwrap();
+        assert_eq!(tape.len(), 10);
+        assert_eq!(tape[5]["value"], "1");
+        assert_eq!(tape[6]["value"], "1");
+
+        assert_eq!(events(&messages, "terminated").len(), 1);
+    }
+
+    #[test]
+    fn data_breakpoints_and_entry() {
+        let path = program("brainfuck_dap_data_breakpoints.bf", "+>++<-");
+        let messages = drive(&[
+            json!({"command": "initialize"}),
+            json!({"command": "launch", "arguments": {
+                "program": path, "tapeSize": 10, "stopOnEntry": true,
+            }}),
+            json!({"command": "dataBreakpointInfo", "arguments": {"variablesReference": 2, "name": "6"}}),
+            json!({"command": "setDataBreakpoints", "arguments": {"breakpoints": [{"dataId": "6"}]}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "continue"}),
+            json!({"command": "continue"}),
+            json!({"command": "continue"}),
+        ]);
+
+        assert_eq!(
+            response(&messages, "dataBreakpointInfo")["body"]["dataId"],
+            "6"
+        );
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped[0]["body"]["reason"], "entry");
+        assert_eq!(stopped[1]["body"]["reason"], "data breakpoint");
+        assert_eq!(stopped[2]["body"]["reason"], "data breakpoint");
+        assert_eq!(events(&messages, "terminated").len(), 1);
+    }
+
+    #[test]
+    fn programs_that_never_end_can_be_paused() {
+        let path = program("brainfuck_dap_pause.bf", "+[]");
+        let messages = drive(&[
+            json!({"command": "initialize"}),
+            json!({"command": "pause"}),
+            json!({"command": "launch", "arguments": {"program": path}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "pause", "arguments": {"threadId": 1}}),
+            json!({"command": "pause", "arguments": {"threadId": 1}}),
+            json!({"command": "variables", "arguments": {"variablesReference": 1}}),
+            json!({"command": "continue", "arguments": {"threadId": 1}}),
+            json!({"command": "disconnect"}),
+        ]);
+
+        let pauses = messages
+            .iter()
+            .filter(|m| m["command"] == "pause")
+            .map(|m| &m["success"])
+            .collect::<Vec<_>>();
+        assert_eq!(pauses, [false, true, false]);
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped.len(), 1);
+        assert_eq!(stopped[0]["body"]["reason"], "pause");
+        let executed = &response(&messages, "variables")["body"]["variables"][3];
+        assert_ne!(executed["value"], "0");
+        assert_eq!(response(&messages, "disconnect")["success"], true);
+        assert_eq!(events(&messages, "terminated").len(), 0);
+    }
+
+    #[test]
+    fn launch_arguments_are_like_bfi_flags() {
+        let path = program("brainfuck_dap_launch.bf", "-.!\u{1}");
+        let extended = program("brainfuck_dap_extended.bf", ",.!a");
+        let messages = drive(&[
+            json!({"command": "launch", "arguments": {
+                "program": path, "cellWidth": 16, "eof": "max", "tapeSize": 4,
+            }}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "variables", "arguments": {"variablesReference": 2}}),
+            json!({"command": "launch", "arguments": {"program": extended, "dialect": "extended"}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "launch", "arguments": {"program": path, "stepLimit": 1}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "launch", "arguments": {"program": path, "cellWidth": 12}}),
+            json!({"command": "launch", "arguments": {"program": path, "eof": "none"}}),
+            json!({"command": "launch", "arguments": {"program": path, "dialect": "ext"}}),
+            json!({"command": "launch", "arguments": {"program": path, "tapeSize": 0}}),
+            json!({"command": "launch", "arguments": {"program": path, "stepLimit": "1"}}),
+        ]);
+
+        let tape = &response(&messages, "variables")["body"]["variables"];
+        assert_eq!(tape[2]["value"], "65535");
+        let outputs = events(&messages, "output");
+        assert_eq!(outputs[0]["body"]["output"], "\u{ffff}");
+        assert_eq!(outputs[1]["body"]["output"], "a");
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped[0]["body"]["reason"], "exception");
+        assert_eq!(
+            stopped[0]["body"]["text"],
+            "executed more than 1 commands, stopped at instruction 2"
+        );
+
+        let failures = messages
+            .iter()
+            .filter(|m| m["success"] == false)
+            .map(|m| m["message"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            failures,
+            vec![
+                "unknown `cellWidth` 12, expecting 8, 16 or 32",
+                "unknown eof behavior `none`, expecting zero, unchanged or max",
+                "unknown dialect `ext`, expecting standard or extended",
+                "`tapeSize` 0 is out of range",
+                "`stepLimit` must be a non-negative integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let bad = program("brainfuck_dap_errors.bf", "+[");
+        let crash = program("brainfuck_dap_crash.bf", "<+");
+        let messages = drive(&[
+            json!({"command": "stackTrace"}),
+            json!({"command": "launch", "arguments": {}}),
+            json!({"command": "launch", "arguments": {"program": bad}}),
+            json!({"command": "launch", "arguments": {"program": crash, "tapeSize": 1}}),
+            json!({"command": "configurationDone"}),
+            json!({"command": "evaluate"}),
+        ]);
+
+        let failures = messages
+            .iter()
+            .filter(|m| m["success"] == false)
+            .map(|m| m["message"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            failures,
+            vec![
+                "not launched",
+                "`program` is missing",
+                "1 unmatched paren(s): `[` at 1:2",
+                "unsupported request `evaluate`",
+            ]
+        );
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped[0]["body"]["reason"], "exception");
+        assert_eq!(
+            stopped[0]["body"]["text"],
+            "data pointer -1 is out of the tape at instruction 1"
+        );
+    }
+}
@@ -0,0 +1,893 @@
+use crate::{
+    byte_code::{ByteCode, ByteCodeKind, OptLevel},
+    machine::{CellDataType, Machine, RuntimeError},
+    machine_io::MachineIO,
+    source_file::{SourcePos, UcSourceFile, UcSourceFileError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    pub fn all() -> &'static [CmpOp] {
+        &[Self::Eq, Self::Ne, Self::Lt, Self::Le, Self::Gt, Self::Ge]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+
+    pub fn eval(&self, lhs: usize, rhs: usize) -> bool {
+        match self {
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+            Self::Lt => lhs < rhs,
+            Self::Le => lhs <= rhs,
+            Self::Gt => lhs > rhs,
+            Self::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl std::fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for CmpOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::all()
+            .iter()
+            .find(|op| op.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown comparison `{}`", s))
+    }
+}
+
+/// what a condition breakpoint looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Subject {
+    /// `ptr`, the data pointer
+    DataPtr,
+    /// `cell`, value of the cell at the data pointer
+    Cell,
+}
+
+impl std::fmt::Display for Subject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DataPtr => f.write_str("ptr"),
+            Self::Cell => f.write_str("cell"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Breakpoint {
+    /// stops before any command starting at `line`, or exactly at `line:col`
+    Location { line: usize, col: Option<usize> },
+    /// stops when the condition becomes true
+    Condition {
+        subject: Subject,
+        op: CmpOp,
+        value: usize,
+    },
+    /// stops after the value of cell `cell` changes
+    Watch { cell: usize },
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Location { line, col: None } => write!(f, "breakpoint at line {}", line),
+            Self::Location {
+                line,
+                col: Some(col),
+            } => write!(f, "breakpoint at {}:{}", line, col),
+            Self::Condition { subject, op, value } => {
+                write!(f, "breakpoint when {} {} {}", subject, op, value)
+            }
+            Self::Watch { cell } => write!(f, "watchpoint on cell {}", cell),
+        }
+    }
+}
+
+/// `line`, `line:col`, `ptr <op> n` or `cell <op> n`
+impl std::str::FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            [subject @ ("ptr" | "cell"), op, value] => Ok(Self::Condition {
+                subject: if subject == "ptr" {
+                    Subject::DataPtr
+                } else {
+                    Subject::Cell
+                },
+                op: op.parse()?,
+                value: parse_num(value)?,
+            }),
+            [location] => match location.split_once(':') {
+                Some((line, col)) => Ok(Self::Location {
+                    line: parse_num(line)?,
+                    col: Some(parse_num(col)?),
+                }),
+                None => Ok(Self::Location {
+                    line: parse_num(location)?,
+                    col: None,
+                }),
+            },
+            _ => Err(format!("bad breakpoint `{}`", s)),
+        }
+    }
+}
+
+fn parse_num(s: &str) -> Result<usize, String> {
+    s.parse()
+        .map_err(|_| format!("expecting a number, got `{}`", s))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Command {
+    /// restart from the beginning
+    Run,
+    Continue,
+    /// run `n` commands
+    Step(usize),
+    /// like `Step(1)`, but runs a whole loop if it is at a `[`
+    Next,
+    /// run until the innermost loop around the current command exits
+    Finish,
+    Break(Breakpoint),
+    Delete(usize),
+    Info,
+    /// cell values around the data pointer, of one cell, or of an inclusive range
+    Print {
+        start: Option<usize>,
+        end: Option<usize>,
+    },
+    /// show the current source line
+    List,
+    Help,
+    Quit,
+}
+
+impl std::str::FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let rest = rest.trim();
+        let no_arg = |cmd: Command| {
+            if rest.is_empty() {
+                Ok(cmd)
+            } else {
+                Err(format!("`{}` takes no argument", name))
+            }
+        };
+        match name {
+            "r" | "run" => no_arg(Self::Run),
+            "c" | "continue" => no_arg(Self::Continue),
+            "s" | "step" if rest.is_empty() => Ok(Self::Step(1)),
+            "s" | "step" => match parse_num(rest)? {
+                0 => Err(String::from("`step` takes at least 1 command")),
+                n => Ok(Self::Step(n)),
+            },
+            "n" | "next" => no_arg(Self::Next),
+            "f" | "finish" => no_arg(Self::Finish),
+            "b" | "break" => Ok(Self::Break(rest.parse()?)),
+            "w" | "watch" => Ok(Self::Break(Breakpoint::Watch {
+                cell: parse_num(rest)?,
+            })),
+            "d" | "delete" => Ok(Self::Delete(parse_num(rest)?)),
+            "i" | "info" => no_arg(Self::Info),
+            "p" | "print" => {
+                let mut nums = rest.split_whitespace().map(parse_num);
+                let start = nums.next().transpose()?;
+                let end = nums.next().transpose()?;
+                match nums.next() {
+                    None => Ok(Self::Print { start, end }),
+                    Some(_) => Err(String::from("`print` takes at most two cells")),
+                }
+            }
+            "l" | "list" => no_arg(Self::List),
+            "h" | "help" => no_arg(Self::Help),
+            "q" | "quit" => no_arg(Self::Quit),
+            _ => Err(format!("unknown command `{}`, try `help`", name)),
+        }
+    }
+}
+
+/// why the program stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Stop {
+    /// the requested steps are done
+    Stepped,
+    Breakpoint {
+        id: usize,
+    },
+    Watchpoint {
+        id: usize,
+        cell: usize,
+        old: CellDataType,
+        new: CellDataType,
+    },
+    Finished,
+    Error(RuntimeError),
+}
+
+/// where a run ends if nothing stops it earlier, see `Debugger::run_for`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Target {
+    /// the end of the program
+    End,
+    /// after this many more commands
+    Steps(usize),
+    /// once the instruction pointer gets here
+    InstrPtr(usize),
+}
+
+impl std::fmt::Display for Stop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stepped => Ok(()),
+            Self::Breakpoint { id } => write!(f, "breakpoint {} hit", id),
+            Self::Watchpoint { id, cell, old, new } => {
+                write!(f, "watchpoint {}: cell {} {} -> {}", id, cell, old, new)
+            }
+            Self::Finished => f.write_str("program finished"),
+            Self::Error(e) => write!(f, "runtime error: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+struct Armed {
+    id: usize,
+    breakpoint: Breakpoint,
+    /// whether the condition held, or the value of the watched cell, at the last check
+    last: usize,
+}
+
+const HELP: &str = "\
+run                  restart the program
+continue             run until a breakpoint is hit
+step [n]             run n commands, 1 by default
+next                 like step, but runs a whole loop at `[`
+finish               run until the current loop exits
+break <line[:col]>   stop before the command at the location
+break ptr <op> <n>   stop when the data pointer comparison becomes true
+break cell <op> <n>  stop when the current cell comparison becomes true
+watch <cell>         stop after the value of the cell changes
+delete <id>          remove a breakpoint or watchpoint
+info                 list breakpoints and watchpoints
+print [a [b]]        print cells around the data pointer, cell a, or cells a to b
+list                 show the current source line
+quit                 exit
+<op> is one of == != < <= > >=";
+
+/// runs a program command by command, every `+` counts as one step, runs of the same
+/// command are never merged
+#[derive(Debug)]
+pub struct Debugger<'src_file, IO> {
+    src_file: std::borrow::Cow<'src_file, UcSourceFile>,
+    byte_codes: Vec<ByteCode>,
+    machine: Machine<IO>,
+    breakpoints: Vec<Armed>,
+    next_id: usize,
+}
+
+impl<IO: MachineIO> Debugger<'static, IO> {
+    /// like `new`, but takes `src_file` with it, errors are the messages of
+    /// `UcSourceFileError`
+    pub fn from_owned(src_file: UcSourceFile, mut machine: Machine<IO>) -> Result<Self, String> {
+        let byte_codes = src_file
+            .to_byte_codes_with(OptLevel::O0)
+            .map_err(|e| e.to_string())?;
+        machine.reset();
+        Ok(Self {
+            src_file: std::borrow::Cow::Owned(src_file),
+            byte_codes,
+            machine,
+            breakpoints: Vec::new(),
+            next_id: 1,
+        })
+    }
+}
+
+impl<'src_file, IO: MachineIO> Debugger<'src_file, IO> {
+    pub fn new(
+        src_file: &'src_file UcSourceFile,
+        mut machine: Machine<IO>,
+    ) -> Result<Self, UcSourceFileError<'src_file>> {
+        let byte_codes = src_file.to_byte_codes_with(OptLevel::O0)?;
+        machine.reset();
+        Ok(Self {
+            src_file: std::borrow::Cow::Borrowed(src_file),
+            byte_codes,
+            machine,
+            breakpoints: Vec::new(),
+            next_id: 1,
+        })
+    }
+
+    pub fn machine(&self) -> &Machine<IO> {
+        &self.machine
+    }
+
+    pub fn io_mut(&mut self) -> &mut IO {
+        self.machine.io_mut()
+    }
+
+    pub fn src_file(&self) -> &UcSourceFile {
+        &self.src_file
+    }
+
+    /// whether any command starts at `line`, or exactly at `line:col`
+    pub fn has_command_at(&self, line: usize, col: Option<usize>) -> bool {
+        self.byte_codes.iter().any(|bc| {
+            let pos = self.src_file.pos_of(bc.range_in_raw.0);
+            pos.line == line && col.is_none_or(|col| col == pos.col)
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.machine.instr_ptr() >= self.byte_codes.len()
+    }
+
+    /// source position of the next command to run, `None` once finished
+    pub fn position(&self) -> Option<SourcePos> {
+        self.byte_codes
+            .get(self.machine.instr_ptr())
+            .map(|bc| self.src_file.pos_of(bc.range_in_raw.0))
+    }
+
+    /// returns the breakpoint id
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        let last = self.current(&breakpoint);
+        self.breakpoints.push(Armed {
+            id,
+            breakpoint,
+            last,
+        });
+        id
+    }
+
+    /// returns `false` if there is no such breakpoint
+    pub fn delete_breakpoint(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.breakpoints.len() != len
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints.iter().map(|b| (b.id, &b.breakpoint))
+    }
+
+    /// restart from the beginning, breakpoints are kept
+    pub fn run(&mut self) -> Stop {
+        self.machine.reset();
+        self.rearm();
+        match self.hit_location() {
+            Some(id) => Stop::Breakpoint { id },
+            None => self.cont(),
+        }
+    }
+
+    pub fn cont(&mut self) -> Stop {
+        self.resume(Target::End)
+    }
+
+    /// `step(0)` runs nothing
+    pub fn step(&mut self, n: usize) -> Stop {
+        self.resume(Target::Steps(n))
+    }
+
+    pub fn step_over(&mut self) -> Stop {
+        self.resume(self.step_over_target())
+    }
+
+    /// `None` if the current command is not in a loop
+    pub fn finish(&mut self) -> Option<Stop> {
+        Some(self.resume(self.finish_target()?))
+    }
+
+    /// a whole loop at `[`, one command otherwise
+    pub fn step_over_target(&self) -> Target {
+        match self.byte_codes.get(self.machine.instr_ptr()) {
+            Some(bc) if bc.kind == ByteCodeKind::LoopStartJumpIfDataZero => {
+                Target::InstrPtr(bc.arg + 1)
+            }
+            _ => Target::Steps(1),
+        }
+    }
+
+    /// right after the innermost loop, `None` if the current command is not in a loop
+    pub fn finish_target(&self) -> Option<Target> {
+        let instr_ptr = self.machine.instr_ptr();
+        // innermost loop is the last `[` before here whose `]` is not before here
+        let after_loop = self.byte_codes[..instr_ptr.min(self.byte_codes.len())]
+            .iter()
+            .rev()
+            .find(|bc| bc.kind == ByteCodeKind::LoopStartJumpIfDataZero && bc.arg >= instr_ptr)?
+            .arg
+            + 1;
+        Some(Target::InstrPtr(after_loop))
+    }
+
+    /// runs at most `budget` commands towards `target`, `None` if the program has not
+    /// stopped yet. `target` is updated, so calling again with it carries on the same run
+    pub fn run_for(&mut self, target: &mut Target, budget: usize) -> Option<Stop> {
+        if *target == Target::Steps(0) {
+            return Some(Stop::Stepped);
+        }
+        self.rearm();
+        for _ in 0..budget {
+            match self.machine.step_byte_codes(&self.byte_codes) {
+                Ok(true) => (),
+                Ok(false) => return Some(Stop::Finished),
+                Err(e) => return Some(Stop::Error(e)),
+            }
+            if let Some(stop) = self.hit_watch_or_condition() {
+                return Some(stop);
+            }
+            if self.is_finished() {
+                return Some(Stop::Finished);
+            }
+            if let Some(id) = self.hit_location() {
+                return Some(Stop::Breakpoint { id });
+            }
+            let reached = match target {
+                Target::End => false,
+                Target::Steps(left) => {
+                    *left -= 1;
+                    *left == 0
+                }
+                Target::InstrPtr(instr_ptr) => self.machine.instr_ptr() == *instr_ptr,
+            };
+            if reached {
+                return Some(Stop::Stepped);
+            }
+        }
+        None
+    }
+
+    /// run one command line, and describe what happened
+    pub fn execute(&mut self, command: &Command) -> String {
+        let stop = match command {
+            Command::Run => self.run(),
+            Command::Continue => self.cont(),
+            Command::Step(n) => self.step(*n),
+            Command::Next => self.step_over(),
+            Command::Finish => match self.finish() {
+                Some(stop) => stop,
+                None => return String::from("not in a loop"),
+            },
+            Command::Break(Breakpoint::Location { line, col })
+                if !self.has_command_at(*line, *col) =>
+            {
+                return match col {
+                    None => format!("no command on line {}", line),
+                    Some(col) => format!("no command at {}:{}", line, col),
+                };
+            }
+            Command::Break(breakpoint) => {
+                let id = self.add_breakpoint(*breakpoint);
+                return format!("{} {}", id, breakpoint);
+            }
+            Command::Delete(id) => {
+                return if self.delete_breakpoint(*id) {
+                    format!("deleted {}", id)
+                } else {
+                    format!("no breakpoint {}", id)
+                };
+            }
+            Command::Info => {
+                let lines = self
+                    .breakpoints()
+                    .map(|(id, b)| format!("{} {}", id, b))
+                    .collect::<Vec<_>>();
+                return if lines.is_empty() {
+                    String::from("no breakpoints")
+                } else {
+                    lines.join("\n")
+                };
+            }
+            Command::Print { start, end } => return self.print(*start, *end),
+            Command::List => return self.list(),
+            Command::Help => return String::from(HELP),
+            Command::Quit => return String::new(),
+        };
+        self.describe(&stop)
+    }
+
+    fn describe(&self, stop: &Stop) -> String {
+        match stop {
+            Stop::Finished => format!("{}, {} commands executed", stop, self.machine.executed()),
+            Stop::Error(_) => stop.to_string(),
+            Stop::Stepped => self.list(),
+            _ => format!("{}\n{}", stop, self.list()),
+        }
+    }
+
+    fn print(&self, start: Option<usize>, end: Option<usize>) -> String {
+        let window = match (start, end) {
+            (None, _) => self.machine.tape_window(5),
+            (Some(start), None) => self.machine.tape_range(start..start + 1),
+            (Some(start), Some(end)) => self.machine.tape_range(start..end.saturating_add(1)),
+        };
+        if window.cells.is_empty() {
+            String::from("out of the tape")
+        } else {
+            window.to_string()
+        }
+    }
+
+    /// the current source line with a marker under the next command
+    pub fn list(&self) -> String {
+        let Some(pos) = self.position() else {
+            return String::from("program finished");
+        };
+        let text = self.src_file.line(pos.line).unwrap_or_default();
+        format!(
+            "{:>4} | {}\n     | {}^",
+            pos.line,
+            text,
+            " ".repeat(pos.col - 1)
+        )
+    }
+
+    fn resume(&mut self, mut target: Target) -> Stop {
+        loop {
+            if let Some(stop) = self.run_for(&mut target, usize::MAX) {
+                return stop;
+            }
+        }
+    }
+
+    fn current(&self, breakpoint: &Breakpoint) -> usize {
+        let cell = self
+            .machine
+            .cells()
+            .get(self.machine.data_ptr())
+            .copied()
+            .unwrap_or_default() as usize;
+        match breakpoint {
+            Breakpoint::Location { .. } => 0,
+            Breakpoint::Condition { subject, op, value } => {
+                let lhs = match subject {
+                    Subject::DataPtr => self.machine.data_ptr(),
+                    Subject::Cell => cell,
+                };
+                op.eval(lhs, *value) as usize
+            }
+            Breakpoint::Watch { cell } => {
+                self.machine.cells().get(*cell).copied().unwrap_or_default() as usize
+            }
+        }
+    }
+
+    fn rearm(&mut self) {
+        for i in 0..self.breakpoints.len() {
+            self.breakpoints[i].last = self.current(&self.breakpoints[i].breakpoint);
+        }
+    }
+
+    fn hit_watch_or_condition(&mut self) -> Option<Stop> {
+        let mut stop = None;
+        for i in 0..self.breakpoints.len() {
+            let now = self.current(&self.breakpoints[i].breakpoint);
+            let armed = &mut self.breakpoints[i];
+            let last = std::mem::replace(&mut armed.last, now);
+            if stop.is_some() || now == last {
+                continue;
+            }
+            stop = match armed.breakpoint {
+                Breakpoint::Condition { .. } if now == 1 => Some(Stop::Breakpoint { id: armed.id }),
+                Breakpoint::Watch { cell } => Some(Stop::Watchpoint {
+                    id: armed.id,
+                    cell,
+                    old: last as CellDataType,
+                    new: now as CellDataType,
+                }),
+                _ => None,
+            };
+        }
+        stop
+    }
+
+    fn hit_location(&self) -> Option<usize> {
+        let pos = self.position()?;
+        self.breakpoints
+            .iter()
+            .find(|b| match b.breakpoint {
+                Breakpoint::Location { line, col } => {
+                    line == pos.line && col.is_none_or(|col| col == pos.col)
+                }
+                _ => false,
+            })
+            .map(|b| b.id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::machine_io::BufferedMachineIO;
+
+    fn create_debugger(src_file: &UcSourceFile) -> Debugger<'_, BufferedMachineIO> {
+        Debugger::new(src_file, Machine::with_io(10, BufferedMachineIO::new(""))).unwrap()
+    }
+
+    fn pos(debugger: &Debugger<'_, BufferedMachineIO>) -> (usize, usize) {
+        let pos = debugger.position().unwrap();
+        (pos.line, pos.col)
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_small_value_enum(&CmpOp::Eq);
+        is_small_value_enum(&Subject::Cell);
+        is_small_value_enum(&Breakpoint::Watch { cell: 0 });
+        is_small_value_enum(&Command::Run);
+        is_small_value_enum(&Stop::Finished);
+        is_small_value_enum(&Target::End);
+        let src_file = UcSourceFile::from_str("", "");
+        is_debug(&create_debugger(&src_file));
+    }
+
+    #[test]
+    fn parse_commands() {
+        let test_data = [
+            ("s", Command::Step(1)),
+            ("step 3", Command::Step(3)),
+            (
+                "b 2",
+                Command::Break(Breakpoint::Location { line: 2, col: None }),
+            ),
+            (
+                "break 2:5",
+                Command::Break(Breakpoint::Location {
+                    line: 2,
+                    col: Some(5),
+                }),
+            ),
+            (
+                "b cell >= 3",
+                Command::Break(Breakpoint::Condition {
+                    subject: Subject::Cell,
+                    op: CmpOp::Ge,
+                    value: 3,
+                }),
+            ),
+            ("watch 6", Command::Break(Breakpoint::Watch { cell: 6 })),
+            (
+                " p 1 3 ",
+                Command::Print {
+                    start: Some(1),
+                    end: Some(3),
+                },
+            ),
+            (
+                "print",
+                Command::Print {
+                    start: None,
+                    end: None,
+                },
+            ),
+        ];
+        for (s, cmd) in test_data {
+            assert_eq!(s.parse(), Ok(cmd), "{}", s);
+        }
+        for s in [
+            "jump",
+            "b ptr ~ 3",
+            "b x:1",
+            "step x",
+            "step 0",
+            "c 1",
+            "p 1 2 3",
+        ] {
+            assert!(s.parse::<Command>().is_err(), "{}", s);
+        }
+        for op in CmpOp::all() {
+            assert_eq!(op.as_str().parse(), Ok(*op));
+        }
+    }
+
+    #[test]
+    fn step_next_and_finish() {
+        let src_file = UcSourceFile::from_str("++\n[>+[-]<-]\n>.", "");
+        let mut debugger = create_debugger(&src_file);
+        assert_eq!(pos(&debugger), (1, 1));
+        assert_eq!(debugger.step(0), Stop::Stepped);
+        assert_eq!(pos(&debugger), (1, 1));
+        assert_eq!(debugger.machine().executed(), 0);
+        assert_eq!(debugger.step(2), Stop::Stepped);
+        assert_eq!(pos(&debugger), (2, 1));
+
+        // over the whole loop
+        assert_eq!(debugger.step_over(), Stop::Stepped);
+        assert_eq!(pos(&debugger), (3, 1));
+        assert_eq!(debugger.machine().cells()[5..7], [0, 0]);
+
+        let mut debugger = create_debugger(&src_file);
+        assert_eq!(debugger.step(4), Stop::Stepped);
+        assert_eq!(pos(&debugger), (2, 3));
+        assert_eq!(debugger.step(2), Stop::Stepped);
+        assert_eq!(pos(&debugger), (2, 5));
+        // out of the inner loop
+        assert_eq!(debugger.finish(), Some(Stop::Stepped));
+        assert_eq!(pos(&debugger), (2, 7));
+        // out of the outer loop
+        assert_eq!(debugger.finish(), Some(Stop::Stepped));
+        assert_eq!(pos(&debugger), (3, 1));
+        assert_eq!(debugger.finish(), None);
+
+        assert_eq!(debugger.cont(), Stop::Finished);
+        assert!(debugger.is_finished());
+        assert_eq!(debugger.step(1), Stop::Finished);
+    }
+
+    #[test]
+    fn runs_in_slices() {
+        let src_file = UcSourceFile::from_str("+[]", "");
+        let mut debugger = create_debugger(&src_file);
+        let mut target = Target::End;
+        assert_eq!(debugger.run_for(&mut target, 100), None);
+        assert_eq!(debugger.run_for(&mut target, 100), None);
+        assert_eq!(debugger.machine().executed(), 200);
+
+        let src_file = UcSourceFile::from_str("+++[>+<-]>.", "");
+        let mut debugger = create_debugger(&src_file);
+        let mut target = Target::Steps(5);
+        assert_eq!(debugger.run_for(&mut target, 3), None);
+        assert_eq!(target, Target::Steps(2));
+        assert_eq!(debugger.run_for(&mut target, 3), Some(Stop::Stepped));
+        assert_eq!(debugger.machine().executed(), 5);
+        let mut target = debugger.finish_target().unwrap();
+        assert_eq!(target, Target::InstrPtr(9));
+        assert_eq!(debugger.run_for(&mut target, 4), None);
+        assert_eq!(debugger.run_for(&mut target, 100), Some(Stop::Stepped));
+        assert_eq!(pos(&debugger), (1, 10));
+        let mut target = Target::Steps(0);
+        assert_eq!(debugger.run_for(&mut target, 0), Some(Stop::Stepped));
+        assert_eq!(
+            debugger.run_for(&mut Target::End, 100),
+            Some(Stop::Finished)
+        );
+    }
+
+    #[test]
+    fn location_breakpoints() {
+        let src_file = UcSourceFile::from_str("+++[\n>+<-]", "");
+        let mut debugger = create_debugger(&src_file);
+        let id = debugger.add_breakpoint("2:2".parse().unwrap());
+        for _ in 0..3 {
+            assert_eq!(debugger.cont(), Stop::Breakpoint { id });
+            assert_eq!(pos(&debugger), (2, 2));
+        }
+        assert_eq!(debugger.machine().cells()[6], 2);
+        assert_eq!(debugger.cont(), Stop::Finished);
+
+        // stops before the first command on the line
+        assert!(debugger.delete_breakpoint(id));
+        assert!(!debugger.delete_breakpoint(id));
+        let id = debugger.add_breakpoint("1".parse().unwrap());
+        assert_eq!(debugger.run(), Stop::Breakpoint { id });
+        assert_eq!(pos(&debugger), (1, 1));
+        assert_eq!(debugger.cont(), Stop::Breakpoint { id });
+        assert_eq!(pos(&debugger), (1, 2));
+    }
+
+    #[test]
+    fn condition_breakpoints_stop_when_becoming_true() {
+        let src_file = UcSourceFile::from_str("+++++>>+<<[-]", "");
+        let mut debugger = create_debugger(&src_file);
+        let cell = debugger.add_breakpoint("cell == 3".parse().unwrap());
+        let ptr = debugger.add_breakpoint("ptr > 6".parse().unwrap());
+        assert_eq!(debugger.cont(), Stop::Breakpoint { id: cell });
+        assert_eq!(debugger.machine().executed(), 3);
+        assert_eq!(debugger.cont(), Stop::Breakpoint { id: ptr });
+        assert_eq!(debugger.machine().data_ptr(), 7);
+        // again on the way down in the loop
+        assert_eq!(debugger.cont(), Stop::Breakpoint { id: cell });
+        assert_eq!(debugger.machine().cells()[5], 3);
+        assert_eq!(debugger.cont(), Stop::Finished);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let src_file = UcSourceFile::from_str("+>++<>-", "");
+        let mut debugger = create_debugger(&src_file);
+        let id = debugger.add_breakpoint(Breakpoint::Watch { cell: 6 });
+        let changed = |old, new| Stop::Watchpoint {
+            id,
+            cell: 6,
+            old,
+            new,
+        };
+        assert_eq!(debugger.cont(), changed(0, 1));
+        assert_eq!(debugger.cont(), changed(1, 2));
+        assert_eq!(debugger.cont(), changed(2, 1));
+        assert_eq!(debugger.cont(), Stop::Finished);
+    }
+
+    #[test]
+    fn owned_source_file() {
+        let src_file = UcSourceFile::from_str("+\n>[", "");
+        let machine = Machine::with_io(10, BufferedMachineIO::new(""));
+        assert_eq!(
+            Debugger::from_owned(src_file, machine).unwrap_err(),
+            "1 unmatched paren(s): `[` at 2:2"
+        );
+
+        let src_file = UcSourceFile::from_str("+\n>.", "");
+        let machine = Machine::with_io(10, BufferedMachineIO::new(""));
+        let mut debugger = Debugger::from_owned(src_file, machine).unwrap();
+        assert!(debugger.has_command_at(2, None));
+        assert!(debugger.has_command_at(2, Some(2)));
+        assert!(!debugger.has_command_at(2, Some(3)));
+        assert!(!debugger.has_command_at(3, None));
+        assert_eq!(debugger.cont(), Stop::Finished);
+        assert_eq!(debugger.io_mut().take_output(), "\u{0}");
+    }
+
+    #[test]
+    fn runtime_errors_stop_the_program() {
+        let src_file = UcSourceFile::from_str("+[<+]", "");
+        let mut debugger = create_debugger(&src_file);
+        assert!(matches!(
+            debugger.cont(),
+            Stop::Error(RuntimeError::DataPtrOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn text_commands() {
+        let src_file = UcSourceFile::from_str("+[\n  ->+<]", "");
+        let mut debugger = create_debugger(&src_file);
+        let mut exec = |s: &str| debugger.execute(&s.parse().unwrap());
+        assert_eq!(exec("list"), "   1 | +[\n     | ^");
+        assert_eq!(exec("break 2:2"), "no command at 2:2");
+        assert_eq!(exec("break 3"), "no command on line 3");
+        assert_eq!(exec("break 2:4"), "1 breakpoint at 2:4");
+        assert_eq!(exec("watch 6"), "2 watchpoint on cell 6");
+        assert_eq!(exec("info"), "1 breakpoint at 2:4\n2 watchpoint on cell 6");
+        assert_eq!(exec("c"), "breakpoint 1 hit\n   2 |   ->+<]\n     |    ^");
+        assert_eq!(
+            exec("c"),
+            "watchpoint 2: cell 6 0 -> 1\n   2 |   ->+<]\n     |      ^"
+        );
+        assert_eq!(exec("p 5 6"), " 5 6\n 0 1\n   ^");
+        assert_eq!(exec("p 100"), "out of the tape");
+        assert_eq!(exec("d 2"), "deleted 2");
+        assert_eq!(exec("finish"), "program finished, 7 commands executed");
+        assert_eq!(exec("s"), "program finished, 7 commands executed");
+        assert_eq!(exec("n"), "program finished, 7 commands executed");
+        assert_eq!(exec("l"), "program finished");
+    }
+}
@@ -0,0 +1,390 @@
+//! runs one program on every engine with the same input and configuration, and reports
+//! where they disagree
+//!
+//! engines are compared on what they end with rather than step by step, as a merged `+++`
+//! is one step of byte codes but three of the source engine
+
+use crate::{
+    byte_code::OptLevel,
+    machine::{CellDataType, Engine, Machine, MachineConfig, MachineError, RuntimeError},
+    machine_io::BufferedMachineIO,
+    source_file::{UcSourceFile, UcSourceFileError},
+};
+
+/// an engine with one of its optimization levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Variant {
+    pub engine: Engine,
+    /// ignored by `Engine::Source`
+    pub opt_level: OptLevel,
+}
+
+impl Variant {
+    /// every engine with every optimization level it has, the source engine first
+    pub fn all() -> Vec<Self> {
+        let mut variants = Vec::new();
+        for &engine in Engine::all() {
+            match engine {
+                Engine::Source => variants.push(Self {
+                    engine,
+                    opt_level: OptLevel::default(),
+                }),
+                Engine::ByteCode | Engine::Closure => {
+                    for &opt_level in OptLevel::all() {
+                        variants.push(Self { engine, opt_level });
+                    }
+                }
+            }
+        }
+        variants
+    }
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.engine {
+            Engine::Source => write!(f, "{}", self.engine),
+            Engine::ByteCode | Engine::Closure => {
+                write!(f, "{} -O{}", self.engine, self.opt_level)
+            }
+        }
+    }
+}
+
+/// the state a variant stops with
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Outcome {
+    pub variant: Variant,
+    pub output: String,
+    pub cells: Vec<CellDataType>,
+    pub data_ptr: usize,
+    /// commands executed, the same as `Machine::executed`
+    pub steps: u64,
+    pub error: Option<RuntimeError>,
+}
+
+impl Outcome {
+    fn is_step_limited(&self) -> bool {
+        matches!(self.error, Some(RuntimeError::StepLimitExceeded { .. }))
+    }
+
+    /// errors without where they happen, which is numbered differently by each engine
+    fn error_kind(&self) -> Option<RuntimeError> {
+        self.error.map(|e| match e {
+            RuntimeError::DataPtrOutOfRange { data_ptr, .. } => RuntimeError::DataPtrOutOfRange {
+                instr_ptr: 0,
+                data_ptr,
+            },
+            RuntimeError::StepLimitExceeded { limit, .. } => RuntimeError::StepLimitExceeded {
+                instr_ptr: 0,
+                limit,
+            },
+            RuntimeError::InvalidProgram(e) => RuntimeError::InvalidProgram(e),
+        })
+    }
+
+    /// `None` if `other` agrees, or if neither finishes within the step limit, since they
+    /// stop at different points
+    pub fn first_divergence(&self, other: &Self) -> Option<Divergence> {
+        if self.is_step_limited() && other.is_step_limited() {
+            return None;
+        }
+        let mut expected = self.output.chars();
+        let mut actual = other.output.chars();
+        let mut offset = 0;
+        loop {
+            match (expected.next(), actual.next()) {
+                (None, None) => break,
+                (e, a) if e == a => offset += 1,
+                (expected, actual) => {
+                    return Some(Divergence::Output {
+                        offset,
+                        expected,
+                        actual,
+                    })
+                }
+            }
+        }
+        if self.error_kind() != other.error_kind() {
+            return Some(Divergence::Error {
+                expected: self.error,
+                actual: other.error,
+            });
+        }
+        if self.data_ptr != other.data_ptr {
+            return Some(Divergence::DataPtr {
+                expected: self.data_ptr,
+                actual: other.data_ptr,
+            });
+        }
+        if let Some(idx) = (0..self.cells.len().max(other.cells.len()))
+            .find(|&idx| self.cells.get(idx) != other.cells.get(idx))
+        {
+            return Some(Divergence::Cell {
+                idx,
+                expected: self.cells.get(idx).copied(),
+                actual: other.cells.get(idx).copied(),
+            });
+        }
+        if self.steps != other.steps {
+            return Some(Divergence::Steps {
+                expected: self.steps,
+                actual: other.steps,
+            });
+        }
+        None
+    }
+}
+
+/// the first thing another variant does differently, checked in the order of the variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Divergence {
+    /// `None` at the end of output
+    Output {
+        /// in chars
+        offset: usize,
+        expected: Option<char>,
+        actual: Option<char>,
+    },
+    Error {
+        expected: Option<RuntimeError>,
+        actual: Option<RuntimeError>,
+    },
+    DataPtr {
+        expected: usize,
+        actual: usize,
+    },
+    /// `None` out of the tape
+    Cell {
+        idx: usize,
+        expected: Option<CellDataType>,
+        actual: Option<CellDataType>,
+    },
+    Steps {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn char_or_end(c: &Option<char>) -> String {
+            c.map_or(String::from("end of output"), |c| {
+                format!("{:?} ({})", c, u32::from(c))
+            })
+        }
+        fn error_or_success(e: &Option<RuntimeError>) -> String {
+            e.map_or(String::from("success"), |e| e.to_string())
+        }
+        fn cell(v: &Option<CellDataType>) -> String {
+            v.map_or(String::from("no cell"), |v| v.to_string())
+        }
+        match self {
+            Self::Output {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "output differs at char {}, expecting {}, got {}",
+                offset,
+                char_or_end(expected),
+                char_or_end(actual)
+            ),
+            Self::Error { expected, actual } => write!(
+                f,
+                "ends differently, expecting {}, got {}",
+                error_or_success(expected),
+                error_or_success(actual)
+            ),
+            Self::DataPtr { expected, actual } => write!(
+                f,
+                "data pointer differs, expecting {}, got {}",
+                expected, actual
+            ),
+            Self::Cell {
+                idx,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "cell {} differs, expecting {}, got {}",
+                idx,
+                cell(expected),
+                cell(actual)
+            ),
+            Self::Steps { expected, actual } => write!(
+                f,
+                "executed commands differ, expecting {}, got {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+/// outcomes of all variants, the first one is the reference the others are compared with
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Comparison {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Comparison {
+    pub fn reference(&self) -> &Outcome {
+        &self.outcomes[0]
+    }
+
+    /// variants disagreeing with the reference
+    pub fn divergences(&self) -> Vec<(Variant, Divergence)> {
+        self.outcomes[1..]
+            .iter()
+            .filter_map(|o| self.reference().first_divergence(o).map(|d| (o.variant, d)))
+            .collect()
+    }
+
+    /// every variant stops at the step limit, so nothing can be compared
+    pub fn is_inconclusive(&self) -> bool {
+        self.outcomes.iter().all(Outcome::is_step_limited)
+    }
+}
+
+/// runs `src_file` on each of `Variant::all()`, input embedded in `src_file` is used
+/// instead of `input`
+pub fn compare<'src_file>(
+    src_file: &'src_file UcSourceFile,
+    config: MachineConfig,
+    input: &str,
+    step_limit: Option<u64>,
+) -> Result<Comparison, UcSourceFileError<'src_file>> {
+    src_file.check_parens()?;
+    let input = src_file.embedded_input().unwrap_or(input);
+
+    let mut outcomes = Vec::new();
+    for variant in Variant::all() {
+        let mut machine = Machine::with_config(config, BufferedMachineIO::new(input));
+        machine.set_step_limit(step_limit);
+        let error = match machine.eval(src_file, variant.engine, variant.opt_level) {
+            Ok(()) => None,
+            Err(MachineError::Runtime(e)) => Some(e),
+            Err(MachineError::Source(e)) => return Err(e),
+        };
+        outcomes.push(Outcome {
+            variant,
+            output: machine.io().output().to_owned(),
+            cells: machine.cells().to_vec(),
+            data_ptr: machine.data_ptr(),
+            steps: machine.executed(),
+            error,
+        });
+    }
+    Ok(Comparison { outcomes })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn outcome(output: &str) -> Outcome {
+        Outcome {
+            variant: Variant::all()[0],
+            output: output.to_owned(),
+            cells: vec![0; 4],
+            data_ptr: 2,
+            steps: 10,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_small_value_struct_but_no_default(&Variant::all()[0]);
+        is_big_value_struct_but_no_default(&outcome(""));
+        is_debug(&Divergence::Steps {
+            expected: 0,
+            actual: 0,
+        });
+    }
+
+    #[test]
+    fn variants() {
+        let names = Variant::all()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "source",
+                "byte-code -O0",
+                "byte-code -O1",
+                "closure -O0",
+                "closure -O1"
+            ]
+        );
+    }
+
+    #[test]
+    fn engines_agree() {
+        for src in ["++[->+<]>.", ",[.,]", "+[>+]", "+[]"] {
+            let src_file = UcSourceFile::from_str(src, "");
+            let config = MachineConfig {
+                tape_size: 8,
+                ..MachineConfig::default()
+            };
+            let comparison = compare(&src_file, config, "abc", Some(1000)).unwrap();
+            assert_eq!(comparison.outcomes.len(), Variant::all().len());
+            assert_eq!(comparison.divergences(), vec![], "{}", src);
+            assert_eq!(comparison.is_inconclusive(), src == "+[]");
+        }
+        let src_file = UcSourceFile::from_str("[", "");
+        assert!(compare(&src_file, MachineConfig::default(), "", None).is_err());
+    }
+
+    #[test]
+    fn first_divergence() {
+        let expected = outcome("abc");
+        assert_eq!(expected.first_divergence(&expected), None);
+
+        let d = expected.first_divergence(&outcome("ab")).unwrap();
+        assert_eq!(
+            d.to_string(),
+            "output differs at char 2, expecting 'c' (99), got end of output"
+        );
+
+        let mut actual = outcome("abc");
+        actual.cells[3] = 7;
+        actual.steps = 11;
+        assert_eq!(
+            expected.first_divergence(&actual).unwrap().to_string(),
+            "cell 3 differs, expecting 0, got 7"
+        );
+        actual.cells[3] = 0;
+        assert_eq!(
+            expected.first_divergence(&actual),
+            Some(Divergence::Steps {
+                expected: 10,
+                actual: 11
+            })
+        );
+
+        // where an error happens is numbered differently by each engine
+        let mut expected = outcome("");
+        let mut actual = outcome("");
+        expected.error = Some(RuntimeError::DataPtrOutOfRange {
+            instr_ptr: 3,
+            data_ptr: 4,
+        });
+        actual.error = Some(RuntimeError::DataPtrOutOfRange {
+            instr_ptr: 1,
+            data_ptr: 4,
+        });
+        assert_eq!(expected.first_divergence(&actual), None);
+        actual.error = None;
+        assert_eq!(
+            expected.first_divergence(&actual).unwrap().to_string(),
+            "ends differently, expecting data pointer 4 is out of the tape at instruction 3, \
+             got success"
+        );
+    }
+}
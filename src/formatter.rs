@@ -0,0 +1,242 @@
+use crate::source_file::{UcSourceFile, UcSourceFileError};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct FormatOptions {
+    /// spaces per loop nesting level
+    pub indent: usize,
+    /// code is wrapped once a line reaches this width, comments are never wrapped
+    pub line_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            line_width: 80,
+        }
+    }
+}
+
+/// rewrite `src_file` in the canonical style
+///
+/// - code is reflowed up to `line_width`, `[` ends a line and the loop body is indented one
+///   level deeper, `]` starts a line at the level of its `[`
+/// - comments are trimmed but kept on their own lines, or at the end of the code line they
+///   followed in the source
+/// - consecutive blank lines are collapsed into one
+///
+/// formatting a formatted source gives back the same source
+pub fn format<'src_file>(
+    src_file: &'src_file UcSourceFile,
+    options: &FormatOptions,
+) -> Result<String, UcSourceFileError<'src_file>> {
+    src_file.check_parens()?;
+
+    let mut w = Writer::new(options);
+    let mut comment = String::new();
+    for token in src_file {
+        if !token.is_command() {
+            comment.push_str(&token.uc);
+            continue;
+        }
+        w.comment(&comment);
+        comment.clear();
+        match token.uc.as_str() {
+            "[" => w.loop_start(),
+            "]" => w.loop_end(),
+            code => w.code(code),
+        }
+    }
+    w.comment(&comment);
+
+    Ok(w.finish())
+}
+
+#[derive(Debug)]
+struct Writer<'o> {
+    options: &'o FormatOptions,
+    lines: Vec<String>,
+    cur: String,
+    cur_width: usize,
+    cur_has_comment: bool,
+    depth: usize,
+}
+
+impl<'o> Writer<'o> {
+    fn new(options: &'o FormatOptions) -> Self {
+        Self {
+            options,
+            lines: Vec::new(),
+            cur: String::new(),
+            cur_width: 0,
+            cur_has_comment: false,
+            depth: 0,
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.cur.is_empty() {
+            let indent = " ".repeat(self.depth * self.options.indent);
+            self.lines.push(indent + &self.cur);
+            self.cur.clear();
+        }
+        self.cur_width = 0;
+        self.cur_has_comment = false;
+    }
+
+    fn blank_line(&mut self) {
+        self.flush();
+        if self.lines.last().is_some_and(|l| !l.is_empty()) {
+            self.lines.push(String::new());
+        }
+    }
+
+    fn code(&mut self, code: &str) {
+        let width = self.depth * self.options.indent + self.cur_width + 1;
+        if self.cur_has_comment || (self.cur_width > 0 && width > self.options.line_width) {
+            self.flush();
+        }
+        self.cur.push_str(code);
+        self.cur_width += 1;
+    }
+
+    fn loop_start(&mut self) {
+        self.code("[");
+        self.flush();
+        self.depth += 1;
+    }
+
+    fn loop_end(&mut self) {
+        self.flush();
+        self.depth -= 1;
+        self.code("]");
+    }
+
+    /// `comment` is a maximal run of non-command graphemes
+    fn comment(&mut self, comment: &str) {
+        if comment.trim().is_empty() {
+            // whitespaces only, code around them is reflowed
+            if comment.matches('\n').count() > 1 {
+                self.blank_line();
+            }
+            return;
+        }
+
+        let pieces = comment.split('\n').collect::<Vec<_>>();
+        for (i, piece) in pieces.iter().enumerate() {
+            let text = piece.trim();
+            if i > 0 {
+                // there is a line break in the source before this piece
+                self.flush();
+                if text.is_empty() && i + 1 < pieces.len() {
+                    self.blank_line();
+                }
+            }
+            if !text.is_empty() {
+                if !self.cur.is_empty() {
+                    self.cur.push(' ');
+                    self.cur_width += 1;
+                }
+                self.cur.push_str(text);
+                self.cur_width += text.graphemes(true).count();
+                self.cur_has_comment = true;
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        while self.lines.last().is_some_and(String::is_empty) {
+            self.lines.pop();
+        }
+        let mut s = self.lines.join("\n");
+        if !s.is_empty() {
+            s.push('\n');
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format_str(s: &str, options: &FormatOptions) -> String {
+        let src_file = UcSourceFile::from_str(s, "");
+        format(&src_file, options).unwrap()
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_default_debug(&FormatOptions::default());
+    }
+
+    #[test]
+    fn empty_source() {
+        assert_eq!(format_str("", &FormatOptions::default()), "");
+        assert_eq!(format_str(" \n\n ", &FormatOptions::default()), "");
+    }
+
+    #[test]
+    fn loops_are_indented() {
+        let formatted = format_str("++[>+ + [>++\n<-]<-]>.", &FormatOptions::default());
+        assert_eq!(formatted, "++[\n    >++[\n        >++<-\n    ]<-\n]>.\n");
+    }
+
+    #[test]
+    fn code_is_wrapped_at_line_width() {
+        let options = FormatOptions {
+            indent: 2,
+            line_width: 6,
+        };
+        let formatted = format_str("++++++++[>+++++++<-]", &options);
+        assert_eq!(formatted, "++++++\n++[\n  >+++\n  ++++\n  <-\n]\n");
+    }
+
+    #[test]
+    fn comments_are_preserved() {
+        let src = "set x +++   \n   to three\n\n\n\n[-] clear x\n.";
+        let formatted = format_str(src, &FormatOptions::default());
+        assert_eq!(
+            formatted,
+            "set x\n+++\nto three\n\n[\n    -\n] clear x\n.\n"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let options = FormatOptions {
+            indent: 3,
+            line_width: 30,
+        };
+        for entry in std::fs::read_dir("tests/artifacts").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|e| e != "bf") {
+                continue;
+            }
+            let src_file = UcSourceFile::new(&path).unwrap();
+            let once = format(&src_file, &options).unwrap();
+            assert_eq!(format_str(&once, &options), once, "src: {}", path.display());
+
+            // only whitespaces of comments can be changed
+            let commands = |s: &str| {
+                UcSourceFile::from_str(s, "")
+                    .iter()
+                    .filter(|t| t.is_command())
+                    .map(|t| t.uc.clone())
+                    .collect::<Vec<_>>()
+            };
+            let original = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(commands(&once), commands(&original));
+        }
+    }
+
+    #[test]
+    fn unmatched_parens_are_errors() {
+        let src_file = UcSourceFile::from_str("+[", "");
+        assert!(format(&src_file, &FormatOptions::default()).is_err());
+    }
+}
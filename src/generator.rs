@@ -0,0 +1,264 @@
+//! random programs that always terminate, for fuzzing and differential testing
+//!
+//! every loop counts down a cell of its own, which nothing else touches:
+//!
+//! ```text
+//! >>>>>>>>+++[-<<<<<<<< body >>>>>>>>]<<<<<<<<
+//! ```
+//!
+//! so a loop runs at most `max_iterations` times, however its body changes the data cells
+
+use crate::machine::MachineConfig;
+
+/// xorshift64, good enough to pick commands and reproducible from a seed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct XorShift64 {
+    state: u64,
+}
+
+impl XorShift64 {
+    /// a zero seed is replaced, as xorshift would only ever return zeros
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: if seed == 0 {
+                0x9E37_79B9_7F4A_7C15
+            } else {
+                seed
+            },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// in `0..n`, `n` must not be zero
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// true once in `n` times on average
+    pub fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct GeneratorOptions {
+    /// most pieces of a program, each is a run of `+` or `-`, a move, `.`, `,` or a loop
+    pub max_len: usize,
+    /// loops nested in loops, 0 for none
+    pub max_depth: usize,
+    /// most times a loop runs, at least 1
+    pub max_iterations: usize,
+    /// cells the program reads and writes, loop counters are to the right of them
+    pub data_cells: usize,
+    /// sprinkle comments, including multi code point graphemes, between commands, `#` and
+    /// `!` among them, which are only comments in `Dialect::Standard`
+    pub comments: bool,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        Self {
+            max_len: 200,
+            max_depth: 3,
+            max_iterations: 5,
+            data_cells: 8,
+            comments: false,
+        }
+    }
+}
+
+impl GeneratorOptions {
+    /// cells to the right of the initial data pointer a program can touch
+    pub fn cells_used(&self) -> usize {
+        self.data_cells + self.max_depth
+    }
+
+    /// the smallest tape programs run on without going out of it
+    pub fn min_tape_size(&self) -> usize {
+        self.cells_used() * 2 + 1
+    }
+
+    /// upper bound of commands a program executes, saturated
+    pub fn max_steps(&self) -> u64 {
+        // a loop with its moves to and from its counter is the longest
+        let per_item = 4 * self.cells_used() + self.max_iterations + 4;
+        let len = (self.max_len as u64).saturating_mul(per_item as u64);
+        // `[` runs once more than its body
+        (0..self.max_depth).fold(len, |steps, _| {
+            steps.saturating_mul(self.max_iterations as u64 + 1)
+        })
+    }
+
+    /// a configuration large enough for every program
+    pub fn config(&self) -> MachineConfig {
+        MachineConfig {
+            tape_size: self.min_tape_size(),
+            ..MachineConfig::default()
+        }
+    }
+}
+
+/// not commands, multi code point graphemes included
+const COMMENTS: &[&str] = &[
+    " ", "\n", "\r\n", "a", "#", "!", "é", "e\u{301}", "👍🏽", "🇨🇳",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Generator {
+    rng: XorShift64,
+    options: GeneratorOptions,
+}
+
+impl Generator {
+    pub fn new(seed: u64, options: GeneratorOptions) -> Self {
+        Self {
+            rng: XorShift64::new(seed),
+            options,
+        }
+    }
+
+    pub fn options(&self) -> &GeneratorOptions {
+        &self.options
+    }
+
+    /// a new program every call, with balanced parens and starting from data cell 0
+    pub fn program(&mut self) -> String {
+        let mut program = Program {
+            code: String::new(),
+            pos: 0,
+            budget: self.options.max_len,
+        };
+        self.block(&mut program, 0);
+        program.code
+    }
+
+    fn block(&mut self, p: &mut Program, depth: usize) {
+        // stop early now and then, so blocks vary in length
+        while p.budget > 0 && !self.rng.one_in(self.options.max_len.max(1) / 4 + 1) {
+            if self.options.comments && self.rng.one_in(4) {
+                p.code.push_str(COMMENTS[self.rng.below(COMMENTS.len())]);
+            }
+            p.budget -= 1;
+            match self.rng.below(10) {
+                0..=3 => {
+                    let c = if self.rng.one_in(2) { '+' } else { '-' };
+                    let n = 1 + self.rng.below(4);
+                    p.code.extend(std::iter::repeat_n(c, n));
+                }
+                4 | 5 => {
+                    let to = self.rng.below(self.options.data_cells.max(1));
+                    p.move_to(to);
+                }
+                6 => p.code.push('.'),
+                7 => p.code.push(','),
+                _ if depth < self.options.max_depth => self.bounded_loop(p, depth),
+                _ => p.code.push('.'),
+            }
+        }
+    }
+
+    fn bounded_loop(&mut self, p: &mut Program, depth: usize) {
+        let data = p.pos;
+        let counter = self.options.data_cells.max(1) + depth;
+        let iterations = self.rng.below(self.options.max_iterations.max(1)) + 1;
+
+        p.move_to(counter);
+        p.code.extend(std::iter::repeat_n('+', iterations));
+        p.code.push_str("[-");
+        p.move_to(data);
+        self.block(p, depth + 1);
+        p.move_to(counter);
+        p.code.push(']');
+        p.move_to(data);
+    }
+}
+
+/// a program being generated
+struct Program {
+    code: String,
+    /// cell the data pointer is on, relative to where it starts
+    pos: usize,
+    /// commands still to generate
+    budget: usize,
+}
+
+impl Program {
+    fn move_to(&mut self, to: usize) {
+        if to > self.pos {
+            self.code.extend(std::iter::repeat_n('>', to - self.pos));
+        } else {
+            self.code.extend(std::iter::repeat_n('<', self.pos - to));
+        }
+        self.pos = to;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{differential::compare, source_file::UcSourceFile};
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_small_value_struct_but_no_default(&XorShift64::new(1));
+        is_default_debug(&GeneratorOptions::default());
+        is_big_value_struct_but_no_default(&Generator::new(1, GeneratorOptions::default()));
+    }
+
+    #[test]
+    fn rng_is_reproducible() {
+        let mut a = XorShift64::new(42);
+        let mut b = XorShift64::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(XorShift64::new(0).next_u64(), 0);
+        assert!((0..100).all(|_| a.below(3) < 3));
+    }
+
+    #[test]
+    fn programs_terminate_and_engines_agree() {
+        let options = GeneratorOptions {
+            comments: true,
+            ..GeneratorOptions::default()
+        };
+        let mut generator = Generator::new(7, options);
+        let mut loops = 0;
+        for _ in 0..50 {
+            let program = generator.program();
+            loops += program.matches('[').count();
+
+            let src_file = UcSourceFile::from_str(&program, "");
+            let comparison = compare(
+                &src_file,
+                options.config(),
+                "some input",
+                Some(options.max_steps()),
+            )
+            .unwrap();
+            let reference = comparison.reference();
+            assert_eq!(reference.error, None, "{}", program);
+            assert_eq!(comparison.divergences(), vec![], "{}", program);
+        }
+        assert!(loops > 0);
+    }
+
+    #[test]
+    fn same_seed_same_programs() {
+        let options = GeneratorOptions::default();
+        let mut a = Generator::new(3, options);
+        let mut b = Generator::new(3, options);
+        assert_eq!(a.program(), b.program());
+        assert_ne!(a.program(), Generator::new(4, options).program());
+    }
+}
@@ -0,0 +1,456 @@
+//! golden tests, programs run on every engine and checked against their expected output
+//! and exit status, as listed in a json manifest like `tests/artifacts/oracles.json`
+//!
+//! ```json
+//! [
+//!     {
+//!         "src_file": "factor.bf",
+//!         "input": "factor.bf.in",
+//!         "output": "factor.bf.out",
+//!         "cell_width": 16,
+//!         "step_limit": 10000000
+//!     }
+//! ]
+//! ```
+
+use crate::{
+    differential::Variant,
+    machine::{CellWidth, EofBehavior, Machine, MachineConfig, MachineError},
+    machine_io::BufferedMachineIO,
+    source_file::{Dialect, UcSourceFile},
+};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// exit statuses of `bfi run`, which the manifest refers to, `bfi` exits with the same
+pub mod exit_status {
+    pub const SUCCESS: u8 = 0;
+    /// source file has unmatched parens, or a compiled program is corrupted
+    pub const SOURCE_ERROR: u8 = 4;
+    /// program fails while running, e.g. data pointer goes out of the tape or it goes over
+    /// the step limit
+    pub const RUNTIME_ERROR: u8 = 5;
+}
+
+/// one entry of a manifest, paths are relative to the manifest
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GoldenTest {
+    /// `src_file` if not given
+    pub name: Option<String>,
+    pub src_file: PathBuf,
+    /// nothing to read without one, unless input is embedded in the source
+    pub input: Option<PathBuf>,
+    /// output is not checked without one
+    pub output: Option<PathBuf>,
+    /// one of `exit_status`
+    #[serde(default)]
+    pub exit_status: u8,
+    pub tape_size: Option<usize>,
+    /// 8, 16 or 32
+    pub cell_width: Option<u32>,
+    /// zero, unchanged or max
+    pub eof: Option<String>,
+    /// standard or extended
+    pub dialect: Option<String>,
+    /// fail with a runtime error once more commands are executed
+    pub step_limit: Option<u64>,
+}
+
+impl GoldenTest {
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| self.src_file.display().to_string())
+    }
+
+    pub fn config(&self) -> Result<MachineConfig, String> {
+        let default = MachineConfig::default();
+        Ok(MachineConfig {
+            tape_size: self.tape_size.unwrap_or(default.tape_size),
+            cell_width: match self.cell_width {
+                Some(bits) => CellWidth::from_bits(bits).ok_or_else(|| {
+                    format!("unknown cell width `{}`, expecting 8, 16 or 32", bits)
+                })?,
+                None => default.cell_width,
+            },
+            eof: match &self.eof {
+                Some(eof) => eof.parse::<EofBehavior>()?,
+                None => default.eof,
+            },
+        })
+    }
+
+    pub fn dialect(&self) -> Result<Dialect, String> {
+        self.dialect
+            .as_deref()
+            .map_or(Ok(Dialect::default()), str::parse)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum ManifestError {
+    Read { path: PathBuf, reason: String },
+    Parse { path: PathBuf, reason: String },
+}
+
+impl std::fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read { path, reason } => {
+                write!(f, "failed to read {}, {}", path.display(), reason)
+            }
+            Self::Parse { path, reason } => {
+                write!(f, "failed to parse {}, {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+/// what goes wrong on some engines, or on all of them if the test cannot run at all
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Failure {
+    /// e.g. `source` and `byte-code -O1`, empty if the test cannot run
+    pub engines: Vec<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct TestReport {
+    pub name: String,
+    pub failures: Vec<Failure>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl std::fmt::Display for TestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "---- {} ----", self.name)?;
+        for failure in &self.failures {
+            if !failure.engines.is_empty() {
+                writeln!(f, "on {}:", failure.engines.join(", "))?;
+            }
+            writeln!(f, "{}", failure.message)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Manifest {
+    base_dir: PathBuf,
+    tests: Vec<GoldenTest>,
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ManifestError> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path).map_err(|e| ManifestError::Read {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        Self::from_json(&json, base_dir).map_err(|e| ManifestError::Parse {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// paths in `json` are relative to `base_dir`
+    pub fn from_json<P: AsRef<Path>>(json: &str, base_dir: P) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            base_dir: base_dir.as_ref().to_path_buf(),
+            tests: serde_json::from_str(json)?,
+        })
+    }
+
+    pub fn tests(&self) -> &[GoldenTest] {
+        &self.tests
+    }
+
+    pub fn path_of<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.base_dir.join(path)
+    }
+
+    pub fn source_file(&self, test: &GoldenTest) -> Result<UcSourceFile, String> {
+        let dialect = test.dialect()?;
+        UcSourceFile::new_with_dialect(self.path_of(&test.src_file), dialect)
+            .map_err(|e| e.to_string())
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>, String> {
+        let path = self.path_of(path);
+        std::fs::read(&path).map_err(|e| format!("failed to read {}, {}", path.display(), e))
+    }
+
+    /// input embedded in `src_file` wins, the input file is read byte by byte, the same
+    /// as `bfi run`
+    pub fn input(&self, test: &GoldenTest, src_file: &UcSourceFile) -> Result<String, String> {
+        match (src_file.embedded_input(), &test.input) {
+            (Some(input), _) => Ok(input.to_owned()),
+            (None, None) => Ok(String::new()),
+            (None, Some(path)) => Ok(self.read(path)?.into_iter().map(char::from).collect()),
+        }
+    }
+
+    pub fn expected_output(&self, test: &GoldenTest) -> Result<Option<String>, String> {
+        match &test.output {
+            None => Ok(None),
+            Some(path) => Ok(Some(
+                String::from_utf8_lossy(&self.read(path)?).into_owned(),
+            )),
+        }
+    }
+
+    /// `step_limit` applies to tests without their own
+    pub fn run(&self, test: &GoldenTest, step_limit: Option<u64>) -> TestReport {
+        match self.source_file(test) {
+            Ok(src_file) => self.run_source(test, &src_file, step_limit),
+            Err(message) => Self::broken(test, message),
+        }
+    }
+
+    /// runs `src_file` in place of the source file of `test`, e.g. a transformed one
+    pub fn run_source(
+        &self,
+        test: &GoldenTest,
+        src_file: &UcSourceFile,
+        step_limit: Option<u64>,
+    ) -> TestReport {
+        let prepared = test.config().and_then(|config| {
+            Ok((
+                config,
+                self.input(test, src_file)?,
+                self.expected_output(test)?,
+            ))
+        });
+        let (config, input, expected_output) = match prepared {
+            Ok(prepared) => prepared,
+            Err(message) => return Self::broken(test, message),
+        };
+
+        let mut failures = Vec::<Failure>::new();
+        for variant in Variant::all() {
+            let mut machine = Machine::with_config(config, BufferedMachineIO::new(&input));
+            machine.set_step_limit(test.step_limit.or(step_limit));
+            let result = machine.eval(src_file, variant.engine, variant.opt_level);
+
+            let mut problems = Vec::new();
+            let (status, error) = match &result {
+                Ok(()) => (exit_status::SUCCESS, String::new()),
+                Err(e @ MachineError::Source(_)) => (exit_status::SOURCE_ERROR, format!(", {}", e)),
+                Err(e @ MachineError::Runtime(_)) => {
+                    (exit_status::RUNTIME_ERROR, format!(", {}", e))
+                }
+            };
+            if status != test.exit_status {
+                problems.push(format!(
+                    "exit status {}, expecting {}{}",
+                    status, test.exit_status, error
+                ));
+            }
+            match &expected_output {
+                Some(expected) if expected != machine.io().output() => {
+                    problems.push(format!(
+                        "output differs, - expected, + actual\n{}",
+                        diff(expected, machine.io().output())
+                    ));
+                }
+                _ => (),
+            }
+            if problems.is_empty() {
+                continue;
+            }
+
+            let message = problems.join("\n");
+            let name = variant.to_string();
+            match failures.iter_mut().find(|f| f.message == message) {
+                Some(failure) => failure.engines.push(name),
+                None => failures.push(Failure {
+                    engines: vec![name],
+                    message,
+                }),
+            }
+        }
+        TestReport {
+            name: test.name(),
+            failures,
+        }
+    }
+
+    fn broken(test: &GoldenTest, message: String) -> TestReport {
+        TestReport {
+            name: test.name(),
+            failures: vec![Failure {
+                engines: Vec::new(),
+                message,
+            }],
+        }
+    }
+}
+
+/// equal lines around changes
+const DIFF_CONTEXT: usize = 2;
+/// above this, changed lines are not matched against each other
+const DIFF_MAX_CELLS: usize = 1_000_000;
+
+/// line diff with a few lines of context, lines without a line break at the end are
+/// marked with `⏎` missing
+fn diff(expected: &str, actual: &str) -> String {
+    let a = expected.split_inclusive('\n').collect::<Vec<_>>();
+    let b = actual.split_inclusive('\n').collect::<Vec<_>>();
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (da, db) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    // ' ', '-' or '+' of each line in the changed part
+    let mut ops = Vec::new();
+    if da.len() * db.len() > DIFF_MAX_CELLS {
+        ops.extend(da.iter().map(|l| ('-', *l)));
+        ops.extend(db.iter().map(|l| ('+', *l)));
+    } else {
+        // longest common subsequence of lines
+        let mut lcs = vec![vec![0usize; db.len() + 1]; da.len() + 1];
+        for i in (0..da.len()).rev() {
+            for j in (0..db.len()).rev() {
+                lcs[i][j] = if da[i] == db[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < da.len() || j < db.len() {
+            if i < da.len() && j < db.len() && da[i] == db[j] {
+                ops.push((' ', da[i]));
+                i += 1;
+                j += 1;
+            } else if j == db.len() || (i < da.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+                ops.push(('-', da[i]));
+                i += 1;
+            } else {
+                ops.push(('+', db[j]));
+                j += 1;
+            }
+        }
+    }
+
+    let mut s = String::new();
+    let mut push = |sign: char, line: &str| match line.strip_suffix('\n') {
+        Some(line) => s.push_str(&format!("{} {}\n", sign, line)),
+        None => s.push_str(&format!("{} {}⏎ missing\n", sign, line)),
+    };
+    if prefix > DIFF_CONTEXT {
+        push(' ', "...\n");
+    }
+    for line in &a[prefix.saturating_sub(DIFF_CONTEXT)..prefix] {
+        push(' ', line);
+    }
+    for (sign, line) in ops {
+        push(sign, line);
+    }
+    for line in &a[a.len() - suffix..][..suffix.min(DIFF_CONTEXT)] {
+        push(' ', line);
+    }
+    if suffix > DIFF_CONTEXT {
+        push(' ', "...\n");
+    }
+    s.pop();
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn manifest(json: &str) -> Manifest {
+        Manifest::from_json(json, "tests/artifacts").unwrap()
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_big_error(&ManifestError::Read {
+            path: PathBuf::new(),
+            reason: String::new(),
+        });
+        is_debug(&manifest("[]"));
+    }
+
+    #[test]
+    fn diffs() {
+        assert_eq!(diff("a\nb\nc\n", "a\nx\nc\n"), "  a\n- b\n+ x\n  c");
+        assert_eq!(
+            diff("1\n2\n3\n4\n5\n6\n", "1\n2\n3\n4\n6\n"),
+            "  ...\n  3\n  4\n- 5\n  6"
+        );
+        assert_eq!(diff("a\n", "a"), "- a\n+ a⏎ missing");
+        assert_eq!(diff("", "b\n"), "+ b");
+    }
+
+    #[test]
+    fn failures_are_grouped_by_engines() {
+        let manifest = manifest(
+            r#"[{"src_file": "hello_world_1.bf", "output": "print_12345.bf.out", "name": "wrong"},
+                {"src_file": "errors/endless.bf", "step_limit": 100}]"#,
+        );
+        let report = manifest.run(&manifest.tests()[0], None);
+        assert_eq!(report.name, "wrong");
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(
+            report.failures[0].engines,
+            vec![
+                "source",
+                "byte-code -O0",
+                "byte-code -O1",
+                "closure -O0",
+                "closure -O1"
+            ]
+        );
+        assert!(report.failures[0].message.starts_with("output differs"));
+
+        // engines stop at different instructions
+        let report = manifest.run(&manifest.tests()[1], None);
+        assert_eq!(report.name, "errors/endless.bf");
+        assert!(report.failures.len() > 1);
+        for failure in &report.failures {
+            assert!(failure
+                .message
+                .starts_with("exit status 5, expecting 0, executed more than 100 commands"));
+        }
+        // the manifest's own limit wins
+        assert_eq!(manifest.run(&manifest.tests()[1], Some(1)), report);
+    }
+
+    #[test]
+    fn broken_tests() {
+        let manifest = manifest(
+            r#"[{"src_file": "missing.bf"},
+                {"src_file": "hello_world_1.bf", "cell_width": 7},
+                {"src_file": "hello_world_1.bf", "input": "missing.in"}]"#,
+        );
+        for test in manifest.tests() {
+            let report = manifest.run(test, None);
+            assert_eq!(report.failures.len(), 1);
+            assert!(report.failures[0].engines.is_empty());
+        }
+        assert!(Manifest::from_json(r#"[{"src": "a.bf"}]"#, "").is_err());
+        assert_eq!(
+            Manifest::load("missing.json").unwrap_err().to_string(),
+            "failed to read missing.json, No such file or directory (os error 2)"
+        );
+    }
+}
@@ -3,10 +3,29 @@
 #![warn(missing_debug_implementations)]
 #![forbid(overflowing_literals)]
 
+pub mod assembly;
+pub mod bench;
 pub mod byte_code;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod differential;
+pub mod formatter;
+pub mod generator;
+pub mod golden;
+pub mod lint;
+pub mod lsp;
 pub mod machine;
 pub mod machine_io;
+pub mod minifier;
+pub mod object;
+pub mod observer;
+pub mod profiler;
+pub mod protocol;
+pub mod repl;
 pub mod source_file;
+pub mod source_map;
+pub mod trace;
 mod utility;
 
 #[doc = include_str!("../README.md")]
@@ -0,0 +1,354 @@
+use crate::{
+    byte_code::{ByteCode, ByteCodeKind},
+    machine::{CellDataType, CellWidth},
+    source_file::{RawContentIndex, SourcePos, UcSourceFile, UcSourceFileError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum LintCode {
+    /// `[]` or `[+-]`, never terminates once being entered
+    InfiniteLoop,
+    /// `][`, the cell is always zero right after a loop, so the second one is never entered
+    DeadLoop,
+    /// `><` or `<>`, pointer moves cancel each other out
+    CancelledMove,
+    /// more `+` or `-` in a row than the largest value of a cell, e.g. 255 for 8-bit cells,
+    /// wrap around
+    CellOverflow,
+    /// `,` whose value is overwritten before being used
+    UnusedInput,
+}
+
+impl LintCode {
+    pub fn all() -> &'static [LintCode] {
+        &[
+            Self::InfiniteLoop,
+            Self::DeadLoop,
+            Self::CancelledMove,
+            Self::CellOverflow,
+            Self::UnusedInput,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InfiniteLoop => "infinite-loop",
+            Self::DeadLoop => "dead-loop",
+            Self::CancelledMove => "cancelled-move",
+            Self::CellOverflow => "cell-overflow",
+            Self::UnusedInput => "unused-input",
+        }
+    }
+}
+
+impl std::fmt::Display for LintCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for LintCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::all()
+            .iter()
+            .find(|c| c.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown lint code `{}`", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct LintWarning {
+    code: LintCode,
+    start: SourcePos,
+    end: SourcePos,
+    range_in_raw: (RawContentIndex, RawContentIndex),
+}
+
+impl LintWarning {
+    pub fn code(&self) -> LintCode {
+        self.code
+    }
+
+    /// position of the first grapheme of the suspicious construct
+    pub fn start(&self) -> SourcePos {
+        self.start
+    }
+
+    /// position right after the last grapheme of the suspicious construct
+    pub fn end(&self) -> SourcePos {
+        self.end
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self.code {
+            LintCode::InfiniteLoop => "loop never terminates once entered",
+            LintCode::DeadLoop => "loop right after another loop is never entered",
+            LintCode::CancelledMove => "pointer moves cancel each other out",
+            LintCode::CellOverflow => "run of `+` or `-` wraps around the cell",
+            LintCode::UnusedInput => "input is overwritten before being used",
+        }
+    }
+}
+
+impl std::fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: warning[{}]: {}",
+            self.start,
+            self.code,
+            self.message()
+        )
+    }
+}
+
+/// lint `src_file` for cells of `cell_width`, warnings with codes in `allowed` are suppressed
+///
+/// warnings are ordered by their positions in the source file
+pub fn lint<'src_file>(
+    src_file: &'src_file UcSourceFile,
+    cell_width: CellWidth,
+    allowed: &[LintCode],
+) -> Result<Vec<LintWarning>, UcSourceFileError<'src_file>> {
+    let byte_codes = src_file.to_byte_codes()?;
+
+    // a loop at the very beginning is never entered, it is the idiom of comment blocks
+    let skipped = match byte_codes.first() {
+        Some(bc) if bc.kind == ByteCodeKind::LoopStartJumpIfDataZero => bc.arg + 1,
+        _ => 0,
+    };
+
+    let mut found = Vec::new();
+    for (idx, bc) in byte_codes.iter().enumerate().skip(skipped) {
+        let next = byte_codes.get(idx + 1);
+        match bc.kind {
+            ByteCodeKind::LoopStartJumpIfDataZero => {
+                if is_infinite_loop(&byte_codes[idx + 1..bc.arg], cell_width) {
+                    found.push((LintCode::InfiniteLoop, bc, &byte_codes[bc.arg]));
+                }
+                if idx > 0 && byte_codes[idx - 1].kind == ByteCodeKind::LoopEndJumpIfDataNotZero {
+                    found.push((LintCode::DeadLoop, bc, &byte_codes[bc.arg]));
+                }
+            }
+            ByteCodeKind::IncPtr | ByteCodeKind::DecPtr => {
+                if let Some(next) = next.filter(|next| is_opposite_move(bc.kind, next.kind)) {
+                    found.push((LintCode::CancelledMove, bc, next));
+                }
+            }
+            ByteCodeKind::IncData | ByteCodeKind::DecData => {
+                if bc.arg > cell_width.mask() as usize {
+                    found.push((LintCode::CellOverflow, bc, bc));
+                }
+            }
+            ByteCodeKind::Read => {
+                let overwritten = next.is_none_or(|next| next.kind == ByteCodeKind::Read);
+                if bc.arg > 1 || overwritten {
+                    found.push((LintCode::UnusedInput, bc, bc));
+                }
+            }
+            ByteCodeKind::Write
+            | ByteCodeKind::LoopEndJumpIfDataNotZero
+            | ByteCodeKind::DebugDump => (),
+        }
+    }
+
+    let mut warnings = found
+        .into_iter()
+        .filter(|(code, _, _)| !allowed.contains(code))
+        .map(|(code, first, last)| {
+            let range_in_raw = (first.range_in_raw.0, last.range_in_raw.1);
+            LintWarning {
+                code,
+                start: src_file.pos_of(range_in_raw.0),
+                end: src_file.pos_of(range_in_raw.1),
+                range_in_raw,
+            }
+        })
+        .collect::<Vec<_>>();
+    warnings.sort();
+    Ok(warnings)
+}
+
+/// loop body only changes the current cell, and the changes add up to zero in a cell of
+/// `cell_width`
+fn is_infinite_loop(body: &[ByteCode], cell_width: CellWidth) -> bool {
+    let mut delta: CellDataType = 0;
+    for bc in body {
+        match bc.kind {
+            ByteCodeKind::IncData => delta = delta.wrapping_add(bc.arg as CellDataType),
+            ByteCodeKind::DecData => delta = delta.wrapping_sub(bc.arg as CellDataType),
+            _ => return false,
+        }
+    }
+    delta & cell_width.mask() == 0
+}
+
+fn is_opposite_move(a: ByteCodeKind, b: ByteCodeKind) -> bool {
+    matches!(
+        (a, b),
+        (ByteCodeKind::IncPtr, ByteCodeKind::DecPtr) | (ByteCodeKind::DecPtr, ByteCodeKind::IncPtr)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// code, and (line, col) of start and end
+    type Found = (LintCode, (usize, usize), (usize, usize));
+
+    fn lint_str(s: &str) -> Vec<Found> {
+        let src_file = UcSourceFile::from_str(s, "");
+        lint(&src_file, CellWidth::U8, &[])
+            .unwrap()
+            .into_iter()
+            .map(|w| {
+                (
+                    w.code(),
+                    (w.start().line, w.start().col),
+                    (w.end().line, w.end().col),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_small_value_enum(&LintCode::DeadLoop);
+        is_big_value_struct_but_no_default(&LintWarning {
+            code: LintCode::DeadLoop,
+            start: SourcePos { line: 1, col: 1 },
+            end: SourcePos { line: 1, col: 1 },
+            range_in_raw: (RawContentIndex::new(0), RawContentIndex::new(0)),
+        });
+    }
+
+    #[test]
+    fn lint_code_round_trip() {
+        for code in LintCode::all() {
+            assert_eq!(code.as_str().parse::<LintCode>(), Ok(*code));
+        }
+        assert!("no-such-lint".parse::<LintCode>().is_err());
+    }
+
+    #[test]
+    fn clean_programs_have_no_warnings() {
+        for s in ["", "+[->+<]>.", "+++[>,.<-]", "[comment, at start]+."] {
+            assert_eq!(lint_str(s), vec![], "src: {}", s);
+        }
+    }
+
+    #[test]
+    fn leading_comment_loop_is_skipped() {
+        for s in ["[+-, <>]+.", "[[]]+[-]."] {
+            assert_eq!(lint_str(s), vec![], "src: {}", s);
+        }
+    }
+
+    #[test]
+    fn infinite_loops() {
+        assert_eq!(
+            lint_str("+[]"),
+            vec![(LintCode::InfiniteLoop, (1, 2), (1, 4))]
+        );
+        assert_eq!(
+            lint_str("+[+ -]"),
+            vec![(LintCode::InfiniteLoop, (1, 2), (1, 7))]
+        );
+        assert_eq!(lint_str("+[--]"), vec![]);
+    }
+
+    #[test]
+    fn dead_loops() {
+        assert_eq!(
+            lint_str("+[-]\n[.]"),
+            vec![(LintCode::DeadLoop, (2, 1), (2, 4))]
+        );
+        assert_eq!(lint_str("+[-]>[.]"), vec![]);
+    }
+
+    #[test]
+    fn cancelled_moves() {
+        assert_eq!(
+            lint_str("+>><."),
+            vec![(LintCode::CancelledMove, (1, 2), (1, 5))]
+        );
+        assert_eq!(
+            lint_str("<>"),
+            vec![(LintCode::CancelledMove, (1, 1), (1, 3))]
+        );
+        assert_eq!(lint_str(">+<"), vec![]);
+    }
+
+    #[test]
+    fn cell_overflows() {
+        assert_eq!(lint_str(&"+".repeat(255)), vec![]);
+        assert_eq!(
+            lint_str(&"-".repeat(256)),
+            vec![(LintCode::CellOverflow, (1, 1), (1, 257))]
+        );
+    }
+
+    #[test]
+    fn cells_wider_than_8_bits() {
+        let codes = |s: &str, cell_width| {
+            let src_file = UcSourceFile::from_str(s, "");
+            lint(&src_file, cell_width, &[])
+                .unwrap()
+                .iter()
+                .map(LintWarning::code)
+                .collect::<Vec<_>>()
+        };
+        let run = "+".repeat(256);
+        assert_eq!(codes(&run, CellWidth::U8), [LintCode::CellOverflow]);
+        assert_eq!(codes(&run, CellWidth::U16), []);
+        assert_eq!(
+            codes(&"+".repeat(65536), CellWidth::U16),
+            [LintCode::CellOverflow]
+        );
+
+        // 256 steps come back to where they started only in an 8-bit cell
+        let loop_ = format!("+[{}]", run);
+        assert_eq!(
+            codes(&loop_, CellWidth::U8),
+            [LintCode::InfiniteLoop, LintCode::CellOverflow]
+        );
+        assert_eq!(codes(&loop_, CellWidth::U32), []);
+    }
+
+    #[test]
+    fn unused_inputs() {
+        assert_eq!(lint_str(",."), vec![]);
+        assert_eq!(
+            lint_str(",,."),
+            vec![(LintCode::UnusedInput, (1, 1), (1, 3))]
+        );
+        assert_eq!(
+            lint_str(".,"),
+            vec![(LintCode::UnusedInput, (1, 2), (1, 3))]
+        );
+    }
+
+    #[test]
+    fn allowed_codes_are_suppressed() {
+        let src_file = UcSourceFile::from_str("+[]<>", "");
+        let warnings = lint(&src_file, CellWidth::U8, &[LintCode::InfiniteLoop]).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code(), LintCode::CancelledMove);
+        assert_eq!(
+            warnings[0].to_string(),
+            "1:4: warning[cancelled-move]: pointer moves cancel each other out"
+        );
+    }
+
+    #[test]
+    fn unmatched_parens_are_errors() {
+        let src_file = UcSourceFile::from_str("+[", "");
+        assert!(lint(&src_file, CellWidth::U8, &[]).is_err());
+    }
+}
@@ -0,0 +1,560 @@
+//! a language server speaking the Language Server Protocol over `Content-Length` framed
+//! JSON-RPC
+//!
+//! documents are fully synced, positions are in UTF-16 code units as the protocol
+//! requires, rather than in graphemes as `SourcePos`
+
+use crate::{
+    formatter::{format, FormatOptions},
+    protocol::{read_message, write_message},
+    source_file::{UcSourceFile, UcSourceFileError},
+    utility::{populate_loop_boundaries, ExtraParen},
+};
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// converts between byte offsets and LSP positions
+#[derive(Debug)]
+struct LineIndex<'a> {
+    raw: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(raw: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(raw.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self { raw, line_starts }
+    }
+
+    fn position(&self, offset: usize) -> Value {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.raw[self.line_starts[line]..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum::<usize>();
+        json!({"line": line, "character": character})
+    }
+
+    /// the offset of the character at `position`, `None` if there is no such character
+    fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()? as usize;
+        let character = position["character"].as_u64()? as usize;
+        let start = *self.line_starts.get(line)?;
+        let mut utf16 = 0;
+        for (idx, c) in self.raw[start..].char_indices() {
+            if utf16 == character && c != '\n' {
+                return Some(start + idx);
+            }
+            if utf16 > character || c == '\n' {
+                return None;
+            }
+            utf16 += c.len_utf16();
+        }
+        None
+    }
+
+    /// the range of the single byte `[` or `]` at `offset`
+    fn bracket_range(&self, offset: usize) -> Value {
+        json!({"start": self.position(offset), "end": self.position(offset + 1)})
+    }
+
+    fn end(&self) -> Value {
+        self.position(self.raw.len())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+struct Loop {
+    /// raw offset of `[`
+    open: usize,
+    /// raw offset of `]`
+    close: usize,
+    /// 1 for outermost loops
+    depth: usize,
+    /// `>` minus `<` of one iteration, `None` if an inner loop moves the pointer
+    ptr_delta: Option<isize>,
+}
+
+#[derive(Debug)]
+struct Document {
+    src_file: UcSourceFile,
+    /// in the order of `[`, empty if there are unmatched parens
+    loops: Vec<Loop>,
+}
+
+impl Document {
+    fn new(uri: &str, text: &str) -> Self {
+        let src_file = UcSourceFile::from_str(text, uri);
+        let loops = Self::loops(&src_file);
+        Self { src_file, loops }
+    }
+
+    fn loops(src_file: &UcSourceFile) -> Vec<Loop> {
+        let Ok(matches) = populate_loop_boundaries(src_file.iter()) else {
+            return Vec::new();
+        };
+        let tokens = src_file.iter().collect::<Vec<_>>();
+
+        let mut loops = Vec::new();
+        // indices into `loops` of the loops around the current token
+        let mut stack: Vec<usize> = Vec::new();
+        // pointer moves directly in each loop on the stack
+        let mut deltas: Vec<Option<isize>> = Vec::new();
+        for (idx, token) in tokens.iter().enumerate() {
+            match token.uc.as_str() {
+                "[" => {
+                    let close = tokens[matches.get_matching_end(idx)].idx_in_raw.get();
+                    stack.push(loops.len());
+                    deltas.push(Some(0));
+                    loops.push(Loop {
+                        open: token.idx_in_raw.get(),
+                        close,
+                        depth: stack.len(),
+                        ptr_delta: None,
+                    });
+                }
+                "]" => {
+                    let delta = deltas.pop().flatten();
+                    loops[stack.pop().unwrap()].ptr_delta = delta;
+                    if let Some(outer) = deltas.last_mut() {
+                        if delta != Some(0) {
+                            *outer = None;
+                        }
+                    }
+                }
+                ">" | "<" => {
+                    if let Some(Some(delta)) = deltas.last_mut() {
+                        *delta += if token.uc == ">" { 1 } else { -1 };
+                    }
+                }
+                _ => (),
+            }
+        }
+        loops
+    }
+
+    fn diagnostics(&self) -> Vec<Value> {
+        let index = LineIndex::new(self.src_file.raw_content());
+        let Err(UcSourceFileError::UnmatchedParen { details, .. }) = self.src_file.check_parens()
+        else {
+            return Vec::new();
+        };
+        let tokens = self.src_file.iter().collect::<Vec<_>>();
+        details
+            .iter()
+            .map(|e| {
+                let offset = tokens[e.idx()].idx_in_raw.get();
+                let message = match e {
+                    ExtraParen::Open { .. } => "unmatched `[`",
+                    ExtraParen::Close { .. } => "unmatched `]`",
+                };
+                json!({
+                    "range": index.bracket_range(offset),
+                    "severity": 1,
+                    "source": "brainfuck",
+                    "message": message,
+                })
+            })
+            .collect()
+    }
+
+    /// innermost loop whose brackets enclose `offset`, brackets included
+    fn loop_at(&self, offset: usize) -> Option<&Loop> {
+        self.loops
+            .iter()
+            .filter(|l| l.open <= offset && offset <= l.close)
+            .max_by_key(|l| l.depth)
+    }
+
+    fn definition(&self, uri: &str, offset: usize) -> Value {
+        let index = LineIndex::new(self.src_file.raw_content());
+        let target = self.loops.iter().find_map(|l| {
+            if l.open == offset {
+                Some(l.close)
+            } else if l.close == offset {
+                Some(l.open)
+            } else {
+                None
+            }
+        });
+        match target {
+            Some(target) => json!({"uri": uri, "range": index.bracket_range(target)}),
+            None => Value::Null,
+        }
+    }
+
+    fn hover(&self, offset: usize) -> Value {
+        let text = match self.loop_at(offset) {
+            None => String::from("loop nesting depth: 0"),
+            Some(l) => {
+                let delta = match l.ptr_delta {
+                    Some(delta) => format!("{:+}", delta),
+                    None => String::from("unknown, an inner loop moves the pointer"),
+                };
+                format!(
+                    "loop nesting depth: {}\n\nnet pointer delta per iteration: {}",
+                    l.depth, delta
+                )
+            }
+        };
+        json!({"contents": {"kind": "markdown", "value": text}})
+    }
+
+    fn folding_ranges(&self) -> Vec<Value> {
+        let index = LineIndex::new(self.src_file.raw_content());
+        self.loops
+            .iter()
+            .filter_map(|l| {
+                let start = index.position(l.open)["line"].as_u64()?;
+                let end = index.position(l.close)["line"].as_u64()?;
+                (start < end).then(|| json!({"startLine": start, "endLine": end}))
+            })
+            .collect()
+    }
+
+    /// `None` if it cannot be formatted
+    fn formatting(&self, options: &Value) -> Option<Value> {
+        let defaults = FormatOptions::default();
+        let options = FormatOptions {
+            indent: options["tabSize"]
+                .as_u64()
+                .map_or(defaults.indent, |n| n as usize),
+            ..defaults
+        };
+        let formatted = format(&self.src_file, &options).ok()?;
+        let index = LineIndex::new(self.src_file.raw_content());
+        let range = json!({"start": {"line": 0, "character": 0}, "end": index.end()});
+        Some(json!([{"range": range, "newText": formatted}]))
+    }
+}
+
+#[derive(Debug)]
+pub struct LspServer<W> {
+    out: W,
+    documents: std::collections::HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<W: Write> LspServer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            documents: std::collections::HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// handle messages from `input` until it is closed or `exit` is received, returns
+    /// whether `shutdown` has been requested before
+    pub fn serve<R: BufRead>(&mut self, input: &mut R) -> std::io::Result<bool> {
+        while let Some(msg) = read_message(input)? {
+            if msg["method"] == "exit" {
+                break;
+            }
+            self.handle(&msg)?;
+        }
+        Ok(self.shutdown)
+    }
+
+    fn respond(&mut self, id: &Value, result: Result<Value, (i64, String)>) -> std::io::Result<()> {
+        let mut msg = json!({"jsonrpc": "2.0", "id": id});
+        match result {
+            Ok(result) => msg["result"] = result,
+            Err((code, message)) => msg["error"] = json!({"code": code, "message": message}),
+        }
+        write_message(&mut self.out, &msg)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> std::io::Result<()> {
+        let msg = json!({"jsonrpc": "2.0", "method": method, "params": params});
+        write_message(&mut self.out, &msg)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> std::io::Result<()> {
+        let diagnostics = self
+            .documents
+            .get(uri)
+            .map(Document::diagnostics)
+            .unwrap_or_default();
+        let params = json!({"uri": uri, "diagnostics": diagnostics});
+        self.notify("textDocument/publishDiagnostics", params)
+    }
+
+    pub fn handle(&mut self, msg: &Value) -> std::io::Result<()> {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+
+        // notifications
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents
+                    .insert(uri.clone(), Document::new(&uri, text));
+                return self.publish_diagnostics(&uri);
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()?["text"].as_str()) {
+                    self.documents
+                        .insert(uri.clone(), Document::new(&uri, text));
+                }
+                return self.publish_diagnostics(&uri);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return self.publish_diagnostics(&uri);
+            }
+            _ => (),
+        }
+
+        let Some(id) = msg.get("id") else {
+            // other notifications, e.g. `initialized`
+            return Ok(());
+        };
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "foldingRangeProvider": true,
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": {"name": "bfls", "version": env!("CARGO_PKG_VERSION")},
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition"
+            | "textDocument/hover"
+            | "textDocument/foldingRange"
+            | "textDocument/formatting" => match self.documents.get(&uri) {
+                None => Err((INVALID_PARAMS, format!("unknown document `{}`", uri))),
+                Some(doc) => {
+                    let index = LineIndex::new(doc.src_file.raw_content());
+                    let offset = index.offset(&params["position"]);
+                    Ok(match method {
+                        "textDocument/definition" => {
+                            offset.map_or(Value::Null, |offset| doc.definition(&uri, offset))
+                        }
+                        "textDocument/hover" => {
+                            offset.map_or(Value::Null, |offset| doc.hover(offset))
+                        }
+                        "textDocument/foldingRange" => json!(doc.folding_ranges()),
+                        _ => doc.formatting(&params["options"]).unwrap_or(Value::Null),
+                    })
+                }
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method `{}`", method))),
+        };
+        let id = id.clone();
+        self.respond(&id, result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const URI: &str = "file:///a.bf";
+
+    /// runs `messages` and returns all messages sent back, and whether it was shut down
+    fn drive(messages: &[Value]) -> (Vec<Value>, bool) {
+        let mut input = Vec::new();
+        for msg in messages {
+            let mut msg = msg.clone();
+            msg["jsonrpc"] = json!("2.0");
+            write_message(&mut input, &msg).unwrap();
+        }
+        let mut output = Vec::new();
+        let shutdown = LspServer::new(&mut output)
+            .serve(&mut input.as_slice())
+            .unwrap();
+
+        let mut r = output.as_slice();
+        let mut replies = Vec::new();
+        while let Some(msg) = read_message(&mut r).unwrap() {
+            replies.push(msg);
+        }
+        (replies, shutdown)
+    }
+
+    fn open(text: &str) -> Value {
+        json!({"method": "textDocument/didOpen", "params": {"textDocument": {
+            "uri": URI, "languageId": "bf", "version": 1, "text": text,
+        }}})
+    }
+
+    fn request(id: u64, method: &str, line: u64, character: u64) -> Value {
+        json!({"id": id, "method": method, "params": {
+            "textDocument": {"uri": URI},
+            "position": {"line": line, "character": character},
+        }})
+    }
+
+    fn result(replies: &[Value], id: u64) -> &Value {
+        &replies.iter().find(|m| m["id"] == id).unwrap()["result"]
+    }
+
+    fn range(start: (u64, u64), end: (u64, u64)) -> Value {
+        json!({
+            "start": {"line": start.0, "character": start.1},
+            "end": {"line": end.0, "character": end.1},
+        })
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_debug(&LspServer::new(Vec::<u8>::new()));
+    }
+
+    #[test]
+    fn line_index_counts_utf16() {
+        let index = LineIndex::new("😀é[\n]");
+        assert_eq!(index.position(6), json!({"line": 0, "character": 3}));
+        assert_eq!(index.position(8), json!({"line": 1, "character": 0}));
+        assert_eq!(index.offset(&json!({"line": 0, "character": 3})), Some(6));
+        assert_eq!(index.offset(&json!({"line": 1, "character": 0})), Some(8));
+        // in the middle of a surrogate pair, past the end of the line, no such line
+        assert_eq!(index.offset(&json!({"line": 0, "character": 1})), None);
+        assert_eq!(index.offset(&json!({"line": 0, "character": 4})), None);
+        assert_eq!(index.offset(&json!({"line": 2, "character": 0})), None);
+    }
+
+    #[test]
+    fn lifecycle() {
+        let (replies, shutdown) = drive(&[
+            json!({"id": 1, "method": "initialize", "params": {"capabilities": {}}}),
+            json!({"method": "initialized", "params": {}}),
+            json!({"id": 2, "method": "workspace/symbol", "params": {}}),
+            json!({"id": 3, "method": "shutdown"}),
+            json!({"method": "exit"}),
+            json!({"id": 4, "method": "shutdown"}),
+        ]);
+        assert_eq!(replies.len(), 3);
+        let capabilities = &result(&replies, 1)["capabilities"];
+        assert_eq!(capabilities["textDocumentSync"], 1);
+        assert_eq!(capabilities["hoverProvider"], true);
+        assert_eq!(replies[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(
+            replies[2],
+            json!({"jsonrpc": "2.0", "id": 3, "result": null})
+        );
+        assert!(shutdown);
+    }
+
+    #[test]
+    fn bracket_diagnostics() {
+        let (replies, _) = drive(&[
+            open("]+\n[é["),
+            json!({"method": "textDocument/didChange", "params": {
+                "textDocument": {"uri": URI, "version": 2},
+                "contentChanges": [{"text": "[]"}],
+            }}),
+            json!({"method": "textDocument/didClose", "params": {"textDocument": {"uri": URI}}}),
+        ]);
+        let diagnostics = replies
+            .iter()
+            .map(|m| {
+                assert_eq!(m["method"], "textDocument/publishDiagnostics");
+                assert_eq!(m["params"]["uri"], URI);
+                m["params"]["diagnostics"].as_array().unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(diagnostics[0].len(), 3);
+        assert_eq!(diagnostics[0][0]["range"], range((0, 0), (0, 1)));
+        assert_eq!(diagnostics[0][0]["message"], "unmatched `]`");
+        assert_eq!(diagnostics[0][0]["severity"], 1);
+        assert_eq!(diagnostics[0][1]["range"], range((1, 0), (1, 1)));
+        assert_eq!(diagnostics[0][2]["range"], range((1, 2), (1, 3)));
+        assert_eq!(diagnostics[0][2]["message"], "unmatched `[`");
+        assert!(diagnostics[1].is_empty());
+        assert!(diagnostics[2].is_empty());
+    }
+
+    #[test]
+    fn go_to_matching_bracket() {
+        let (replies, _) = drive(&[
+            open("+[>[-]\n<]"),
+            request(1, "textDocument/definition", 0, 1),
+            request(2, "textDocument/definition", 1, 1),
+            request(3, "textDocument/definition", 0, 5),
+            request(4, "textDocument/definition", 0, 0),
+        ]);
+        assert_eq!(
+            result(&replies, 1),
+            &json!({"uri": URI, "range": range((1, 1), (1, 2))})
+        );
+        assert_eq!(result(&replies, 2)["range"], range((0, 1), (0, 2)));
+        assert_eq!(result(&replies, 3)["range"], range((0, 3), (0, 4)));
+        assert_eq!(result(&replies, 4), &Value::Null);
+    }
+
+    #[test]
+    fn hover_shows_depth_and_pointer_delta() {
+        let (replies, _) = drive(&[
+            open("+[>>[-]<]\n[>[>]<]."),
+            request(1, "textDocument/hover", 0, 5),
+            request(2, "textDocument/hover", 0, 2),
+            request(3, "textDocument/hover", 1, 0),
+            request(4, "textDocument/hover", 1, 7),
+        ]);
+        let text = |id| result(&replies, id)["contents"]["value"].clone();
+        assert_eq!(
+            text(1),
+            "loop nesting depth: 2\n\nnet pointer delta per iteration: +0"
+        );
+        assert_eq!(
+            text(2),
+            "loop nesting depth: 1\n\nnet pointer delta per iteration: +1"
+        );
+        assert_eq!(
+            text(3),
+            "loop nesting depth: 1\n\nnet pointer delta per iteration: unknown, an inner loop moves the pointer"
+        );
+        assert_eq!(text(4), "loop nesting depth: 0");
+    }
+
+    #[test]
+    fn folding_ranges_and_formatting() {
+        let (replies, _) = drive(&[
+            open("+[\n>[-]\n<]\n[-]"),
+            json!({"id": 1, "method": "textDocument/foldingRange", "params": {
+                "textDocument": {"uri": URI},
+            }}),
+            json!({"id": 2, "method": "textDocument/formatting", "params": {
+                "textDocument": {"uri": URI},
+                "options": {"tabSize": 2, "insertSpaces": true},
+            }}),
+            json!({"id": 3, "method": "textDocument/hover", "params": {
+                "textDocument": {"uri": "file:///unknown.bf"},
+                "position": {"line": 0, "character": 0},
+            }}),
+        ]);
+        assert_eq!(
+            result(&replies, 1),
+            &json!([{"startLine": 0, "endLine": 2}])
+        );
+        assert_eq!(
+            result(&replies, 2),
+            &json!([{
+                "range": range((0, 0), (3, 3)),
+                "newText": "+[\n  >[\n    -\n  ]<\n][\n  -\n]\n",
+            }])
+        );
+        assert_eq!(replies[3]["error"]["code"], INVALID_PARAMS);
+    }
+}
@@ -1,48 +1,369 @@
-use crate::byte_code::{ByteCode, ByteCodeKind};
-use crate::machine_io::{DefaultMachineIO, MachineIO};
-use crate::source_file::UcSourceFile;
+use crate::byte_code::{
+    ByteCode, ByteCodeKind, DenseByteCode, DenseByteCodes, OptLevel, ProgramError,
+};
+use crate::machine_io::{DebugSink, DefaultMachineIO, MachineIO};
+use crate::observer::{NoopObserver, Observer, Op};
+use crate::source_file::{Dialect, SourcePos, UcSourceFile, UcSourceFileError};
 use crate::utility::populate_loop_boundaries;
 
-type CellDataType = u8;
+mod closure;
+
+pub type CellDataType = u32;
+
+/// cells on each side of the data pointer in a `#` dump
+const DEBUG_DUMP_RADIUS: usize = 5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum CellWidth {
+    #[default]
+    U8,
+    U16,
+    U32,
+}
+
+impl CellWidth {
+    /// `None` unless `bits` is 8, 16 or 32
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            8 => Some(Self::U8),
+            16 => Some(Self::U16),
+            32 => Some(Self::U32),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::U8 => 8,
+            Self::U16 => 16,
+            Self::U32 => 32,
+        }
+    }
+
+    /// the max value a cell can hold, cell values wrap around with it
+    pub fn mask(&self) -> CellDataType {
+        CellDataType::MAX >> (CellDataType::BITS - self.bits())
+    }
+}
+
+impl std::fmt::Display for CellWidth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.bits())
+    }
+}
+
+impl std::str::FromStr for CellWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(Self::U8),
+            "16" => Ok(Self::U16),
+            "32" => Ok(Self::U32),
+            _ => Err(format!("unknown cell width `{}`, expecting 8, 16 or 32", s)),
+        }
+    }
+}
+
+/// what `,` stores in the cell when there is no more input
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum EofBehavior {
+    #[default]
+    Zero,
+    Unchanged,
+    /// all bits set, i.e. -1
+    MaxValue,
+}
+
+impl std::fmt::Display for EofBehavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Zero => f.write_str("zero"),
+            Self::Unchanged => f.write_str("unchanged"),
+            Self::MaxValue => f.write_str("max"),
+        }
+    }
+}
+
+impl std::str::FromStr for EofBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(Self::Zero),
+            "unchanged" => Ok(Self::Unchanged),
+            "max" => Ok(Self::MaxValue),
+            _ => Err(format!(
+                "unknown eof behavior `{}`, expecting zero, unchanged or max",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct MachineConfig {
+    /// number of cells, data pointer starts at the middle of the tape
+    pub tape_size: usize,
+    pub cell_width: CellWidth,
+    pub eof: EofBehavior,
+}
+
+impl MachineConfig {
+    /// the most cells a tape read from a file may have, 1 GiB of 32-bit cells
+    pub const MAX_TAPE_SIZE: usize = 1 << 28;
+
+    pub fn initial_data_ptr(&self) -> usize {
+        self.tape_size / 2
+    }
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            tape_size: 60_000,
+            cell_width: CellWidth::default(),
+            eof: EofBehavior::default(),
+        }
+    }
+}
+
+/// which interpreter runs the program
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Engine {
+    /// interpret unicode tokens directly
+    Source,
+    #[default]
+    ByteCode,
+    /// byte codes compiled into closures, which check the tape once for a run of them
+    Closure,
+}
+
+impl Engine {
+    pub fn all() -> &'static [Engine] {
+        &[Self::Source, Self::ByteCode, Self::Closure]
+    }
+
+    /// instruction pointers index byte codes rather than tokens
+    pub fn runs_byte_codes(&self) -> bool {
+        match self {
+            Self::Source => false,
+            Self::ByteCode | Self::Closure => true,
+        }
+    }
+
+    /// source position of each instruction pointer of this engine, `opt_level` only
+    /// matters to byte codes
+    pub fn locations<'src_file>(
+        &self,
+        src_file: &'src_file UcSourceFile,
+        opt_level: OptLevel,
+    ) -> Result<Vec<SourcePos>, UcSourceFileError<'src_file>> {
+        Ok(match self {
+            Self::Source => src_file
+                .iter()
+                .map(|token| src_file.pos_of(token.idx_in_raw))
+                .collect(),
+            Self::ByteCode | Self::Closure => src_file
+                .to_byte_codes_with(opt_level)?
+                .iter()
+                .map(|bc| src_file.pos_of(bc.range_in_raw.0))
+                .collect(),
+        })
+    }
+}
+
+impl std::fmt::Display for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Source => f.write_str("source"),
+            Self::ByteCode => f.write_str("byte-code"),
+            Self::Closure => f.write_str("closure"),
+        }
+    }
+}
+
+impl std::str::FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::all()
+            .iter()
+            .find(|e| e.to_string() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown engine `{}`", s))
+    }
+}
+
+/// errors can only be found when running the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum RuntimeError {
+    /// `data_ptr` is relative to the start of the tape, it is negative if it went off the left
+    DataPtrOutOfRange { instr_ptr: usize, data_ptr: isize },
+    /// more than `limit` commands are executed, `instr_ptr` is where it stops
+    StepLimitExceeded { instr_ptr: usize, limit: u64 },
+    /// byte codes `byte_code::validate` rejects, found before anything runs
+    InvalidProgram(ProgramError),
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DataPtrOutOfRange {
+                instr_ptr,
+                data_ptr,
+            } => write!(
+                f,
+                "data pointer {} is out of the tape at instruction {}",
+                data_ptr, instr_ptr
+            ),
+            Self::StepLimitExceeded { instr_ptr, limit } => write!(
+                f,
+                "executed more than {} commands, stopped at instruction {}",
+                limit, instr_ptr
+            ),
+            Self::InvalidProgram(e) => write!(f, "invalid program, {}", e),
+        }
+    }
+}
+
+impl RuntimeError {
+    /// index of the instruction it stops at, see `Engine::locations`
+    pub fn instr_ptr(&self) -> usize {
+        match self {
+            Self::DataPtrOutOfRange { instr_ptr, .. }
+            | Self::StepLimitExceeded { instr_ptr, .. } => *instr_ptr,
+            Self::InvalidProgram(e) => e.idx,
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum MachineError<'src_file> {
+    Source(UcSourceFileError<'src_file>),
+    Runtime(RuntimeError),
+}
+
+impl<'src_file> From<UcSourceFileError<'src_file>> for MachineError<'src_file> {
+    fn from(e: UcSourceFileError<'src_file>) -> Self {
+        Self::Source(e)
+    }
+}
+
+impl<'src_file> From<RuntimeError> for MachineError<'src_file> {
+    fn from(e: RuntimeError) -> Self {
+        Self::Runtime(e)
+    }
+}
+
+impl<'src_file> std::fmt::Display for MachineError<'src_file> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Source(e) => e.fmt(f),
+            Self::Runtime(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<'src_file> std::error::Error for MachineError<'src_file> {}
+
+/// a slice of the tape, printed as cell indices over cell values, with the data pointer
+/// marked by `^`
+///
+/// ```text
+///  29998 29999 30000 30001
+///      0     0    72     0
+///                  ^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct TapeWindow {
+    /// index of the first cell in the window
+    pub start: usize,
+    pub cells: Vec<CellDataType>,
+    /// can be out of the window, or even out of the tape after a runtime error
+    pub data_ptr: usize,
+}
+
+impl std::fmt::Display for TapeWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let columns = self
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(i, v)| ((self.start + i).to_string(), v.to_string()))
+            .collect::<Vec<_>>();
+        let widths = columns
+            .iter()
+            .map(|(i, v)| i.len().max(v.len()))
+            .collect::<Vec<_>>();
+
+        let mut indices = String::new();
+        let mut values = String::new();
+        let mut marker = String::new();
+        for (col, ((i, v), w)) in columns.iter().zip(&widths).enumerate() {
+            indices.push_str(&format!(" {:>w$}", i, w = w));
+            values.push_str(&format!(" {:>w$}", v, w = w));
+            let c = if self.start + col == self.data_ptr {
+                "^"
+            } else {
+                ""
+            };
+            marker.push_str(&format!(" {:>w$}", c, w = w));
+        }
+        writeln!(f, "{}", indices)?;
+        writeln!(f, "{}", values)?;
+        write!(f, "{}", marker.trim_end())
+    }
+}
 
 #[derive(Debug)]
-pub struct Machine<IO> {
+pub struct Machine<IO, O = NoopObserver> {
     cells: Vec<CellDataType>,
+    cell_mask: CellDataType,
+    eof: EofBehavior,
     data_ptr: usize,
     instr_ptr: usize,
+    /// number of commands executed, `+++` counts as three no matter how it is represented
+    executed: u64,
+    /// `u64::MAX` if there is no limit
+    step_limit: u64,
     io: IO,
-    #[cfg(feature = "instr_tracing")]
-    instr_tracing: crate::utility::tracing::InstructionTracingCollector,
-    #[cfg(feature = "instr_timing")]
-    instr_timing: crate::utility::timing::InstructionTimingCollector,
+    /// `#` is ignored without one
+    debug_sink: Option<Box<dyn DebugSink>>,
+    observer: O,
 }
 
 impl<IO: MachineIO> Machine<IO> {
-    #[cfg(any(feature = "instr_timing", feature = "instr_tracing"))]
-    const fn codes() -> &'static [&'static str] {
-        &[
-            "write",
-            "read",
-            "inc_ptr",
-            "dec_ptr",
-            "inc_data",
-            "dec_data",
-            "loop_start",
-            "loop_end",
-            "get_token",
-        ]
+    pub fn with_io(cell_size: usize, io: IO) -> Self {
+        Self::with_config(
+            MachineConfig {
+                tape_size: cell_size,
+                ..MachineConfig::default()
+            },
+            io,
+        )
     }
 
-    pub fn with_io(cell_size: usize, io: IO) -> Self {
+    pub fn with_config(config: MachineConfig, io: IO) -> Self {
+        Self::with_observer(config, io, NoopObserver)
+    }
+}
+
+impl<IO: MachineIO, O: Observer> Machine<IO, O> {
+    pub fn with_observer(config: MachineConfig, io: IO, observer: O) -> Self {
         Self {
-            cells: vec![0; cell_size],
-            data_ptr: Self::reset_data_ptr(cell_size),
+            cells: vec![0; config.tape_size],
+            cell_mask: config.cell_width.mask(),
+            eof: config.eof,
+            data_ptr: config.initial_data_ptr(),
             instr_ptr: Self::reset_instr_ptr(),
+            executed: 0,
+            step_limit: u64::MAX,
             io,
-            #[cfg(feature = "instr_tracing")]
-            instr_tracing: crate::utility::tracing::InstructionTracingCollector::new(Self::codes()),
-            #[cfg(feature = "instr_timing")]
-            instr_timing: crate::utility::timing::InstructionTimingCollector::new(Self::codes()),
+            debug_sink: None,
+            observer,
         }
     }
 
@@ -54,243 +375,650 @@ impl<IO: MachineIO> Machine<IO> {
         0
     }
 
-    fn reset(&mut self) {
+    /// clears the tape and moves both pointers back to where they start
+    pub fn reset(&mut self) {
         self.cells.iter_mut().for_each(|e| *e = 0);
         self.data_ptr = Self::reset_data_ptr(self.cells.len());
         self.instr_ptr = Self::reset_instr_ptr();
+        self.executed = 0;
         self.io.flush_all();
     }
 
-    /// . Output `arg` bytes at the data pointer.
-    fn write(&mut self, arg: usize) {
-        #[cfg(feature = "instr_tracing")]
-        self.instr_tracing.add("write");
+    /// number of commands executed by the last evaluation
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
 
-        #[cfg(feature = "instr_timing")]
-        let _t = self.instr_timing.start("write");
+    /// fail with `RuntimeError::StepLimitExceeded` once more than `limit` commands are
+    /// executed, `+++` counts as three on every engine
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit.unwrap_or(u64::MAX);
+    }
 
-        self.io
-            .out_char_n_times(*self.cells.get(self.data_ptr).unwrap() as char, arg);
-        self.instr_ptr += 1;
+    fn check_step_limit(&self) -> Result<(), RuntimeError> {
+        if self.executed > self.step_limit {
+            return Err(RuntimeError::StepLimitExceeded {
+                instr_ptr: self.instr_ptr,
+                limit: self.step_limit,
+            });
+        }
+        Ok(())
     }
 
-    /// , Accept one byte of input, storing its value in the byte at the data pointer.
-    fn read(&mut self, _arg: usize) {
-        #[cfg(feature = "instr_tracing")]
-        self.instr_tracing.add("read");
+    pub fn io(&self) -> &IO {
+        &self.io
+    }
 
-        #[cfg(feature = "instr_timing")]
-        let _t = self.instr_timing.start("read");
+    pub fn io_mut(&mut self) -> &mut IO {
+        &mut self.io
+    }
 
-        *self.cells.get_mut(self.data_ptr).unwrap() = self.io.in_char() as CellDataType;
-        self.instr_ptr += 1;
+    pub fn observer(&self) -> &O {
+        &self.observer
     }
 
-    /// > Increment the data pointer (to point to the next `arg` cells to the right).
-    fn inc_ptr(&mut self, arg: usize) {
-        #[cfg(feature = "instr_tracing")]
-        self.instr_tracing.add("inc_ptr");
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
 
-        #[cfg(feature = "instr_timing")]
-        let _t = self.instr_timing.start("inc_ptr");
+    pub fn into_observer(self) -> O {
+        self.observer
+    }
 
-        self.data_ptr = self.data_ptr.wrapping_add(arg);
+    pub fn set_debug_sink(&mut self, sink: Box<dyn DebugSink>) {
+        self.debug_sink = Some(sink);
+    }
+
+    pub fn data_ptr(&self) -> usize {
+        self.data_ptr
+    }
+
+    pub(crate) fn set_data_ptr(&mut self, data_ptr: usize) {
+        self.data_ptr = data_ptr;
+    }
+
+    pub fn cells(&self) -> &[CellDataType] {
+        &self.cells
+    }
+
+    /// cells within `radius` of the data pointer, clamped to the tape
+    pub fn tape_window(&self, radius: usize) -> TapeWindow {
+        let data_ptr = self.data_ptr.min(self.cells.len().saturating_sub(1));
+        self.tape_range(data_ptr.saturating_sub(radius)..data_ptr.saturating_add(radius + 1))
+    }
+
+    /// cells in `range`, clamped to the tape
+    pub fn tape_range(&self, range: std::ops::Range<usize>) -> TapeWindow {
+        let end = range.end.min(self.cells.len());
+        let start = range.start.min(end);
+        TapeWindow {
+            start,
+            cells: self.cells[start..end].to_vec(),
+            data_ptr: self.data_ptr,
+        }
+    }
+
+    /// index of the next instruction to be executed
+    pub fn instr_ptr(&self) -> usize {
+        self.instr_ptr
+    }
+
+    fn out_of_range(&self) -> RuntimeError {
+        RuntimeError::DataPtrOutOfRange {
+            instr_ptr: self.instr_ptr,
+            data_ptr: self.data_ptr as isize,
+        }
+    }
+
+    fn cell(&self) -> Result<CellDataType, RuntimeError> {
+        self.cells
+            .get(self.data_ptr)
+            .copied()
+            .ok_or_else(|| self.out_of_range())
+    }
+
+    fn set_cell(&mut self, v: CellDataType) -> Result<(), RuntimeError> {
+        let err = self.out_of_range();
+        let cell = self.cells.get_mut(self.data_ptr).ok_or(err)?;
+        let old = std::mem::replace(cell, v & self.cell_mask);
+        self.observer.on_cell_write(self.data_ptr, old, *cell);
+        Ok(())
+    }
+
+    /// . Output `arg` bytes at the data pointer.
+    fn write(&mut self, arg: usize) -> Result<(), RuntimeError> {
+        self.observer.before(Op::Write, arg, self.instr_ptr);
+        let c = char::from_u32(self.cell()?).unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.io.out_char_n_times(c, arg);
+        self.observer.on_output(c, arg);
+        self.executed += arg as u64;
         self.instr_ptr += 1;
+        self.observer.after(Op::Write);
+        Ok(())
     }
 
-    /// < Decrement the data pointer (to point to the previous `arg` cells to the left).
-    fn dec_ptr(&mut self, arg: usize) {
-        #[cfg(feature = "instr_tracing")]
-        self.instr_tracing.add("dec_ptr");
+    /// , Accept one byte of input, storing its value in the byte at the data pointer,
+    ///   `arg` times.
+    fn read(&mut self, arg: usize) -> Result<(), RuntimeError> {
+        self.observer.before(Op::Read, arg, self.instr_ptr);
+        for _ in 0..arg {
+            let c = self.io.in_char();
+            self.observer.on_input(c);
+            let v = match (c, self.eof) {
+                (Some(c), _) => c as CellDataType,
+                (None, EofBehavior::Zero) => 0,
+                (None, EofBehavior::Unchanged) => self.cell()?,
+                (None, EofBehavior::MaxValue) => CellDataType::MAX,
+            };
+            self.set_cell(v)?;
+        }
+        self.executed += arg as u64;
+        self.instr_ptr += 1;
+        self.observer.after(Op::Read);
+        Ok(())
+    }
 
-        #[cfg(feature = "instr_timing")]
-        let _t = self.instr_timing.start("dec_ptr");
+    /// > Increment the data pointer (to point to the next `arg` cells to the right).
+    fn inc_ptr(&mut self, arg: usize) -> Result<(), RuntimeError> {
+        self.observer.before(Op::IncPtr, arg, self.instr_ptr);
+        self.data_ptr = self.data_ptr.wrapping_add(arg);
+        self.executed += arg as u64;
+        self.instr_ptr += 1;
+        self.observer.after(Op::IncPtr);
+        Ok(())
+    }
 
+    /// < Decrement the data pointer (to point to the previous `arg` cells to the left).
+    fn dec_ptr(&mut self, arg: usize) -> Result<(), RuntimeError> {
+        self.observer.before(Op::DecPtr, arg, self.instr_ptr);
         self.data_ptr = self.data_ptr.wrapping_sub(arg);
+        self.executed += arg as u64;
         self.instr_ptr += 1;
+        self.observer.after(Op::DecPtr);
+        Ok(())
     }
 
     /// + Increment (increase by `arg`) the byte at the data pointer.
-    fn inc_data(&mut self, arg: usize) {
-        #[cfg(feature = "instr_tracing")]
-        self.instr_tracing.add("inc_data");
-
-        #[cfg(feature = "instr_timing")]
-        let _t = self.instr_timing.start("inc_data");
-
-        *self.cells.get_mut(self.data_ptr).unwrap() = self
-            .cells
-            .get(self.data_ptr)
-            .unwrap()
-            .wrapping_add(arg as CellDataType);
+    fn inc_data(&mut self, arg: usize) -> Result<(), RuntimeError> {
+        self.observer.before(Op::IncData, arg, self.instr_ptr);
+        self.set_cell(self.cell()?.wrapping_add(arg as CellDataType))?;
+        self.executed += arg as u64;
         self.instr_ptr += 1;
+        self.observer.after(Op::IncData);
+        Ok(())
     }
 
     /// - Decrement (decrease by `arg`) the byte at the data pointer.
-    fn dec_data(&mut self, arg: usize) {
-        #[cfg(feature = "instr_tracing")]
-        self.instr_tracing.add("dec_data");
-
-        #[cfg(feature = "instr_timing")]
-        let _t = self.instr_timing.start("dec_data");
+    fn dec_data(&mut self, arg: usize) -> Result<(), RuntimeError> {
+        self.observer.before(Op::DecData, arg, self.instr_ptr);
+        self.set_cell(self.cell()?.wrapping_sub(arg as CellDataType))?;
+        self.executed += arg as u64;
+        self.instr_ptr += 1;
+        self.observer.after(Op::DecData);
+        Ok(())
+    }
 
-        *self.cells.get_mut(self.data_ptr).unwrap() = self
-            .cells
-            .get(self.data_ptr)
-            .unwrap()
-            .wrapping_sub(arg as CellDataType);
+    /// # Dump cells around the data pointer to the debug sink.
+    fn debug_dump(&mut self) -> Result<(), RuntimeError> {
+        self.observer.before(Op::DebugDump, 1, self.instr_ptr);
+        if let Some(mut sink) = self.debug_sink.take() {
+            sink.dump(&self.tape_window(DEBUG_DUMP_RADIUS));
+            self.debug_sink = Some(sink);
+        }
+        self.executed += 1;
         self.instr_ptr += 1;
+        self.observer.after(Op::DebugDump);
+        Ok(())
     }
 
     /// [ If the byte at the data pointer is zero, then instead of moving
     ///    the instruction pointer forward to the next command, jump it
     ///    forward to the command after the matching ] command.
-    fn loop_start_jump_if_data_zero(&mut self, end_ptr: usize) {
-        #[cfg(feature = "instr_tracing")]
-        self.instr_tracing.add("loop_start");
-
-        #[cfg(feature = "instr_timing")]
-        let _t = self.instr_timing.start("loop_start");
-
-        if *self.cells.get(self.data_ptr).unwrap() == 0 {
+    fn loop_start_jump_if_data_zero(&mut self, end_ptr: usize) -> Result<(), RuntimeError> {
+        self.observer.before(Op::LoopStart, 1, self.instr_ptr);
+        if self.cell()? == 0 {
             self.instr_ptr = end_ptr + 1;
         } else {
+            self.observer.on_loop_entry(self.instr_ptr);
             self.instr_ptr += 1;
         }
+        self.executed += 1;
+        self.observer.after(Op::LoopStart);
+        Ok(())
     }
 
     /// ] If the byte at the data pointer is nonzero, then instead of
     ///   moving the instruction pointer forward to the next command,
     ///   jump it back to the command after the matching [ command.
-    fn loop_end_jump_if_data_not_zero(&mut self, start_ptr: usize) {
-        #[cfg(feature = "instr_tracing")]
-        self.instr_tracing.add("loop_end");
-
-        #[cfg(feature = "instr_timing")]
-        let _t = self.instr_timing.start("loop_end");
-
-        if *self.cells.get(self.data_ptr).unwrap() != 0 {
+    fn loop_end_jump_if_data_not_zero(&mut self, start_ptr: usize) -> Result<(), RuntimeError> {
+        self.observer.before(Op::LoopEnd, 1, self.instr_ptr);
+        if self.cell()? != 0 {
             self.instr_ptr = start_ptr;
         } else {
+            self.observer.on_loop_exit(self.instr_ptr);
             self.instr_ptr += 1;
         }
+        self.executed += 1;
+        self.observer.after(Op::LoopEnd);
+        Ok(())
+    }
+
+    /// runs `src_file` with `engine`, `opt_level` only matters to byte codes
+    pub fn eval<'src_file>(
+        &mut self,
+        src_file: &'src_file UcSourceFile,
+        engine: Engine,
+        opt_level: OptLevel,
+    ) -> Result<(), MachineError<'src_file>> {
+        match engine {
+            Engine::Source => self.eval_source_file(src_file),
+            Engine::ByteCode => {
+                let byte_codes = src_file.to_byte_codes_with(opt_level)?;
+                Ok(self.eval_dense_byte_codes(&DenseByteCodes::from(byte_codes.as_slice()))?)
+            }
+            Engine::Closure => {
+                let byte_codes = src_file.to_byte_codes_with(opt_level)?;
+                Ok(self.eval_closures(&byte_codes)?)
+            }
+        }
     }
 
-    pub fn eval_source_file(&mut self, src_file: &UcSourceFile) {
+    pub fn eval_source_file<'src_file>(
+        &mut self,
+        src_file: &'src_file UcSourceFile,
+    ) -> Result<(), MachineError<'src_file>> {
         self.reset();
 
-        let loop_matches = populate_loop_boundaries(src_file.iter()).unwrap();
+        let loop_matches = populate_loop_boundaries(src_file.iter()).map_err(|e| {
+            UcSourceFileError::UnmatchedParen {
+                src_file,
+                details: e,
+            }
+        })?;
 
+        let extended = src_file.dialect() == Dialect::Extended;
         while self.instr_ptr < src_file.len() {
-            match {
-                #[cfg(feature = "instr_tracing")]
-                self.instr_tracing.add("get_token");
-
-                #[cfg(feature = "instr_timing")]
-                let _t = self.instr_timing.start("get_token");
-
-                src_file.at_instr_ptr(self.instr_ptr).uc.as_str()
-            } {
-                "." => self.write(1),
-                "," => self.read(1),
-                ">" => self.inc_ptr(1),
-                "<" => self.dec_ptr(1),
-                "+" => self.inc_data(1),
-                "-" => self.dec_data(1),
-                "[" => {
-                    self.loop_start_jump_if_data_zero(loop_matches.get_matching_end(self.instr_ptr))
-                }
+            match src_file.at_instr_ptr(self.instr_ptr).uc.as_str() {
+                "." => self.write(1)?,
+                "," => self.read(1)?,
+                ">" => self.inc_ptr(1)?,
+                "<" => self.dec_ptr(1)?,
+                "+" => self.inc_data(1)?,
+                "-" => self.dec_data(1)?,
+                "[" => self
+                    .loop_start_jump_if_data_zero(loop_matches.get_matching_end(self.instr_ptr))?,
                 "]" => self.loop_end_jump_if_data_not_zero(
                     loop_matches.get_matching_start(self.instr_ptr),
-                ),
+                )?,
+                "#" if extended => self.debug_dump()?,
                 _ => self.instr_ptr += 1,
             }
+            self.check_step_limit()?;
         }
 
-        #[cfg(feature = "instr_timing")]
-        eprintln!("{}", self.instr_timing.finalize_to_string());
+        Ok(())
+    }
 
-        #[cfg(feature = "instr_tracing")]
-        eprintln!("{}", self.instr_tracing.finalize_to_string());
+    /// converts `byte_codes` to `DenseByteCodes` and runs them
+    pub fn eval_byte_codes(&mut self, byte_codes: &[ByteCode]) -> Result<(), RuntimeError> {
+        self.eval_dense_byte_codes(&DenseByteCodes::from(byte_codes))
     }
 
-    pub fn eval_byte_codes(&mut self, byte_codes: &[ByteCode]) {
+    /// fails with `RuntimeError::InvalidProgram` before running anything if `byte_codes`
+    /// do not pass `DenseByteCodes::validate`
+    pub fn eval_dense_byte_codes(
+        &mut self,
+        byte_codes: &DenseByteCodes,
+    ) -> Result<(), RuntimeError> {
+        byte_codes
+            .validate()
+            .map_err(RuntimeError::InvalidProgram)?;
         self.reset();
+        self.run_byte_codes(byte_codes.codes())
+    }
 
-        while self.instr_ptr < byte_codes.len() {
-            match {
-                #[cfg(feature = "instr_tracing")]
-                self.instr_tracing.add("get_token");
-
-                #[cfg(feature = "instr_timing")]
-                let _t = self.instr_timing.start("get_token");
-
-                &byte_codes[self.instr_ptr]
-            } {
-                ByteCode {
-                    kind: ByteCodeKind::Write,
-                    arg,
-                    ..
-                } => self.write(*arg),
-                ByteCode {
-                    kind: ByteCodeKind::Read,
-                    arg,
-                    ..
-                } => self.read(*arg),
-                ByteCode {
-                    kind: ByteCodeKind::IncPtr,
-                    arg,
-                    ..
-                } => self.inc_ptr(*arg),
-                ByteCode {
-                    kind: ByteCodeKind::DecPtr,
-                    arg,
-                    ..
-                } => self.dec_ptr(*arg),
-                ByteCode {
-                    kind: ByteCodeKind::IncData,
-                    arg,
-                    ..
-                } => self.inc_data(*arg),
-                ByteCode {
-                    kind: ByteCodeKind::DecData,
-                    arg,
-                    ..
-                } => self.dec_data(*arg),
-                ByteCode {
-                    kind: ByteCodeKind::LoopStartJumpIfDataZero,
-                    arg,
-                    ..
-                } => self.loop_start_jump_if_data_zero(*arg),
-                ByteCode {
-                    kind: ByteCodeKind::LoopEndJumpIfDataNotZero,
-                    arg,
-                    ..
-                } => self.loop_end_jump_if_data_not_zero(*arg),
-            }
+    /// runs `byte_codes` against the current tape and data pointer, nothing is reset but
+    /// the instruction pointer
+    pub fn continue_byte_codes(&mut self, byte_codes: &[ByteCode]) -> Result<(), RuntimeError> {
+        let byte_codes = DenseByteCodes::from(byte_codes);
+        byte_codes
+            .validate()
+            .map_err(RuntimeError::InvalidProgram)?;
+        self.instr_ptr = Self::reset_instr_ptr();
+        self.run_byte_codes(byte_codes.codes())
+    }
+
+    fn run_byte_codes(&mut self, byte_codes: &[DenseByteCode]) -> Result<(), RuntimeError> {
+        while let Some(&byte_code) = byte_codes.get(self.instr_ptr) {
+            self.exec_byte_code(byte_code)?;
+            self.check_step_limit()?;
         }
 
-        #[cfg(feature = "instr_timing")]
-        eprintln!("{}", self.instr_timing.finalize_to_string());
+        Ok(())
+    }
+
+    /// executes the byte code at the instruction pointer against the current tape,
+    /// returns `false` without doing anything if the program has already finished
+    ///
+    /// `byte_codes` are not validated, which would cost a pass over them on every step
+    pub fn step_byte_codes(&mut self, byte_codes: &[ByteCode]) -> Result<bool, RuntimeError> {
+        match byte_codes.get(self.instr_ptr) {
+            Some(byte_code) => {
+                self.exec_byte_code(byte_code.to_dense())?;
+                self.check_step_limit()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
 
-        #[cfg(feature = "instr_tracing")]
-        eprintln!("{}", self.instr_tracing.finalize_to_string());
+    fn exec_byte_code(&mut self, byte_code: DenseByteCode) -> Result<(), RuntimeError> {
+        let arg = byte_code.arg as usize;
+        match byte_code.kind {
+            ByteCodeKind::Write => self.write(arg),
+            ByteCodeKind::Read => self.read(arg),
+            ByteCodeKind::IncPtr => self.inc_ptr(arg),
+            ByteCodeKind::DecPtr => self.dec_ptr(arg),
+            ByteCodeKind::IncData => self.inc_data(arg),
+            ByteCodeKind::DecData => self.dec_data(arg),
+            ByteCodeKind::LoopStartJumpIfDataZero => self.loop_start_jump_if_data_zero(arg),
+            ByteCodeKind::LoopEndJumpIfDataNotZero => self.loop_end_jump_if_data_not_zero(arg),
+            ByteCodeKind::DebugDump => self.debug_dump(),
+        }
     }
 }
 
 pub fn create_default_machine() -> Machine<DefaultMachineIO> {
     let io = DefaultMachineIO::new();
-    Machine::<DefaultMachineIO>::with_io(60_000, io)
+    Machine::<DefaultMachineIO>::with_config(MachineConfig::default(), io)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::machine_io::{BufferedMachineIO, EmbeddedInputIO};
 
     #[test]
     fn traits() {
         use crate::utility::traits::*;
 
         is_debug(&create_default_machine());
+        is_small_value_enum(&CellWidth::U8);
+        is_small_value_enum(&EofBehavior::Zero);
+        is_small_value_enum(&Engine::Source);
+        is_small_value_enum(&RuntimeError::DataPtrOutOfRange {
+            instr_ptr: 0,
+            data_ptr: 0,
+        });
+        is_default_debug(&MachineConfig::default());
+        is_big_value_struct_but_no_default(&create_default_machine().tape_window(1));
+    }
+
+    #[test]
+    fn tape_window_around_data_ptr() {
+        let mut machine = Machine::with_io(12, BufferedMachineIO::new(""));
+        let src_file = UcSourceFile::from_str(">>>>++++++++++<+<", "");
+        machine.eval_source_file(&src_file).unwrap();
+        assert_eq!(machine.data_ptr(), 8);
+        assert_eq!(machine.cells()[9..11], [1, 10]);
+
+        let window = machine.tape_window(2);
+        assert_eq!(window.start, 6);
+        assert_eq!(window.cells, vec![0, 0, 0, 1, 10]);
+        assert_eq!(window.to_string(), " 6 7 8 9 10\n 0 0 0 1 10\n     ^");
+
+        // clamped to the tape
+        let window = machine.tape_window(100);
+        assert_eq!((window.start, window.cells.len()), (0, 12));
+    }
+
+    #[derive(Debug, Default, Clone)]
+    struct RecordingSink(std::sync::Arc<std::sync::Mutex<Vec<TapeWindow>>>);
+
+    impl DebugSink for RecordingSink {
+        fn dump(&mut self, window: &TapeWindow) {
+            self.0.lock().unwrap().push(window.clone());
+        }
+    }
+
+    #[test]
+    fn debug_dumps_in_extended_dialect() {
+        let src = "+>++#<#!ignored #";
+        for (dialect, dumps) in [(Dialect::Standard, 0), (Dialect::Extended, 2)] {
+            let src_file = UcSourceFile::from_str_with_dialect(src, "", dialect);
+            for &engine in Engine::all() {
+                let sink = RecordingSink::default();
+                let mut machine = Machine::with_io(20, BufferedMachineIO::new(""));
+                machine.set_debug_sink(Box::new(sink.clone()));
+                machine.eval(&src_file, engine, OptLevel::O1).unwrap();
+
+                let windows = sink.0.lock().unwrap();
+                assert_eq!(windows.len(), dumps, "{} {}", dialect, engine);
+                if dumps > 0 {
+                    assert_eq!(windows[0].data_ptr, 11);
+                    assert_eq!(windows[0].start, 6);
+                    assert_eq!(windows[0].cells[4..6], [1, 2]);
+                    assert_eq!(windows[1].data_ptr, 10);
+                    assert_eq!(machine.executed(), 7);
+                }
+            }
+        }
+
+        // ignored without a sink
+        let src_file = UcSourceFile::from_str_with_dialect("#+#", "", Dialect::Extended);
+        let mut machine = Machine::with_io(20, BufferedMachineIO::new(""));
+        machine.eval_source_file(&src_file).unwrap();
+        assert_eq!(machine.executed(), 3);
+    }
+
+    #[test]
+    fn embedded_input() {
+        let src_file = UcSourceFile::from_str_with_dialect(",.,.,.!ab", "", Dialect::Extended);
+        let io = EmbeddedInputIO::new(
+            src_file.embedded_input().unwrap(),
+            BufferedMachineIO::new("xyz"),
+        );
+        let mut machine = Machine::with_io(20, io);
+        machine.eval_source_file(&src_file).unwrap();
+        assert_eq!(machine.io().inner().output(), "ab\u{0}");
+    }
+
+    #[test]
+    fn continue_without_reset() {
+        let mut machine = Machine::with_io(10, BufferedMachineIO::new(""));
+        let byte_codes = UcSourceFile::from_str("+>++", "").to_byte_codes().unwrap();
+        machine.eval_byte_codes(&byte_codes).unwrap();
+        machine.continue_byte_codes(&byte_codes).unwrap();
+        assert_eq!(machine.data_ptr(), 7);
+        assert_eq!(machine.cells()[5..8], [1, 3, 2]);
+
+        machine.eval_byte_codes(&byte_codes).unwrap();
+        assert_eq!(machine.cells()[5..8], [1, 2, 0]);
+    }
+
+    #[test]
+    fn unmatched_parens_are_reported_before_running() {
+        use crate::utility::ExtraParen;
+
+        let src_file = &UcSourceFile::from_str("]+[.", "");
+        let err = create_default_machine().eval_source_file(src_file);
+        assert_eq!(
+            err,
+            Err(MachineError::Source(UcSourceFileError::UnmatchedParen {
+                src_file,
+                details: vec![ExtraParen::Close { idx: 0 }, ExtraParen::Open { idx: 2 }],
+            }))
+        );
+    }
+
+    fn run(src: &str, input: &str, config: MachineConfig) -> Machine<BufferedMachineIO> {
+        let mut machine = Machine::with_config(config, BufferedMachineIO::new(input));
+        let src_file = UcSourceFile::from_str(src, "");
+        for &engine in Engine::all() {
+            machine
+                .eval(&src_file, engine, OptLevel::default())
+                .unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn config_round_trip() {
+        for cell_width in [CellWidth::U8, CellWidth::U16, CellWidth::U32] {
+            assert_eq!(cell_width.to_string().parse(), Ok(cell_width));
+            assert_eq!(CellWidth::from_bits(cell_width.bits()), Some(cell_width));
+        }
+        assert!("7".parse::<CellWidth>().is_err());
+        assert_eq!(CellWidth::from_bits(7), None);
+        for eof in [
+            EofBehavior::Zero,
+            EofBehavior::Unchanged,
+            EofBehavior::MaxValue,
+        ] {
+            assert_eq!(eof.to_string().parse(), Ok(eof));
+        }
+        assert!("none".parse::<EofBehavior>().is_err());
+        for &engine in Engine::all() {
+            assert_eq!(engine.to_string().parse(), Ok(engine));
+        }
+        assert!("jit".parse::<Engine>().is_err());
+    }
+
+    #[test]
+    fn cells_wrap_around_at_cell_width() {
+        let test_data = [
+            (CellWidth::U8, "\u{ff}"),
+            (CellWidth::U16, "\u{ffff}"),
+            (CellWidth::U32, "\u{fffd}"),
+        ];
+        for (cell_width, output) in test_data {
+            let config = MachineConfig {
+                cell_width,
+                ..MachineConfig::default()
+            };
+            assert_eq!(cell_width.mask().count_ones(), cell_width.bits());
+            let machine = run("-.", "", config);
+            assert_eq!(machine.io().output(), output, "{}", cell_width);
+        }
+    }
+
+    #[test]
+    fn eof_behaviors() {
+        let test_data = [
+            (EofBehavior::Zero, "a\u{0}"),
+            (EofBehavior::Unchanged, "aa"),
+            (EofBehavior::MaxValue, "a\u{ff}"),
+        ];
+        for (eof, output) in test_data {
+            let config = MachineConfig {
+                eof,
+                ..MachineConfig::default()
+            };
+            let mut machine = Machine::with_config(config, BufferedMachineIO::new("a"));
+            let src_file = UcSourceFile::from_str(",.,.", "");
+            machine
+                .eval(&src_file, Engine::ByteCode, OptLevel::default())
+                .unwrap();
+            assert_eq!(machine.io().output(), output, "{}", eof);
+        }
+    }
+
+    #[test]
+    fn consecutive_reads_consume_all_inputs() {
+        let mut machine = Machine::with_io(10, BufferedMachineIO::new("abc"));
+        let src_file = UcSourceFile::from_str(",,.", "");
+        machine
+            .eval(&src_file, Engine::ByteCode, OptLevel::O1)
+            .unwrap();
+        assert_eq!(machine.io().output(), "b");
+    }
+
+    #[test]
+    fn executed_commands_are_counted_the_same_by_all_engines() {
+        let src_file = UcSourceFile::from_str("++[->+<] comment", "");
+        let mut machine = Machine::with_io(10, BufferedMachineIO::new(""));
+        for &engine in Engine::all() {
+            for opt_level in [OptLevel::O0, OptLevel::O1] {
+                machine.eval(&src_file, engine, opt_level).unwrap();
+                // `]` jumps back to `[`, which checks the cell again
+                assert_eq!(machine.executed(), 2 + (1 + 4 + 1) * 2);
+            }
+        }
+    }
+
+    #[test]
+    fn step_limit_is_the_same_for_all_engines() {
+        let src_file = UcSourceFile::from_str("++[->+<] comment", "");
+        let mut machine = Machine::with_io(10, BufferedMachineIO::new(""));
+        for &engine in Engine::all() {
+            for opt_level in [OptLevel::O0, OptLevel::O1] {
+                machine.set_step_limit(Some(14));
+                machine.eval(&src_file, engine, opt_level).unwrap();
+                machine.set_step_limit(Some(13));
+                let err = machine.eval(&src_file, engine, opt_level).unwrap_err();
+                assert!(matches!(
+                    err,
+                    MachineError::Runtime(RuntimeError::StepLimitExceeded { limit: 13, .. })
+                ));
+            }
+        }
+
+        let src_file = UcSourceFile::from_str("+[]", "");
+        machine.set_step_limit(Some(100));
+        let err = machine.eval_source_file(&src_file).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "executed more than 100 commands, stopped at instruction 1"
+        );
+        machine.set_step_limit(None);
+        machine
+            .eval_source_file(&UcSourceFile::from_str("++[-]", ""))
+            .unwrap();
+    }
+
+    #[test]
+    fn data_ptr_out_of_tape() {
+        let src_file = UcSourceFile::from_str("+[>+]", "");
+        let mut machine = Machine::with_io(4, BufferedMachineIO::new(""));
+        for &engine in Engine::all() {
+            let err = machine.eval(&src_file, engine, OptLevel::O0).unwrap_err();
+            assert!(matches!(
+                err,
+                MachineError::Runtime(RuntimeError::DataPtrOutOfRange { data_ptr: 4, .. })
+            ));
+        }
+
+        let src_file = UcSourceFile::from_str("<<<+", "");
+        let err = machine.eval_source_file(&src_file).unwrap_err();
+        assert_eq!(
+            err,
+            MachineError::Runtime(RuntimeError::DataPtrOutOfRange {
+                instr_ptr: 3,
+                data_ptr: -1
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "data pointer -1 is out of the tape at instruction 3"
+        );
+    }
+
+    #[test]
+    fn runtime_errors_are_located_in_the_source() {
+        let src_file = UcSourceFile::from_str("++\n  <<<+", "");
+        let mut machine = Machine::with_io(4, BufferedMachineIO::new(""));
+        for &engine in Engine::all() {
+            for &opt_level in OptLevel::all() {
+                let Err(MachineError::Runtime(e)) = machine.eval(&src_file, engine, opt_level)
+                else {
+                    panic!("{} should fail", engine);
+                };
+                let locations = engine.locations(&src_file, opt_level).unwrap();
+                let pos = locations[e.instr_ptr()];
+                // engines check the data pointer at different commands of `<<<+`
+                assert_eq!(pos.line, 2, "{} {}", engine, opt_level);
+                assert!((3..=6).contains(&pos.col), "{} {}", engine, opt_level);
+            }
+        }
     }
 }
@@ -0,0 +1,405 @@
+//! byte codes compiled into a table of closures, `Engine::Closure`
+//!
+//! each run of byte codes between loop boundaries becomes one closure, which checks once
+//! that every cell it touches is on the tape and that the step limit cannot be hit
+//! within it, and then runs without checking again. If either check fails it runs the
+//! same byte codes one at a time as `Engine::ByteCode` does, so errors are reported at
+//! the same instruction
+//!
+//! `[` and `]` are closures of their own, returning where to go next, so loops do not
+//! nest closures and deep nesting cannot overflow the stack
+
+use super::{CellDataType, Machine, RuntimeError};
+use crate::byte_code::{ByteCode, ByteCodeKind, DenseByteCode, DenseByteCodes};
+use crate::machine_io::MachineIO;
+use crate::observer::{Observer, Op};
+
+/// runs one entry of the table, returns the index of the next one
+type Code<'a, IO, O> = Box<dyn Fn(&mut Machine<IO, O>) -> Result<usize, RuntimeError> + 'a>;
+
+/// runs one byte code of a block
+type BlockCode<'a, IO, O> = Box<dyn Fn(&mut Machine<IO, O>) -> Result<(), RuntimeError> + 'a>;
+
+/// an entry of the table before it is compiled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+enum Node {
+    /// byte codes in `start..end`, none of them a jump
+    Block { start: usize, end: usize },
+    /// `[` at `instr_ptr`
+    LoopStart { instr_ptr: usize },
+    /// `]` at `instr_ptr`
+    LoopEnd { instr_ptr: usize },
+}
+
+/// offsets from the data pointer of the cells a block touches, and the steps it takes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+struct Footprint {
+    /// `None` if no cell is touched
+    range: Option<(isize, isize)>,
+    steps: u64,
+}
+
+impl Footprint {
+    fn of(byte_codes: &[DenseByteCode]) -> Self {
+        let mut footprint = Self::default();
+        let mut offset = 0isize;
+        for bc in byte_codes {
+            match bc.kind {
+                ByteCodeKind::IncPtr => offset = offset.wrapping_add(bc.arg as isize),
+                ByteCodeKind::DecPtr => offset = offset.wrapping_sub(bc.arg as isize),
+                ByteCodeKind::IncData
+                | ByteCodeKind::DecData
+                | ByteCodeKind::Read
+                | ByteCodeKind::Write => {
+                    footprint.range = Some(match footprint.range {
+                        None => (offset, offset),
+                        Some((lo, hi)) => (lo.min(offset), hi.max(offset)),
+                    })
+                }
+                // the dump is clamped to the tape
+                ByteCodeKind::DebugDump => (),
+                ByteCodeKind::LoopStartJumpIfDataZero | ByteCodeKind::LoopEndJumpIfDataNotZero => {
+                    unreachable!("blocks have no jumps")
+                }
+            }
+            footprint.steps += bc.arg as u64;
+        }
+        footprint
+    }
+
+    /// every touched cell is on a tape of `tape_size` with the data pointer at `data_ptr`
+    fn fits(&self, data_ptr: usize, tape_size: usize) -> bool {
+        match self.range {
+            None => true,
+            Some((lo, hi)) => {
+                let start = data_ptr.wrapping_add_signed(lo);
+                start < tape_size && hi.abs_diff(lo) < tape_size - start
+            }
+        }
+    }
+}
+
+fn nodes(byte_codes: &[DenseByteCode]) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut start = 0;
+    for (instr_ptr, bc) in byte_codes.iter().enumerate() {
+        let node = match bc.kind {
+            ByteCodeKind::LoopStartJumpIfDataZero => Node::LoopStart { instr_ptr },
+            ByteCodeKind::LoopEndJumpIfDataNotZero => Node::LoopEnd { instr_ptr },
+            _ => continue,
+        };
+        if start < instr_ptr {
+            nodes.push(Node::Block {
+                start,
+                end: instr_ptr,
+            });
+        }
+        nodes.push(node);
+        start = instr_ptr + 1;
+    }
+    if start < byte_codes.len() {
+        nodes.push(Node::Block {
+            start,
+            end: byte_codes.len(),
+        });
+    }
+    nodes
+}
+
+/// changes the cell at the data pointer without checking it is on the tape
+#[inline(always)]
+fn update_cell_unchecked<IO: MachineIO, O: Observer>(
+    m: &mut Machine<IO, O>,
+    f: impl FnOnce(CellDataType) -> CellDataType,
+) {
+    let data_ptr = m.data_ptr;
+    // SAFETY: the block checks with `Footprint::fits` that every cell it touches is on
+    // the tape before it runs this
+    let cell = unsafe { m.cells.get_unchecked_mut(data_ptr) };
+    let old = *cell;
+    *cell = f(old) & m.cell_mask;
+    m.observer.on_cell_write(data_ptr, old, *cell);
+}
+
+/// one byte code of a block, once the block is known to fit
+fn compile_unchecked<'a, IO: MachineIO + 'a, O: Observer + 'a>(
+    bc: DenseByteCode,
+    instr_ptr: usize,
+) -> BlockCode<'a, IO, O> {
+    let arg = bc.arg as usize;
+    match bc.kind {
+        ByteCodeKind::IncPtr => Box::new(move |m| {
+            m.observer.before(Op::IncPtr, arg, instr_ptr);
+            m.data_ptr = m.data_ptr.wrapping_add(arg);
+            m.observer.after(Op::IncPtr);
+            Ok(())
+        }),
+        ByteCodeKind::DecPtr => Box::new(move |m| {
+            m.observer.before(Op::DecPtr, arg, instr_ptr);
+            m.data_ptr = m.data_ptr.wrapping_sub(arg);
+            m.observer.after(Op::DecPtr);
+            Ok(())
+        }),
+        ByteCodeKind::IncData => Box::new(move |m| {
+            m.observer.before(Op::IncData, arg, instr_ptr);
+            update_cell_unchecked(m, |v| v.wrapping_add(arg as CellDataType));
+            m.observer.after(Op::IncData);
+            Ok(())
+        }),
+        ByteCodeKind::DecData => Box::new(move |m| {
+            m.observer.before(Op::DecData, arg, instr_ptr);
+            update_cell_unchecked(m, |v| v.wrapping_sub(arg as CellDataType));
+            m.observer.after(Op::DecData);
+            Ok(())
+        }),
+        // bound by io rather than by checks, and count their own steps
+        ByteCodeKind::Read => Box::new(move |m| {
+            m.instr_ptr = instr_ptr;
+            m.read(arg)
+        }),
+        ByteCodeKind::Write => Box::new(move |m| {
+            m.instr_ptr = instr_ptr;
+            m.write(arg)
+        }),
+        ByteCodeKind::DebugDump => Box::new(move |m| {
+            m.instr_ptr = instr_ptr;
+            m.debug_dump()
+        }),
+        ByteCodeKind::LoopStartJumpIfDataZero | ByteCodeKind::LoopEndJumpIfDataNotZero => {
+            unreachable!("blocks have no jumps")
+        }
+    }
+}
+
+fn compile_block<'a, IO: MachineIO + 'a, O: Observer + 'a>(
+    byte_codes: &'a [DenseByteCode],
+    start: usize,
+    end: usize,
+    next: usize,
+) -> Code<'a, IO, O> {
+    let footprint = Footprint::of(&byte_codes[start..end]);
+    let io_steps = byte_codes[start..end]
+        .iter()
+        .filter(|bc| {
+            matches!(
+                bc.kind,
+                ByteCodeKind::Read | ByteCodeKind::Write | ByteCodeKind::DebugDump
+            )
+        })
+        .map(|bc| bc.arg as u64)
+        .sum::<u64>();
+    let codes = (start..end)
+        .map(|instr_ptr| compile_unchecked(byte_codes[instr_ptr], instr_ptr))
+        .collect::<Vec<_>>();
+    Box::new(move |m| {
+        if footprint.fits(m.data_ptr, m.cells.len())
+            && m.executed.saturating_add(footprint.steps) <= m.step_limit
+        {
+            // counted up front, io counts its own
+            m.executed += footprint.steps - io_steps;
+            for code in &codes {
+                code(m)?;
+            }
+        } else {
+            m.instr_ptr = start;
+            while m.instr_ptr < end {
+                m.exec_byte_code(byte_codes[m.instr_ptr])?;
+                m.check_step_limit()?;
+            }
+        }
+        Ok(next)
+    })
+}
+
+/// compiles `byte_codes` into a table of closures, the first entry runs first and the
+/// program ends when one returns the length of the table
+fn compile<'a, IO: MachineIO + 'a, O: Observer + 'a>(
+    byte_codes: &'a [DenseByteCode],
+) -> Vec<Code<'a, IO, O>> {
+    let nodes = nodes(byte_codes);
+    let mut node_of_instr = vec![0; byte_codes.len()];
+    for (idx, node) in nodes.iter().enumerate() {
+        if let Node::LoopStart { instr_ptr } | Node::LoopEnd { instr_ptr } = *node {
+            node_of_instr[instr_ptr] = idx;
+        }
+    }
+
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(idx, &node)| -> Code<'a, IO, O> {
+            match node {
+                Node::Block { start, end } => compile_block(byte_codes, start, end, idx + 1),
+                Node::LoopStart { instr_ptr } => {
+                    let end_ptr = byte_codes[instr_ptr].arg as usize;
+                    let after_loop = node_of_instr[end_ptr] + 1;
+                    Box::new(move |m| {
+                        m.instr_ptr = instr_ptr;
+                        m.loop_start_jump_if_data_zero(end_ptr)?;
+                        m.check_step_limit()?;
+                        Ok(if m.instr_ptr == instr_ptr + 1 {
+                            idx + 1
+                        } else {
+                            after_loop
+                        })
+                    })
+                }
+                Node::LoopEnd { instr_ptr } => {
+                    let start_ptr = byte_codes[instr_ptr].arg as usize;
+                    let loop_start = node_of_instr[start_ptr];
+                    Box::new(move |m| {
+                        m.instr_ptr = instr_ptr;
+                        m.loop_end_jump_if_data_not_zero(start_ptr)?;
+                        m.check_step_limit()?;
+                        Ok(if m.instr_ptr == start_ptr {
+                            loop_start
+                        } else {
+                            idx + 1
+                        })
+                    })
+                }
+            }
+        })
+        .collect()
+}
+
+impl<IO: MachineIO, O: Observer> Machine<IO, O> {
+    /// the same as `eval_byte_codes`, but compiles `byte_codes` into closures first
+    pub fn eval_closures(&mut self, byte_codes: &[ByteCode]) -> Result<(), RuntimeError> {
+        self.eval_dense_closures(&DenseByteCodes::from(byte_codes))
+    }
+
+    /// the same as `eval_dense_byte_codes`, but compiles `byte_codes` into closures first
+    pub fn eval_dense_closures(&mut self, byte_codes: &DenseByteCodes) -> Result<(), RuntimeError> {
+        byte_codes
+            .validate()
+            .map_err(RuntimeError::InvalidProgram)?;
+        self.reset();
+        let table = compile::<IO, O>(byte_codes.codes());
+        let mut next = 0;
+        while let Some(code) = table.get(next) {
+            next = code(self)?;
+        }
+        self.instr_ptr = byte_codes.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{machine_io::BufferedMachineIO, source_file::UcSourceFile};
+
+    fn byte_codes(src: &str) -> Vec<ByteCode> {
+        UcSourceFile::from_str(src, "").to_byte_codes().unwrap()
+    }
+
+    fn dense(src: &str) -> Vec<DenseByteCode> {
+        DenseByteCodes::from(byte_codes(src).as_slice())
+            .codes()
+            .to_vec()
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_small_value_enum(&Node::LoopStart { instr_ptr: 0 });
+        is_default_debug(&Footprint::default());
+    }
+
+    #[test]
+    fn blocks_are_split_at_loops() {
+        assert_eq!(
+            nodes(&dense("+>[[-]<]..")),
+            vec![
+                Node::Block { start: 0, end: 2 },
+                Node::LoopStart { instr_ptr: 2 },
+                Node::LoopStart { instr_ptr: 3 },
+                Node::Block { start: 4, end: 5 },
+                Node::LoopEnd { instr_ptr: 5 },
+                Node::Block { start: 6, end: 7 },
+                Node::LoopEnd { instr_ptr: 7 },
+                Node::Block { start: 8, end: 9 },
+            ]
+        );
+        assert_eq!(nodes(&[]), vec![]);
+    }
+
+    #[test]
+    fn footprint_of_blocks() {
+        let footprint = Footprint::of(&dense(">>+<<<<-.>>>>>>"));
+        assert_eq!(footprint.range, Some((-2, 2)));
+        assert_eq!(footprint.steps, 15);
+        assert!(footprint.fits(2, 5));
+        assert!(!footprint.fits(1, 5));
+        assert!(!footprint.fits(3, 5));
+        // wrapped off the left of the tape
+        assert!(!footprint.fits(usize::MAX, 5));
+
+        let moves_only = Footprint::of(&dense("<<<<"));
+        assert_eq!(moves_only.range, None);
+        assert!(moves_only.fits(0, 1));
+    }
+
+    #[test]
+    fn falls_back_to_checks_at_the_edge_of_the_tape() {
+        // the loop body fits until it reaches the right end of the tape
+        let byte_codes = byte_codes("+[>+.]");
+        let mut machine = Machine::with_io(8, BufferedMachineIO::new(""));
+        let expected = machine.eval_byte_codes(&byte_codes).unwrap_err();
+        let expected = (expected, machine.cells().to_vec(), machine.executed());
+        let err = machine.eval_closures(&byte_codes).unwrap_err();
+        assert_eq!(
+            (err, machine.cells().to_vec(), machine.executed()),
+            expected
+        );
+        assert_eq!(machine.io().output(), "\u{1}\u{1}\u{1}");
+
+        let byte_codes = UcSourceFile::from_str("+.>>+", "")
+            .to_byte_codes_with(crate::byte_code::OptLevel::O0)
+            .unwrap();
+        let mut machine = Machine::with_io(3, BufferedMachineIO::new(""));
+        assert_eq!(
+            machine.eval_closures(&byte_codes),
+            Err(RuntimeError::DataPtrOutOfRange {
+                instr_ptr: 4,
+                data_ptr: 3
+            })
+        );
+        // everything before the failing instruction has run
+        assert_eq!(machine.io().output(), "\u{1}");
+        assert_eq!(machine.cells(), [0, 1, 0]);
+        assert_eq!(machine.executed(), 4);
+    }
+
+    #[test]
+    fn invalid_programs_are_rejected_before_running() {
+        use crate::byte_code::{ByteCodeError, ByteCodeKind, ProgramError};
+        use crate::source_file::RawContentIndex;
+
+        let range = (RawContentIndex::new(0), RawContentIndex::new(1));
+        let byte_codes = [
+            ByteCode::new(ByteCodeKind::IncData, 1, range).unwrap(),
+            ByteCode::new(ByteCodeKind::LoopStartJumpIfDataZero, 99, range).unwrap(),
+            ByteCode::new(ByteCodeKind::LoopEndJumpIfDataNotZero, 1, range).unwrap(),
+        ];
+        let expected = Err(RuntimeError::InvalidProgram(ProgramError {
+            idx: 1,
+            error: ByteCodeError::BadJump,
+        }));
+        let mut machine = Machine::with_io(4, BufferedMachineIO::new(""));
+        assert_eq!(machine.eval_byte_codes(&byte_codes), expected);
+        assert_eq!(machine.eval_closures(&byte_codes), expected);
+        assert_eq!(machine.continue_byte_codes(&byte_codes), expected);
+        assert_eq!(machine.executed(), 0);
+    }
+
+    #[test]
+    fn deep_nesting_does_not_overflow() {
+        let src = format!("+{}-{}", "[".repeat(100_000), "]".repeat(100_000));
+        let mut machine = Machine::with_io(4, BufferedMachineIO::new(""));
+        machine.eval_closures(&byte_codes(&src)).unwrap();
+        assert_eq!(machine.cells()[2], 0);
+    }
+}
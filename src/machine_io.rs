@@ -1,12 +1,38 @@
+use crate::machine::TapeWindow;
+use std::io::Read;
+
 pub trait MachineIO {
     fn out_char_n_times(&mut self, c: char, n: usize);
-    fn in_char(&mut self) -> char;
+    /// `None` when there is no more input
+    fn in_char(&mut self) -> Option<char>;
     fn flush_all(&mut self);
 }
 
+/// receives the tape dumps of `#` in `Dialect::Extended`
+pub trait DebugSink: std::fmt::Debug + Send + Sync {
+    fn dump(&mut self, window: &TapeWindow);
+}
+
+/// writes dumps to stderr
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct StderrDebugSink;
+
+impl DebugSink for StderrDebugSink {
+    fn dump(&mut self, window: &TapeWindow) {
+        eprintln!("{}", window);
+    }
+}
+
+#[derive(Debug)]
+enum Input {
+    Term(console::Term),
+    Reader(std::io::BufReader<std::fs::File>),
+    Stdin,
+}
+
 #[derive(Debug)]
 pub struct DefaultMachineIO {
-    term: console::Term,
+    input: Input,
 }
 
 impl Default for DefaultMachineIO {
@@ -16,9 +42,29 @@ impl Default for DefaultMachineIO {
 }
 
 impl DefaultMachineIO {
+    /// read from the terminal if stdin is one, otherwise from whatever is piped into stdin
     pub fn new() -> Self {
+        let term = console::Term::stdout();
+        let input = if console::user_attended() {
+            Input::Term(term)
+        } else {
+            Input::Stdin
+        };
+        Self { input }
+    }
+
+    /// read from `file` instead of stdin
+    pub fn with_input_file(file: std::fs::File) -> Self {
         Self {
-            term: console::Term::stdout(),
+            input: Input::Reader(std::io::BufReader::new(file)),
+        }
+    }
+
+    fn read_byte<R: Read>(r: &mut R) -> Option<char> {
+        let mut buf = [0u8; 1];
+        match r.read(&mut buf) {
+            Ok(1) => Some(buf[0] as char),
+            _ => None,
         }
     }
 }
@@ -28,13 +74,100 @@ impl MachineIO for DefaultMachineIO {
         print!("{}", c.to_string().repeat(n));
     }
 
-    fn in_char(&mut self) -> char {
-        self.term.read_char().unwrap()
+    fn in_char(&mut self) -> Option<char> {
+        use std::io::Write;
+
+        std::io::stdout().flush().unwrap();
+        match &mut self.input {
+            Input::Term(term) => term.read_char().ok(),
+            Input::Reader(r) => Self::read_byte(r),
+            Input::Stdin => Self::read_byte(&mut std::io::stdin().lock()),
+        }
     }
 
     fn flush_all(&mut self) {}
 }
 
+/// reads input from `input` until it runs out, `inner` is only used for output
+///
+/// for the input embedded in the source file of `Dialect::Extended`
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct EmbeddedInputIO<IO> {
+    input: std::collections::VecDeque<char>,
+    inner: IO,
+}
+
+impl<IO: MachineIO> EmbeddedInputIO<IO> {
+    pub fn new(input: &str, inner: IO) -> Self {
+        Self {
+            input: input.chars().collect(),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &IO {
+        &self.inner
+    }
+}
+
+impl<IO: MachineIO> MachineIO for EmbeddedInputIO<IO> {
+    fn out_char_n_times(&mut self, c: char, n: usize) {
+        self.inner.out_char_n_times(c, n);
+    }
+
+    fn in_char(&mut self) -> Option<char> {
+        self.input.pop_front()
+    }
+
+    fn flush_all(&mut self) {
+        self.inner.flush_all();
+    }
+}
+
+/// reads from a fixed input and keeps the output in memory
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct BufferedMachineIO {
+    input: std::collections::VecDeque<char>,
+    output: String,
+}
+
+impl BufferedMachineIO {
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: input.chars().collect(),
+            output: String::new(),
+        }
+    }
+
+    /// everything written since the last `flush_all`
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn push_input(&mut self, input: &str) {
+        self.input.extend(input.chars());
+    }
+}
+
+impl MachineIO for BufferedMachineIO {
+    fn out_char_n_times(&mut self, c: char, n: usize) {
+        self.output.extend(std::iter::repeat_n(c, n));
+    }
+
+    fn in_char(&mut self) -> Option<char> {
+        self.input.pop_front()
+    }
+
+    /// input is kept, so that it can be given before running a program
+    fn flush_all(&mut self) {
+        self.output.clear();
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -44,5 +177,45 @@ mod test {
         use crate::utility::traits::*;
 
         is_default_debug(&DefaultMachineIO::default());
+        is_default_debug(&BufferedMachineIO::default());
+        is_default_debug(&StderrDebugSink);
+        is_default_debug(&EmbeddedInputIO::<BufferedMachineIO>::default());
+    }
+
+    #[test]
+    fn embedded_input_never_falls_back_to_inner() {
+        let mut io = EmbeddedInputIO::new("a", BufferedMachineIO::new("b"));
+        assert_eq!(io.in_char(), Some('a'));
+        assert_eq!(io.in_char(), None);
+        io.out_char_n_times('x', 2);
+        assert_eq!(io.inner().output(), "xx");
+    }
+
+    #[test]
+    fn buffered_io() {
+        let mut io = BufferedMachineIO::new("a");
+        io.push_input("b");
+        assert_eq!(io.in_char(), Some('a'));
+        assert_eq!(io.in_char(), Some('b'));
+        assert_eq!(io.in_char(), None);
+
+        io.out_char_n_times('x', 3);
+        assert_eq!(io.output(), "xxx");
+        io.flush_all();
+        assert_eq!(io.take_output(), "");
+    }
+
+    #[test]
+    fn read_from_input_file() {
+        let path = std::env::temp_dir().join("brainfuck_machine_io_read_from_input_file");
+        std::fs::write(&path, "ab").unwrap();
+
+        let mut io = DefaultMachineIO::with_input_file(std::fs::File::open(&path).unwrap());
+        assert_eq!(io.in_char(), Some('a'));
+        assert_eq!(io.in_char(), Some('b'));
+        assert_eq!(io.in_char(), None);
+        assert_eq!(io.in_char(), None);
+
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,289 @@
+use crate::source_file::{Dialect, UcSourceFile, UcSourceFileError};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct MinifyOptions {
+    /// remove adjacent `+-` and `-+`
+    pub cancel_pairs: bool,
+    /// remove adjacent `><` and `<>` as well, off unless asked for so that the data pointer
+    /// moves as written. The tape is only checked when a cell is used, a pair stepping off
+    /// the edge and back never fails either way
+    pub cancel_pointer_pairs: bool,
+    /// remove loops that can never be entered, either because no cell has been changed yet,
+    /// or because they come right after another loop
+    pub dead_loops: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Minified {
+    pub code: String,
+    /// in bytes
+    pub original_size: usize,
+    /// in bytes
+    pub minified_size: usize,
+}
+
+impl Minified {
+    pub fn reduction(&self) -> usize {
+        self.original_size - self.minified_size
+    }
+}
+
+impl std::fmt::Display for Minified {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let percent = if self.original_size == 0 {
+            0.0
+        } else {
+            self.reduction() as f64 * 100.0 / self.original_size as f64
+        };
+        write!(
+            f,
+            "{} -> {} bytes, {} bytes ({:.1}%) removed",
+            self.original_size,
+            self.minified_size,
+            self.reduction(),
+            percent
+        )
+    }
+}
+
+/// keep only the eight commands of `src_file`, optionally simplified by `options`, `#` and
+/// the embedded input after `!` are kept as well in `Dialect::Extended`
+///
+/// simplifications never change what a program that runs without errors outputs, nor make
+/// a program stepping off the tape run without errors
+pub fn minify<'src_file>(
+    src_file: &'src_file UcSourceFile,
+    options: &MinifyOptions,
+) -> Result<Minified, UcSourceFileError<'src_file>> {
+    src_file.check_parens()?;
+
+    let extended = src_file.dialect() == Dialect::Extended;
+    let mut codes = src_file
+        .iter()
+        .filter(|t| t.is_command() || (extended && t.uc == "#"))
+        .map(|t| t.uc.as_bytes()[0])
+        .collect::<Vec<_>>();
+
+    loop {
+        let len = codes.len();
+        if options.cancel_pairs || options.cancel_pointer_pairs {
+            codes = cancel_pairs(&codes, options);
+        }
+        if options.dead_loops {
+            codes = remove_dead_loops(&codes);
+        }
+        if codes.len() == len {
+            break;
+        }
+    }
+
+    let mut code = String::from_utf8(codes).unwrap();
+    if let Some(input) = src_file.embedded_input() {
+        code.push('!');
+        code.push_str(input);
+    }
+    Ok(Minified {
+        original_size: src_file.raw_content().len(),
+        minified_size: code.len(),
+        code,
+    })
+}
+
+fn cancel_pairs(codes: &[u8], options: &MinifyOptions) -> Vec<u8> {
+    let mut out = Vec::with_capacity(codes.len());
+    for &c in codes {
+        match (out.last(), c) {
+            (Some(b'+'), b'-') | (Some(b'-'), b'+') if options.cancel_pairs => {
+                out.pop();
+            }
+            (Some(b'>'), b'<') | (Some(b'<'), b'>') if options.cancel_pointer_pairs => {
+                out.pop();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn remove_dead_loops(codes: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(codes.len());
+    // no cell has been changed, every cell is still zero
+    let mut all_zero = true;
+    let mut idx = 0;
+    while idx < codes.len() {
+        let c = codes[idx];
+        if c == b'[' && (all_zero || out.last() == Some(&b']')) {
+            idx = matching_end(codes, idx) + 1;
+            continue;
+        }
+        if matches!(c, b'+' | b'-' | b',') {
+            all_zero = false;
+        }
+        out.push(c);
+        idx += 1;
+    }
+    out
+}
+
+fn matching_end(codes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    for (idx, &c) in codes.iter().enumerate().skip(start) {
+        match c {
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return idx;
+                }
+            }
+            _ => (),
+        }
+    }
+    unreachable!("parens have been checked")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn minify_str(s: &str, options: &MinifyOptions) -> String {
+        let src_file = UcSourceFile::from_str(s, "");
+        minify(&src_file, options).unwrap().code
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_default_debug(&MinifyOptions::default());
+        is_big_value_struct_but_no_default(&Minified {
+            code: String::new(),
+            original_size: 0,
+            minified_size: 0,
+        });
+    }
+
+    #[test]
+    fn comments_are_stripped() {
+        let src_file = UcSourceFile::from_str("set é to 2 ++\n[->+<] done", "");
+        let minified = minify(&src_file, &MinifyOptions::default()).unwrap();
+        assert_eq!(minified.code, "++[->+<]");
+        assert_eq!(minified.original_size, 26);
+        assert_eq!(minified.minified_size, 8);
+        assert_eq!(minified.reduction(), 18);
+        assert_eq!(
+            minified.to_string(),
+            "26 -> 8 bytes, 18 bytes (69.2%) removed"
+        );
+    }
+
+    #[test]
+    fn no_simplification_by_default() {
+        let s = "[comment]+-><+[-][.].";
+        assert_eq!(minify_str(s, &MinifyOptions::default()), "[]+-><+[-][.].");
+    }
+
+    #[test]
+    fn cancelling_pairs() {
+        let options = MinifyOptions {
+            cancel_pairs: true,
+            ..MinifyOptions::default()
+        };
+        assert_eq!(minify_str("+-><.", &options), "><.");
+        assert_eq!(minify_str("++-+--.", &options), ".");
+        assert_eq!(minify_str("+[-]-", &options), "+[-]-");
+
+        let options = MinifyOptions {
+            cancel_pointer_pairs: true,
+            ..MinifyOptions::default()
+        };
+        assert_eq!(minify_str("+-><.", &options), "+-.");
+
+        let options = MinifyOptions {
+            cancel_pairs: true,
+            cancel_pointer_pairs: true,
+            ..MinifyOptions::default()
+        };
+        assert_eq!(minify_str("+-><.", &options), ".");
+        assert_eq!(minify_str("++>+<<>>-<--.", &options), ".");
+        assert_eq!(minify_str(">[<]>", &options), ">[<]>");
+    }
+
+    #[test]
+    fn stepping_off_the_tape_still_fails() {
+        use crate::{
+            byte_code::OptLevel,
+            machine::{Engine, Machine},
+            machine_io::BufferedMachineIO,
+        };
+
+        // whether `s` runs without errors on a tape of one cell
+        let run = |s: &str| {
+            let src_file = UcSourceFile::from_str(s, "");
+            let mut machine = Machine::with_io(1, BufferedMachineIO::new(""));
+            machine
+                .eval(&src_file, Engine::Source, OptLevel::O0)
+                .is_ok()
+        };
+        let options = MinifyOptions {
+            cancel_pairs: true,
+            cancel_pointer_pairs: true,
+            dead_loops: true,
+        };
+        for src in ["><<+", "+-<>>.", "<>+<[+]"] {
+            assert!(!run(src), "{}", src);
+            assert!(!run(&minify_str(src, &options)), "{}", src);
+        }
+        // stepping off and back without using a cell is fine
+        assert!(run("<>+."));
+        assert!(run(&minify_str("<>+.", &options)));
+    }
+
+    #[test]
+    fn dead_loops() {
+        let options = MinifyOptions {
+            dead_loops: true,
+            ..MinifyOptions::default()
+        };
+        assert_eq!(
+            minify_str("[comment [nested]]>.[-]+[-][.]", &options),
+            ">.+[-]"
+        );
+        assert_eq!(minify_str(",[.,]", &options), ",[.,]");
+        assert_eq!(minify_str("+[[-]>][<]", &options), "+[[-]>]");
+    }
+
+    #[test]
+    fn simplifications_reach_fixed_point() {
+        let options = MinifyOptions {
+            cancel_pairs: true,
+            cancel_pointer_pairs: true,
+            dead_loops: true,
+        };
+        assert_eq!(minify_str("+-[.]+", &options), "+");
+        assert_eq!(minify_str("+[-][.]<>[.]>+", &options), "+[-]>+");
+        assert_eq!(minify_str("+[-]<[.]>[.]", &options), "+[-]<[.]>[.]");
+    }
+
+    #[test]
+    fn extended_dialect_is_kept() {
+        let src_file = UcSourceFile::from_str_with_dialect(
+            "read a char # dump +-\n,[.,]!input #[\n",
+            "",
+            Dialect::Extended,
+        );
+        let options = MinifyOptions {
+            cancel_pairs: true,
+            cancel_pointer_pairs: true,
+            dead_loops: true,
+        };
+        let minified = minify(&src_file, &options).unwrap();
+        assert_eq!(minified.code, "#,[.,]!input #[\n");
+    }
+
+    #[test]
+    fn unmatched_parens_are_errors() {
+        let src_file = UcSourceFile::from_str("+]", "");
+        assert!(minify(&src_file, &MinifyOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,473 @@
+//! `.bfc` object files, byte codes compiled ahead of time so a program runs without its
+//! source being parsed again
+//!
+//! ```text
+//! "BFC\0" version:u8 flags:u8 tape_size:varint cell_width:u8 eof:u8 opt_level:u8
+//! count:varint (kind:u8 arg:varint){count}
+//! input:(len:varint utf8)?                            if flags has FLAG_INPUT
+//! source_map:(source:(len:varint utf8)
+//!     (raw_start raw_end start_line start_col end_line end_col){count})?
+//!                                                     if flags has FLAG_SOURCE_MAP
+//! crc32:u32
+//! ```
+//!
+//! numbers are LEB128 varints but the checksum, which is little endian and covers every
+//! byte before it
+
+use crate::{
+    byte_code::{validate, ByteCode, ByteCodeError, ByteCodeKind, DenseByteCodes, OptLevel},
+    machine::{CellWidth, EofBehavior, MachineConfig},
+    source_file::{RawContentIndex, SourcePos, UcSourceFile, UcSourceFileError},
+    source_map::{Mapping, SourceMap},
+    utility::{crc32, write_varint, DecodeError, Reader},
+};
+
+const MAGIC: &[u8; 4] = b"BFC\0";
+
+/// input embedded in the source follows the byte codes
+const FLAG_INPUT: u8 = 1;
+/// a source map follows the byte codes and the input
+const FLAG_SOURCE_MAP: u8 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum ObjectError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// the file ends in the middle of something
+    Truncated,
+    /// something decoded is out of its range, at this byte offset
+    Corrupted(usize),
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// a `[` or `]` at this byte code does not jump to its matching one
+    BadJump(usize),
+}
+
+impl std::fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("not a compiled program"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported compiled program version {}, expecting {}",
+                v,
+                ObjectFile::VERSION
+            ),
+            Self::Truncated => f.write_str("compiled program is truncated"),
+            Self::Corrupted(offset) => {
+                write!(f, "compiled program is corrupted at byte {}", offset)
+            }
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "compiled program is corrupted, checksum is {:08x} rather than {:08x}",
+                actual, expected
+            ),
+            Self::BadJump(idx) => write!(f, "byte code {} jumps to a wrong place", idx),
+        }
+    }
+}
+
+impl std::error::Error for ObjectError {}
+
+impl From<DecodeError> for ObjectError {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Truncated => Self::Truncated,
+            DecodeError::Corrupted(offset) => Self::Corrupted(offset),
+        }
+    }
+}
+
+/// a compiled program, with the machine configuration it runs with
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct ObjectFile {
+    pub config: MachineConfig,
+    pub opt_level: OptLevel,
+    /// source ranges are all empty without a source map
+    pub byte_codes: DenseByteCodes,
+    /// embedded in a source file of `Dialect::Extended`
+    pub input: Option<String>,
+    pub source_map: Option<SourceMap>,
+}
+
+impl ObjectFile {
+    pub const VERSION: u8 = 1;
+
+    pub fn compile<'src_file>(
+        src_file: &'src_file UcSourceFile,
+        config: MachineConfig,
+        opt_level: OptLevel,
+        with_source_map: bool,
+    ) -> Result<Self, UcSourceFileError<'src_file>> {
+        let byte_codes = src_file.to_byte_codes_with(opt_level)?;
+        Ok(Self {
+            config,
+            opt_level,
+            byte_codes: DenseByteCodes::from(byte_codes.as_slice()),
+            input: src_file.embedded_input().map(str::to_owned),
+            source_map: with_source_map.then(|| SourceMap::from_byte_codes(src_file, &byte_codes)),
+        })
+    }
+
+    /// whether `bytes` start like an object file, they can still fail to decode
+    pub fn is_object(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.push(Self::VERSION);
+        let mut flags = 0;
+        if self.input.is_some() {
+            flags |= FLAG_INPUT;
+        }
+        if self.source_map.is_some() {
+            flags |= FLAG_SOURCE_MAP;
+        }
+        buf.push(flags);
+
+        write_varint(&mut buf, self.config.tape_size as u64);
+        buf.push(self.config.cell_width.bits() as u8);
+        buf.push(match self.config.eof {
+            EofBehavior::Zero => 0,
+            EofBehavior::Unchanged => 1,
+            EofBehavior::MaxValue => 2,
+        });
+        buf.push(match self.opt_level {
+            OptLevel::O0 => 0,
+            OptLevel::O1 => 1,
+        });
+
+        write_varint(&mut buf, self.byte_codes.len() as u64);
+        for code in self.byte_codes.codes() {
+            buf.push(encode_kind(code.kind));
+            write_varint(&mut buf, u64::from(code.arg));
+        }
+        if let Some(input) = &self.input {
+            write_str(&mut buf, input);
+        }
+        if let Some(source_map) = &self.source_map {
+            write_str(&mut buf, &source_map.source);
+            for m in &source_map.mappings {
+                for n in [
+                    m.raw_start,
+                    m.raw_end,
+                    m.start.line,
+                    m.start.col,
+                    m.end.line,
+                    m.end.col,
+                ] {
+                    write_varint(&mut buf, n as u64);
+                }
+            }
+        }
+
+        let crc = crc32(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ObjectError> {
+        if !Self::is_object(bytes) {
+            return Err(ObjectError::BadMagic);
+        }
+        let Some(&version) = bytes.get(MAGIC.len()) else {
+            return Err(ObjectError::Truncated);
+        };
+        if version != Self::VERSION {
+            return Err(ObjectError::UnsupportedVersion(version));
+        }
+        let Some(body_len) = bytes.len().checked_sub(4) else {
+            return Err(ObjectError::Truncated);
+        };
+        let (body, crc) = bytes.split_at(body_len);
+        let expected = u32::from_le_bytes(crc.try_into().unwrap());
+        let actual = crc32(body);
+        if expected != actual {
+            return Err(ObjectError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut r = Reader::new(body);
+        r.bytes(MAGIC.len() + 1)?;
+        let flags = r.byte()?;
+        if flags & !(FLAG_INPUT | FLAG_SOURCE_MAP) != 0 {
+            return Err(ObjectError::Corrupted(r.offset - 1));
+        }
+        let config = decode_config(&mut r)?;
+        let opt_level = match r.byte()? {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            _ => return Err(r.corrupted().into()),
+        };
+
+        let count = r.usize()?;
+        let mut byte_codes = Vec::new();
+        // where each byte code starts, to report the bad ones
+        let mut offsets = Vec::new();
+        for _ in 0..count {
+            offsets.push(r.offset);
+            let kind = decode_kind(r.byte()?).ok_or_else(|| r.corrupted())?;
+            byte_codes.push(ByteCode {
+                kind,
+                arg: r.usize()?,
+                range_in_raw: (RawContentIndex::new(0), RawContentIndex::new(0)),
+            });
+        }
+        let input = if flags & FLAG_INPUT != 0 {
+            Some(read_str(&mut r)?)
+        } else {
+            None
+        };
+        let source_map = if flags & FLAG_SOURCE_MAP != 0 {
+            let source = read_str(&mut r)?;
+            let mut mappings = Vec::with_capacity(count);
+            for (output, bc) in byte_codes.iter_mut().enumerate() {
+                let mut n = [0; 6];
+                for n in &mut n {
+                    *n = r.usize()?;
+                }
+                let [raw_start, raw_end, start_line, start_col, end_line, end_col] = n;
+                bc.range_in_raw = (
+                    RawContentIndex::new(raw_start),
+                    RawContentIndex::new(raw_end),
+                );
+                mappings.push(Mapping {
+                    output,
+                    start: SourcePos {
+                        line: start_line,
+                        col: start_col,
+                    },
+                    end: SourcePos {
+                        line: end_line,
+                        col: end_col,
+                    },
+                    raw_start,
+                    raw_end,
+                });
+            }
+            Some(SourceMap {
+                version: SourceMap::VERSION,
+                source,
+                target: String::from("byte_codes"),
+                mappings,
+            })
+        } else {
+            None
+        };
+        if !r.is_empty() {
+            return Err(r.corrupted().into());
+        }
+        validate(&byte_codes).map_err(|e| match e.error {
+            ByteCodeError::UnmatchedLoopStart
+            | ByteCodeError::UnmatchedLoopEnd
+            | ByteCodeError::BadJump => ObjectError::BadJump(e.idx),
+            _ => ObjectError::Corrupted(offsets[e.idx]),
+        })?;
+
+        Ok(Self {
+            config,
+            opt_level,
+            byte_codes: DenseByteCodes::from(byte_codes.as_slice()),
+            input,
+            source_map,
+        })
+    }
+}
+
+fn decode_config(r: &mut Reader<'_>) -> Result<MachineConfig, ObjectError> {
+    let tape_size = r.usize()?;
+    if tape_size > MachineConfig::MAX_TAPE_SIZE {
+        return Err(r.corrupted().into());
+    }
+    let cell_width = match r.byte()? {
+        8 => CellWidth::U8,
+        16 => CellWidth::U16,
+        32 => CellWidth::U32,
+        _ => return Err(r.corrupted().into()),
+    };
+    let eof = match r.byte()? {
+        0 => EofBehavior::Zero,
+        1 => EofBehavior::Unchanged,
+        2 => EofBehavior::MaxValue,
+        _ => return Err(r.corrupted().into()),
+    };
+    Ok(MachineConfig {
+        tape_size,
+        cell_width,
+        eof,
+    })
+}
+
+/// stable across changes to `ByteCodeKind`
+fn encode_kind(kind: ByteCodeKind) -> u8 {
+    match kind {
+        ByteCodeKind::IncPtr => 0,
+        ByteCodeKind::DecPtr => 1,
+        ByteCodeKind::IncData => 2,
+        ByteCodeKind::DecData => 3,
+        ByteCodeKind::Read => 4,
+        ByteCodeKind::Write => 5,
+        ByteCodeKind::LoopStartJumpIfDataZero => 6,
+        ByteCodeKind::LoopEndJumpIfDataNotZero => 7,
+        ByteCodeKind::DebugDump => 8,
+    }
+}
+
+fn decode_kind(b: u8) -> Option<ByteCodeKind> {
+    Some(match b {
+        0 => ByteCodeKind::IncPtr,
+        1 => ByteCodeKind::DecPtr,
+        2 => ByteCodeKind::IncData,
+        3 => ByteCodeKind::DecData,
+        4 => ByteCodeKind::Read,
+        5 => ByteCodeKind::Write,
+        6 => ByteCodeKind::LoopStartJumpIfDataZero,
+        7 => ByteCodeKind::LoopEndJumpIfDataNotZero,
+        8 => ByteCodeKind::DebugDump,
+        _ => return None,
+    })
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    write_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn read_str(r: &mut Reader<'_>) -> Result<String, ObjectError> {
+    let len = r.usize()?;
+    let offset = r.offset;
+    let bytes = r.bytes(len)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| ObjectError::Corrupted(offset))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{machine::Machine, machine_io::BufferedMachineIO, source_file::Dialect};
+
+    fn compile(src: &str, with_source_map: bool) -> ObjectFile {
+        let src_file = UcSourceFile::from_str_with_dialect(src, "prog.bf", Dialect::Extended);
+        let config = MachineConfig {
+            tape_size: 100,
+            cell_width: CellWidth::U16,
+            eof: EofBehavior::MaxValue,
+        };
+        ObjectFile::compile(&src_file, config, OptLevel::O1, with_source_map).unwrap()
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_big_error(&ObjectError::BadJump(0));
+        is_big_value_struct_but_no_default(&compile("", false));
+    }
+
+    #[test]
+    fn round_trip() {
+        for with_source_map in [false, true] {
+            let object = compile("++[->+\n<]>.,.!ab", with_source_map);
+            let bytes = object.to_bytes();
+            assert!(ObjectFile::is_object(&bytes));
+            let decoded = ObjectFile::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded.input.as_deref(), Some("ab"));
+            assert_eq!(decoded.source_map, object.source_map);
+            assert_eq!(decoded.config, object.config);
+            assert_eq!(decoded.byte_codes.codes(), object.byte_codes.codes());
+            if with_source_map {
+                assert_eq!(decoded, object);
+                assert_eq!(
+                    decoded.source_map.unwrap().lookup(5).unwrap().start,
+                    SourcePos { line: 2, col: 1 }
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn runs_the_same_as_the_source() {
+        let src = "++++++[>++++++++<-]>+.+.";
+        let object = ObjectFile::from_bytes(&compile(src, false).to_bytes()).unwrap();
+        let mut machine = Machine::with_config(object.config, BufferedMachineIO::new(""));
+        machine.eval_dense_byte_codes(&object.byte_codes).unwrap();
+        assert_eq!(machine.io().output(), "12");
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let bytes = compile("+[>+<-]", true).to_bytes();
+        assert_eq!(ObjectFile::from_bytes(b"BFTR"), Err(ObjectError::BadMagic));
+        assert_eq!(
+            ObjectFile::from_bytes(b"BFC\0"),
+            Err(ObjectError::Truncated)
+        );
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = 9;
+        assert_eq!(
+            ObjectFile::from_bytes(&wrong_version),
+            Err(ObjectError::UnsupportedVersion(9))
+        );
+
+        for idx in 5..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[idx] ^= 0x10;
+            assert!(matches!(
+                ObjectFile::from_bytes(&flipped),
+                Err(ObjectError::ChecksumMismatch { .. })
+            ));
+        }
+        assert!(ObjectFile::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn byte_codes_and_tape_size_are_checked() {
+        let mut object = compile("+.", false);
+        object.config.tape_size = MachineConfig::MAX_TAPE_SIZE + 1;
+        assert!(matches!(
+            ObjectFile::from_bytes(&object.to_bytes()),
+            Err(ObjectError::Corrupted(_))
+        ));
+
+        // a well formed file with `.` repeated zero times
+        let mut object = compile("+.", false);
+        let mut byte_codes = (0..object.byte_codes.len())
+            .map(|idx| object.byte_codes.get(idx).unwrap())
+            .collect::<Vec<_>>();
+        byte_codes[1].arg = 0;
+        object.byte_codes = DenseByteCodes::from(byte_codes.as_slice());
+        let bytes = object.to_bytes();
+        // magic, version, flags, config, opt level, count and the first byte code
+        let offset = 4 + 1 + 1 + 3 + 1 + 1 + 2;
+        assert_eq!(bytes[offset], encode_kind(ByteCodeKind::Write));
+        assert_eq!(
+            ObjectFile::from_bytes(&bytes),
+            Err(ObjectError::Corrupted(offset))
+        );
+    }
+
+    #[test]
+    fn jumps_are_checked() {
+        // a well formed file with `[` jumping to itself
+        let mut object = compile("+[-]", false);
+        let mut byte_codes = (0..object.byte_codes.len())
+            .map(|idx| object.byte_codes.get(idx).unwrap())
+            .collect::<Vec<_>>();
+        byte_codes[1].arg = 1;
+        object.byte_codes = DenseByteCodes::from(byte_codes.as_slice());
+        assert_eq!(
+            ObjectFile::from_bytes(&object.to_bytes()),
+            Err(ObjectError::BadJump(1))
+        );
+
+        byte_codes.pop();
+        object.byte_codes = DenseByteCodes::from(byte_codes.as_slice());
+        assert_eq!(
+            ObjectFile::from_bytes(&object.to_bytes()),
+            Err(ObjectError::BadJump(1))
+        );
+    }
+}
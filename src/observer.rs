@@ -0,0 +1,342 @@
+//! hooks into a running `Machine`, picked at runtime rather than with cargo features
+
+use crate::machine::CellDataType;
+use std::time::{Duration, Instant};
+use thousands::Separable;
+
+/// what the machine is executing, the same for both engines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Op {
+    IncPtr,
+    DecPtr,
+    IncData,
+    DecData,
+    Read,
+    Write,
+    LoopStart,
+    LoopEnd,
+    /// `#` of `Dialect::Extended`
+    DebugDump,
+}
+
+impl Op {
+    pub fn all() -> &'static [Self] {
+        &[
+            Self::IncPtr,
+            Self::DecPtr,
+            Self::IncData,
+            Self::DecData,
+            Self::Read,
+            Self::Write,
+            Self::LoopStart,
+            Self::LoopEnd,
+            Self::DebugDump,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::IncPtr => "inc_ptr",
+            Self::DecPtr => "dec_ptr",
+            Self::IncData => "inc_data",
+            Self::DecData => "dec_data",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::LoopStart => "loop_start",
+            Self::LoopEnd => "loop_end",
+            Self::DebugDump => "debug_dump",
+        }
+    }
+
+    fn idx(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl std::fmt::Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// called by `Machine` while running, every hook does nothing unless overridden
+///
+/// `Machine` is generic over its observer, so the default `NoopObserver` compiles away
+pub trait Observer: std::fmt::Debug {
+    /// `arg` is how many times the instruction repeats, e.g. 3 for `+++` as one byte code
+    #[inline(always)]
+    fn before(&mut self, _op: Op, _arg: usize, _instr_ptr: usize) {}
+
+    /// not called if the instruction fails
+    #[inline(always)]
+    fn after(&mut self, _op: Op) {}
+
+    /// the cell at `data_ptr` changes from `old` to `new`, they can be the same
+    #[inline(always)]
+    fn on_cell_write(&mut self, _data_ptr: usize, _old: CellDataType, _new: CellDataType) {}
+
+    /// `c` is written `n` times
+    #[inline(always)]
+    fn on_output(&mut self, _c: char, _n: usize) {}
+
+    /// `None` at the end of input
+    #[inline(always)]
+    fn on_input(&mut self, _c: Option<char>) {}
+
+    /// `[` at `instr_ptr` runs the loop body, once for every iteration
+    #[inline(always)]
+    fn on_loop_entry(&mut self, _instr_ptr: usize) {}
+
+    /// `]` at `instr_ptr` leaves the loop
+    #[inline(always)]
+    fn on_loop_exit(&mut self, _instr_ptr: usize) {}
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
+
+/// counts executions of each `Op`
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct CountingObserver {
+    counts: [u64; 9],
+}
+
+impl CountingObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self, op: Op) -> u64 {
+        self.counts[op.idx()]
+    }
+
+    /// executed ops, the most frequent first
+    pub fn report(&self) -> String {
+        let mut ops = Op::all()
+            .iter()
+            .filter(|op| self.count(**op) > 0)
+            .collect::<Vec<_>>();
+        ops.sort_by_key(|op| std::cmp::Reverse(self.count(**op)));
+
+        let mut s = String::new();
+        for op in ops {
+            s.push_str(&format!(
+                "{:10}: {:>15}\n",
+                op,
+                self.count(*op).separate_with_commas()
+            ));
+        }
+        s.push_str(&format!("{:-<1$}\n", "", 27));
+        let total = self.counts.iter().sum::<u64>();
+        s.push_str(&format!(
+            "{:10}: {:>15}",
+            "TOTAL",
+            total.separate_with_commas()
+        ));
+        s
+    }
+}
+
+impl Observer for CountingObserver {
+    fn before(&mut self, op: Op, _arg: usize, _instr_ptr: usize) {
+        self.counts[op.idx()] += 1;
+    }
+}
+
+/// times each `Op`, including the overhead of taking the time
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct TimingObserver {
+    started: Option<Instant>,
+    counts: [u64; 9],
+    time: [Duration; 9],
+}
+
+impl Default for TimingObserver {
+    fn default() -> Self {
+        Self {
+            started: None,
+            counts: [0; 9],
+            time: [Duration::ZERO; 9],
+        }
+    }
+}
+
+impl TimingObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self, op: Op) -> u64 {
+        self.counts[op.idx()]
+    }
+
+    pub fn time(&self, op: Op) -> Duration {
+        self.time[op.idx()]
+    }
+
+    /// executed ops, the most time consuming first
+    pub fn report(&self) -> String {
+        let mut ops = Op::all()
+            .iter()
+            .filter(|op| self.count(**op) > 0)
+            .collect::<Vec<_>>();
+        ops.sort_by_key(|op| std::cmp::Reverse(self.time(**op)));
+
+        let mut s = String::new();
+        for op in ops {
+            let time = self.time(*op);
+            s.push_str(&format!(
+                "{:10}: mean ={:>7}ns | total ={:>12.3}ms | count ={:>15}\n",
+                op,
+                (time.as_nanos() / u128::from(self.count(*op))).separate_with_commas(),
+                time.as_secs_f64() * 1000.0,
+                self.count(*op).separate_with_commas()
+            ));
+        }
+        s.pop();
+        s
+    }
+}
+
+impl Observer for TimingObserver {
+    fn before(&mut self, _op: Op, _arg: usize, _instr_ptr: usize) {
+        self.started = Some(Instant::now());
+    }
+
+    fn after(&mut self, op: Op) {
+        if let Some(started) = self.started.take() {
+            self.counts[op.idx()] += 1;
+            self.time[op.idx()] += started.elapsed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        byte_code::OptLevel,
+        machine::{Engine, Machine, MachineConfig},
+        machine_io::BufferedMachineIO,
+        source_file::UcSourceFile,
+    };
+
+    /// remembers every hook call
+    #[derive(Debug, Default)]
+    struct RecordingObserver {
+        calls: Vec<String>,
+    }
+
+    impl Observer for RecordingObserver {
+        fn before(&mut self, op: Op, arg: usize, instr_ptr: usize) {
+            self.calls.push(format!("{} {}x @{}", op, arg, instr_ptr));
+        }
+        fn after(&mut self, op: Op) {
+            self.calls.push(format!("/{}", op));
+        }
+        fn on_cell_write(&mut self, data_ptr: usize, old: CellDataType, new: CellDataType) {
+            self.calls
+                .push(format!("cell {} {}->{}", data_ptr, old, new));
+        }
+        fn on_output(&mut self, c: char, n: usize) {
+            self.calls.push(format!("out {:?} {}x", c, n));
+        }
+        fn on_input(&mut self, c: Option<char>) {
+            self.calls.push(format!("in {:?}", c));
+        }
+        fn on_loop_entry(&mut self, instr_ptr: usize) {
+            self.calls.push(format!("enter @{}", instr_ptr));
+        }
+        fn on_loop_exit(&mut self, instr_ptr: usize) {
+            self.calls.push(format!("exit @{}", instr_ptr));
+        }
+    }
+
+    fn run<O: Observer>(src: &str, engine: Engine, input: &str, observer: O) -> O {
+        let src_file = UcSourceFile::from_str(src, "");
+        let io = BufferedMachineIO::new(input);
+        let mut machine = Machine::with_observer(MachineConfig::default(), io, observer);
+        machine.eval(&src_file, engine, OptLevel::O1).unwrap();
+        machine.into_observer()
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_small_value_enum(&Op::Read);
+        is_default_debug(&NoopObserver);
+        is_default_debug(&CountingObserver::new());
+        is_default_debug(&TimingObserver::new());
+    }
+
+    #[test]
+    fn hooks_are_called_in_order() {
+        let observer = run(
+            ",[.-]",
+            Engine::ByteCode,
+            "\u{2}",
+            RecordingObserver::default(),
+        );
+        assert_eq!(
+            observer.calls,
+            vec![
+                "read 1x @0",
+                "in Some('\\u{2}')",
+                "cell 30000 0->2",
+                "/read",
+                "loop_start 1x @1",
+                "enter @1",
+                "/loop_start",
+                "write 1x @2",
+                "out '\\u{2}' 1x",
+                "/write",
+                "dec_data 1x @3",
+                "cell 30000 2->1",
+                "/dec_data",
+                "loop_end 1x @4",
+                "/loop_end",
+                "loop_start 1x @1",
+                "enter @1",
+                "/loop_start",
+                "write 1x @2",
+                "out '\\u{1}' 1x",
+                "/write",
+                "dec_data 1x @3",
+                "cell 30000 1->0",
+                "/dec_data",
+                "loop_end 1x @4",
+                "exit @4",
+                "/loop_end",
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_are_the_same_for_both_engines() {
+        let src = "++[>+++<-]>.";
+        let by_source = run(src, Engine::Source, "", CountingObserver::new());
+        let by_byte_codes = run(src, Engine::ByteCode, "", CountingObserver::new());
+        assert_eq!(by_source.count(Op::LoopEnd), 2);
+        assert_eq!(by_source.count(Op::LoopStart), 2);
+        assert_eq!(by_byte_codes.count(Op::LoopEnd), 2);
+        // `+++` is one byte code
+        assert_eq!(by_source.count(Op::IncData), 8);
+        assert_eq!(by_byte_codes.count(Op::IncData), 3);
+
+        let report = by_source.report();
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "inc_data  :               8");
+        assert_eq!(lines.last(), Some(&"TOTAL     :              20"));
+    }
+
+    #[test]
+    fn timing_covers_executed_ops() {
+        let observer = run("+++[-]", Engine::Source, "", TimingObserver::new());
+        assert_eq!(observer.count(Op::DecData), 3);
+        assert_eq!(observer.count(Op::Write), 0);
+        assert_eq!(observer.report().lines().count(), 4);
+    }
+}
@@ -0,0 +1,449 @@
+//! attributes executions and time to each byte code, and through `range_in_raw` to
+//! source ranges, then aggregates them per loop
+
+use crate::{
+    byte_code::{ByteCode, ByteCodeKind},
+    machine::{Machine, RuntimeError},
+    machine_io::MachineIO,
+    observer::Observer,
+    source_file::{SourcePos, UcSourceFile},
+};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+/// collects raw numbers while running, indexed by byte code
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Profiler {
+    executions: Vec<u64>,
+    time: Vec<Duration>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// resets `machine` and runs `byte_codes` one at a time, timing each of them
+    ///
+    /// numbers collected before a runtime error are kept
+    pub fn run<IO: MachineIO, O: Observer>(
+        &mut self,
+        machine: &mut Machine<IO, O>,
+        byte_codes: &[ByteCode],
+    ) -> Result<(), RuntimeError> {
+        self.executions = vec![0; byte_codes.len()];
+        self.time = vec![Duration::ZERO; byte_codes.len()];
+
+        machine.reset();
+        loop {
+            let instr_ptr = machine.instr_ptr();
+            let start = Instant::now();
+            if !machine.step_byte_codes(byte_codes)? {
+                return Ok(());
+            }
+            self.time[instr_ptr] += start.elapsed();
+            self.executions[instr_ptr] += 1;
+        }
+    }
+
+    /// `byte_codes` must be the ones passed to `run`, and generated from `src_file`
+    pub fn profile(&self, src_file: &UcSourceFile, byte_codes: &[ByteCode]) -> Profile {
+        let byte_code_profiles = byte_codes
+            .iter()
+            .enumerate()
+            .map(|(idx, bc)| {
+                let executions = self.executions.get(idx).copied().unwrap_or_default();
+                let weight = match bc.kind {
+                    ByteCodeKind::LoopStartJumpIfDataZero
+                    | ByteCodeKind::LoopEndJumpIfDataNotZero => 1,
+                    _ => bc.arg as u64,
+                };
+                ByteCodeProfile {
+                    start: src_file.pos_of(bc.range_in_raw.0),
+                    end: src_file.pos_of(bc.range_in_raw.1),
+                    executions,
+                    commands: executions * weight,
+                    time: self.time.get(idx).copied().unwrap_or_default(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut loops = Vec::new();
+        let mut depth = 0;
+        for (idx, bc) in byte_codes.iter().enumerate() {
+            match bc.kind {
+                ByteCodeKind::LoopStartJumpIfDataZero => {
+                    depth += 1;
+                    let body = &byte_code_profiles[idx..=bc.arg];
+                    loops.push(LoopProfile {
+                        start: body[0].start,
+                        end: body[body.len() - 1].end,
+                        depth,
+                        byte_codes: (idx, bc.arg),
+                        iterations: body[body.len() - 1].executions,
+                        commands: body.iter().map(|p| p.commands).sum(),
+                        time: body.iter().map(|p| p.time).sum(),
+                    });
+                }
+                ByteCodeKind::LoopEndJumpIfDataNotZero => depth -= 1,
+                _ => (),
+            }
+        }
+
+        Profile {
+            total_time: byte_code_profiles.iter().map(|p| p.time).sum(),
+            total_commands: byte_code_profiles.iter().map(|p| p.commands).sum(),
+            byte_codes: byte_code_profiles,
+            loops,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct ByteCodeProfile {
+    pub start: SourcePos,
+    /// exclusive
+    pub end: SourcePos,
+    pub executions: u64,
+    /// `+++` executed once counts as three, the same as `Machine::executed`
+    pub commands: u64,
+    pub time: Duration,
+}
+
+/// numbers of a loop include those of its inner loops
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct LoopProfile {
+    /// position of `[`
+    pub start: SourcePos,
+    /// position right after `]`
+    pub end: SourcePos,
+    /// 1 for outermost loops
+    pub depth: usize,
+    /// indices of the byte codes of `[` and `]`
+    pub byte_codes: (usize, usize),
+    /// times the body runs to `]`
+    pub iterations: u64,
+    pub commands: u64,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Profile {
+    /// in the order of byte codes
+    pub byte_codes: Vec<ByteCodeProfile>,
+    /// in the order of `[`
+    pub loops: Vec<LoopProfile>,
+    pub total_commands: u64,
+    pub total_time: Duration,
+}
+
+impl LoopProfile {
+    /// frame name in exported stacks
+    pub fn name(&self) -> String {
+        format!("loop {}-{}", self.start, self.end)
+    }
+}
+
+/// frame at the bottom of exported stacks, for everything outside of loops
+const ROOT_FRAME: &str = "program";
+
+impl Profile {
+    /// calls `f` with each byte code index and the indices into `loops` of the loops
+    /// around it, `[` and `]` are in their loops
+    fn walk<F>(&self, mut f: F)
+    where
+        F: FnMut(usize, &[usize]),
+    {
+        let mut next_loop = 0;
+        let mut stack = Vec::new();
+        for idx in 0..self.byte_codes.len() {
+            if self.loops.get(next_loop).map(|l| l.byte_codes.0) == Some(idx) {
+                stack.push(next_loop);
+                next_loop += 1;
+            }
+            f(idx, &stack);
+            while stack.last().map(|&l| self.loops[l].byte_codes.1) == Some(idx) {
+                stack.pop();
+            }
+        }
+    }
+
+    /// one `frame;frame;frame nanoseconds` line per loop nesting, as consumed by
+    /// `flamegraph.pl` and `inferno-flamegraph`, time spent in inner loops is excluded
+    pub fn to_collapsed_stacks(&self) -> String {
+        // in the order of first appearances
+        let mut stacks: Vec<(Vec<usize>, Duration)> = Vec::new();
+        self.walk(
+            |idx, stack| match stacks.iter_mut().find(|(s, _)| s == stack) {
+                Some((_, time)) => *time += self.byte_codes[idx].time,
+                None => stacks.push((stack.to_vec(), self.byte_codes[idx].time)),
+            },
+        );
+
+        let mut s = String::new();
+        for (stack, time) in stacks.iter().filter(|(_, time)| !time.is_zero()) {
+            s.push_str(ROOT_FRAME);
+            for &l in stack {
+                s.push(';');
+                s.push_str(&self.loops[l].name());
+            }
+            s.push_str(&format!(" {}\n", time.as_nanos()));
+        }
+        s
+    }
+
+    /// Chrome `trace_event` json for `chrome://tracing` and Perfetto
+    ///
+    /// times are aggregated, so each loop is a single event as long as all its
+    /// iterations, laid out in source order inside the loop around it
+    pub fn to_chrome_trace(&self) -> String {
+        let event = |name: &str, ts: Duration, dur: Duration, args: Value| {
+            json!({
+                "name": name,
+                "ph": "X",
+                "pid": 1,
+                "tid": 1,
+                "ts": ts.as_secs_f64() * 1e6,
+                "dur": dur.as_secs_f64() * 1e6,
+                "args": args,
+            })
+        };
+        let mut events = vec![event(
+            ROOT_FRAME,
+            Duration::ZERO,
+            self.total_time,
+            json!({"commands": self.total_commands}),
+        )];
+
+        let mut now = Duration::ZERO;
+        // loops are entered in the order of `[`
+        let mut entered = 0;
+        self.walk(|idx, stack| {
+            if let Some(&innermost) = stack.last().filter(|&&l| l >= entered) {
+                let l = &self.loops[innermost];
+                let args = json!({"commands": l.commands, "iterations": l.iterations});
+                events.push(event(&l.name(), now, l.time, args));
+                entered = innermost + 1;
+            }
+            now += self.byte_codes[idx].time;
+        });
+
+        json!({"traceEvents": events, "displayTimeUnit": "ns"}).to_string()
+    }
+
+    /// at most `n` loops that take the most time, ties broken by executed commands
+    pub fn hot_loops(&self, n: usize) -> Vec<&LoopProfile> {
+        let mut loops = self.loops.iter().collect::<Vec<_>>();
+        loops.sort_by_key(|l| std::cmp::Reverse((l.time, l.commands)));
+        loops.truncate(n);
+        loops
+    }
+
+    /// a table of `hot_loops(n)`
+    pub fn hot_loop_report(&self, n: usize) -> String {
+        let hot_loops = self.hot_loops(n);
+        let mut s = format!(
+            "{} hottest of {} loops, times include inner loops\n",
+            hot_loops.len(),
+            self.loops.len()
+        );
+        s.push_str(&format!(
+            "{:>12} {:>7} {:>14} {:>12} {:>5}  location\n",
+            "time", "share", "commands", "iterations", "depth"
+        ));
+        for l in hot_loops {
+            let share = if self.total_time.is_zero() {
+                0.0
+            } else {
+                l.time.as_secs_f64() / self.total_time.as_secs_f64() * 100.0
+            };
+            s.push_str(&format!(
+                "{:>10.3}ms {:>6.1}% {:>14} {:>12} {:>5}  {}-{}\n",
+                l.time.as_secs_f64() * 1000.0,
+                share,
+                l.commands,
+                l.iterations,
+                l.depth,
+                l.start,
+                l.end
+            ));
+        }
+        s.push_str(&format!(
+            "total: {:.3}ms, {} commands",
+            self.total_time.as_secs_f64() * 1000.0,
+            self.total_commands
+        ));
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{byte_code::OptLevel, machine_io::BufferedMachineIO};
+
+    fn pos(line: usize, col: usize) -> SourcePos {
+        SourcePos { line, col }
+    }
+
+    fn profile(src: &str, opt_level: OptLevel) -> Profile {
+        let src_file = UcSourceFile::from_str(src, "");
+        let byte_codes = src_file.to_byte_codes_with(opt_level).unwrap();
+        let mut machine = Machine::with_io(100, BufferedMachineIO::new(""));
+        let mut profiler = Profiler::new();
+        profiler.run(&mut machine, &byte_codes).unwrap();
+        let profile = profiler.profile(&src_file, &byte_codes);
+        assert_eq!(profile.total_commands, machine.executed());
+        profile
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_default_debug(&Profiler::new());
+        is_big_value_struct_but_no_default(&profile("+", OptLevel::O1));
+    }
+
+    #[test]
+    fn counts_are_attributed_to_source_ranges() {
+        for opt_level in [OptLevel::O0, OptLevel::O1] {
+            let profile = profile("+++\n[>++<-]", opt_level);
+            let first = profile.byte_codes[0];
+            assert_eq!(first.start, pos(1, 1));
+            assert_eq!(first.executions, 1);
+
+            let dec = profile.byte_codes[profile.byte_codes.len() - 2];
+            assert_eq!((dec.start, dec.end), (pos(2, 6), pos(2, 7)));
+            assert_eq!(dec.executions, 3);
+        }
+
+        let profile = profile("+++\n[>++<-]", OptLevel::O1);
+        let inc = profile.byte_codes[3];
+        assert_eq!((inc.start, inc.end), (pos(2, 3), pos(2, 5)));
+        assert_eq!((inc.executions, inc.commands), (3, 6));
+        assert!(profile.total_time >= inc.time);
+    }
+
+    #[test]
+    fn loops_include_inner_loops() {
+        let profile = profile("++[>+++[>+<-]<-]", OptLevel::O1);
+        assert_eq!(profile.loops.len(), 2);
+
+        let (outer, inner) = (profile.loops[0], profile.loops[1]);
+        assert_eq!(
+            (outer.start, outer.end, outer.depth),
+            (pos(1, 3), pos(1, 17), 1)
+        );
+        assert_eq!(
+            (inner.start, inner.end, inner.depth),
+            (pos(1, 8), pos(1, 14), 2)
+        );
+        assert_eq!((outer.iterations, inner.iterations), (2, 6));
+        // everything but the leading `++`
+        assert_eq!(outer.commands, profile.total_commands - 2);
+        assert!(outer.commands > inner.commands);
+        assert!(outer.time >= inner.time);
+    }
+
+    #[test]
+    fn runtime_errors_keep_numbers_so_far() {
+        let src_file = UcSourceFile::from_str("++<<+", "");
+        let byte_codes = src_file.to_byte_codes_with(OptLevel::O0).unwrap();
+        let mut machine = Machine::with_io(2, BufferedMachineIO::new(""));
+        let mut profiler = Profiler::new();
+        assert!(profiler.run(&mut machine, &byte_codes).is_err());
+
+        let profile = profiler.profile(&src_file, &byte_codes);
+        let executions = profile.byte_codes.iter().map(|p| p.executions);
+        assert_eq!(executions.collect::<Vec<_>>(), vec![1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn hot_loop_report() {
+        let make_loop = |line, ms, commands| LoopProfile {
+            start: pos(line, 1),
+            end: pos(line, 4),
+            depth: 1,
+            byte_codes: (0, 1),
+            iterations: 2,
+            commands,
+            time: Duration::from_millis(ms),
+        };
+        let profile = Profile {
+            byte_codes: Vec::new(),
+            loops: vec![
+                make_loop(1, 1, 10),
+                make_loop(2, 3, 30),
+                make_loop(3, 1, 20),
+            ],
+            total_commands: 60,
+            total_time: Duration::from_millis(5),
+        };
+
+        let hot = profile.hot_loops(2);
+        assert_eq!(hot[0].start.line, 2);
+        assert_eq!(hot[1].start.line, 3);
+
+        let report = profile.hot_loop_report(2);
+        let lines = report.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "2 hottest of 3 loops, times include inner loops");
+        assert_eq!(
+            lines[2],
+            "     3.000ms   60.0%             30            2     1  2:1-2:4"
+        );
+        assert_eq!(lines[4], "total: 5.000ms, 60 commands");
+    }
+
+    /// every byte code takes 1µs
+    fn with_fixed_times(mut profile: Profile) -> Profile {
+        let us = Duration::from_micros(1);
+        profile.byte_codes.iter_mut().for_each(|p| p.time = us);
+        for l in profile.loops.iter_mut() {
+            l.time = us * (l.byte_codes.1 - l.byte_codes.0 + 1) as u32;
+        }
+        profile.total_time = us * profile.byte_codes.len() as u32;
+        profile
+    }
+
+    #[test]
+    fn collapsed_stacks_exclude_inner_loops() {
+        let profile = with_fixed_times(profile("+[>[-]<[-]]>[-]", OptLevel::O1));
+        assert_eq!(
+            profile.to_collapsed_stacks(),
+            "program 2000\n\
+             program;loop 1:2-1:12 4000\n\
+             program;loop 1:2-1:12;loop 1:4-1:7 3000\n\
+             program;loop 1:2-1:12;loop 1:8-1:11 3000\n\
+             program;loop 1:13-1:16 3000\n"
+        );
+    }
+
+    #[test]
+    fn chrome_trace_nests_loops() {
+        let profile = with_fixed_times(profile("+[>[-]<[-]]>[-]", OptLevel::O1));
+        let trace: Value = serde_json::from_str(&profile.to_chrome_trace()).unwrap();
+        let events = trace["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                assert_eq!(e["ph"], "X");
+                let name = e["name"].as_str().unwrap().to_owned();
+                (name, e["ts"].as_f64().unwrap(), e["dur"].as_f64().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let oracle = [
+            ("program", 0.0, 15.0),
+            ("loop 1:2-1:12", 1.0, 10.0),
+            ("loop 1:4-1:7", 3.0, 3.0),
+            ("loop 1:8-1:11", 7.0, 3.0),
+            ("loop 1:13-1:16", 12.0, 3.0),
+        ];
+        assert_eq!(events.len(), oracle.len());
+        for ((name, ts, dur), oracle) in events.iter().zip(oracle) {
+            assert_eq!((name.as_str(), *ts, *dur), oracle);
+        }
+        assert_eq!(trace["traceEvents"][1]["args"]["iterations"], 1);
+    }
+}
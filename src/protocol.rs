@@ -0,0 +1,89 @@
+//! `Content-Length` framed json messages, as spoken by debug adapters and language servers
+
+use std::io::{BufRead, Write};
+
+fn invalid_data(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// `None` when `r` is closed before a new message starts
+pub fn read_message<R: BufRead>(r: &mut R) -> std::io::Result<Option<serde_json::Value>> {
+    let mut content_length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return match content_length {
+                None => Ok(None),
+                Some(_) => Err(invalid_data(String::from("message ends in headers"))),
+            };
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                let len = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| invalid_data(format!("bad Content-Length `{}`", value.trim())))?;
+                content_length = Some(len);
+            }
+        }
+    }
+
+    let len = content_length.ok_or_else(|| invalid_data(String::from("no Content-Length")))?;
+    let mut body = vec![0u8; len];
+    r.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid_data(e.to_string()))
+}
+
+pub fn write_message<W: Write>(w: &mut W, msg: &serde_json::Value) -> std::io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &json!({"seq": 1, "text": "é"})).unwrap();
+        write_message(&mut buf, &json!([])).unwrap();
+        assert!(buf.starts_with(b"Content-Length: 21\r\n\r\n{"));
+
+        let mut r = buf.as_slice();
+        assert_eq!(
+            read_message(&mut r).unwrap(),
+            Some(json!({"seq": 1, "text": "é"}))
+        );
+        assert_eq!(read_message(&mut r).unwrap(), Some(json!([])));
+        assert_eq!(read_message(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn other_headers_are_ignored() {
+        let msg = "Content-Type: application/json\r\ncontent-length: 2\r\n\r\n{}";
+        assert_eq!(read_message(&mut msg.as_bytes()).unwrap(), Some(json!({})));
+    }
+
+    #[test]
+    fn bad_messages() {
+        for msg in [
+            "Content-Length: x\r\n\r\n{}",
+            "\r\n{}",
+            "Content-Length: 2\r\n",
+            "Content-Length: 2\r\n\r\n{",
+            "Content-Length: 2\r\n\r\n[}",
+        ] {
+            assert!(read_message(&mut msg.as_bytes()).is_err(), "{:?}", msg);
+        }
+    }
+}
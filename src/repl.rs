@@ -0,0 +1,167 @@
+use crate::{
+    machine::{Machine, RuntimeError, TapeWindow},
+    machine_io::MachineIO,
+    source_file::UcSourceFile,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Feed {
+    /// there are unclosed `[`, more lines are needed before anything can run
+    Pending,
+    Executed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum ReplError {
+    /// the fragment does not parse, the message says why
+    Source(String),
+    /// the tape keeps whatever the fragment did before failing, the data pointer goes back
+    /// to where it was before the fragment
+    Runtime(RuntimeError),
+}
+
+impl std::fmt::Display for ReplError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Source(e) => f.write_str(e),
+            Self::Runtime(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ReplError {}
+
+/// runs source fragments one after another on the same machine, the tape and the data
+/// pointer are kept between fragments
+#[derive(Debug)]
+pub struct Repl<IO> {
+    machine: Machine<IO>,
+    /// lines fed so far that are still waiting for their `]`
+    pending: String,
+}
+
+impl<IO: MachineIO> Repl<IO> {
+    pub fn new(machine: Machine<IO>) -> Self {
+        Self {
+            machine,
+            pending: String::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine<IO> {
+        &self.machine
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// feed one line, it runs once all its loops are closed, possibly together with the
+    /// lines before it
+    ///
+    /// a pending fragment is dropped if the line does not parse
+    pub fn feed_line(&mut self, line: &str) -> Result<Feed, ReplError> {
+        self.pending.push_str(line);
+        self.pending.push('\n');
+
+        let src_file = UcSourceFile::from_str(&self.pending, "<repl>");
+        let mut depth = 0usize;
+        for token in &src_file {
+            match token.uc.as_str() {
+                "[" => depth += 1,
+                "]" if depth == 0 => break,
+                "]" => depth -= 1,
+                _ => (),
+            }
+        }
+        if depth > 0 {
+            return Ok(Feed::Pending);
+        }
+        self.pending.clear();
+
+        let byte_codes = src_file
+            .to_byte_codes()
+            .map_err(|e| ReplError::Source(e.to_string()))?;
+        let data_ptr = self.machine.data_ptr();
+        if let Err(e) = self.machine.continue_byte_codes(&byte_codes) {
+            self.machine.set_data_ptr(data_ptr);
+            return Err(ReplError::Runtime(e));
+        }
+        Ok(Feed::Executed)
+    }
+
+    /// drop pending lines and clear the tape
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.machine.reset();
+    }
+
+    pub fn tape_window(&self, radius: usize) -> TapeWindow {
+        self.machine.tape_window(radius)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::machine_io::BufferedMachineIO;
+
+    fn create_repl(input: &str) -> Repl<BufferedMachineIO> {
+        Repl::new(Machine::with_io(10, BufferedMachineIO::new(input)))
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_debug(&create_repl(""));
+        is_small_value_enum(&Feed::Pending);
+        is_big_error(&ReplError::Source(String::new()));
+    }
+
+    #[test]
+    fn tape_is_kept_between_lines() {
+        let mut repl = create_repl("A");
+        assert_eq!(repl.feed_line("+++ >"), Ok(Feed::Executed));
+        assert_eq!(repl.feed_line(",."), Ok(Feed::Executed));
+        assert_eq!(repl.feed_line("<[->+<]"), Ok(Feed::Executed));
+        assert_eq!(repl.machine().data_ptr(), 5);
+        assert_eq!(repl.machine().cells()[5..7], [0, 68]);
+        assert_eq!(repl.machine().io().output(), "A");
+    }
+
+    #[test]
+    fn unclosed_loops_wait_for_more_lines() {
+        let mut repl = create_repl("");
+        assert_eq!(repl.feed_line("++++[>"), Ok(Feed::Pending));
+        assert!(repl.is_pending());
+        assert_eq!(repl.feed_line("++[>+<-]"), Ok(Feed::Pending));
+        assert_eq!(repl.feed_line("<-]"), Ok(Feed::Executed));
+        assert!(!repl.is_pending());
+        assert_eq!(repl.machine().cells()[5..8], [0, 0, 8]);
+    }
+
+    #[test]
+    fn errors_do_not_stop_the_repl() {
+        let mut repl = create_repl("");
+        assert_eq!(repl.feed_line("[+"), Ok(Feed::Pending));
+        assert!(matches!(repl.feed_line("]]"), Err(ReplError::Source(_))));
+        assert!(!repl.is_pending());
+
+        assert_eq!(repl.feed_line("+>"), Ok(Feed::Executed));
+        assert!(matches!(
+            repl.feed_line("<<<<<<<+"),
+            Err(ReplError::Runtime(RuntimeError::DataPtrOutOfRange { .. }))
+        ));
+        // the data pointer is back on the tape, cells changed before failing are kept
+        assert_eq!(repl.machine().data_ptr(), 6);
+        assert_eq!(repl.feed_line("+"), Ok(Feed::Executed));
+        assert_eq!(repl.machine().cells()[5..7], [1, 1]);
+
+        repl.reset();
+        assert_eq!(repl.machine().data_ptr(), 5);
+        assert_eq!(repl.machine().cells(), [0; 10]);
+        assert_eq!(repl.feed_line("+"), Ok(Feed::Executed));
+        assert_eq!(repl.tape_window(0).cells, vec![1]);
+    }
+}
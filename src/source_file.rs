@@ -1,5 +1,5 @@
 use crate::{
-    byte_code::{ByteCode, ByteCodeKind},
+    byte_code::{ByteCode, ByteCodeKind, OptLevel},
     utility::{populate_loop_boundaries, ExtraParen},
 };
 use smol_str::SmolStr;
@@ -14,7 +14,7 @@ pub enum UcSourceFileError<'src_file> {
     },
     UnmatchedParen {
         src_file: &'src_file UcSourceFile,
-        details: ExtraParen,
+        details: Vec<ExtraParen>,
     },
 }
 impl<'src_file> std::fmt::Display for UcSourceFileError<'src_file> {
@@ -23,11 +23,16 @@ impl<'src_file> std::fmt::Display for UcSourceFileError<'src_file> {
             Self::FileFailToRead { path, reason } => {
                 write!(f, "failed to read {}, {}", path.display(), reason)
             }
-            Self::UnmatchedParen {
-                src_file: _src_file,
-                details: _details,
-            } => {
-                write!(f, "unmatched paren")
+            Self::UnmatchedParen { src_file, details } => {
+                write!(f, "{} unmatched paren(s):", details.len())?;
+                for e in details {
+                    let pos = src_file.pos_of(src_file[UcContentIndex::new(e.idx())].idx_in_raw);
+                    match e {
+                        ExtraParen::Open { .. } => write!(f, " `[` at {}", pos)?,
+                        ExtraParen::Close { .. } => write!(f, " `]` at {}", pos)?,
+                    }
+                }
+                Ok(())
             }
         }
     }
@@ -46,7 +51,7 @@ impl<'a> IntoIterator for &'a UcSourceFile {
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
 pub struct UcToken {
-    idx_in_raw: RawContentIndex,
+    pub(crate) idx_in_raw: RawContentIndex,
     pub(crate) uc: SmolStr,
 }
 #[cfg(test)]
@@ -57,18 +62,66 @@ fn make_mock_raw_token() -> UcToken {
     }
 }
 
+impl UcToken {
+    /// the grapheme
+    pub fn as_str(&self) -> &str {
+        &self.uc
+    }
+
+    /// where the grapheme starts in the source file
+    pub fn idx_in_raw(&self) -> RawContentIndex {
+        self.idx_in_raw
+    }
+
+    /// one of the eight commands, everything else is comment
+    pub(crate) fn is_command(&self) -> bool {
+        matches!(
+            self.uc.as_str(),
+            "+" | "-" | ">" | "<" | "." | "," | "[" | "]"
+        )
+    }
+}
+
 pub type UcTokens = Vec<UcToken>;
 
+/// byte offset in the content of a source file as it is read
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
-pub(crate) struct RawContentIndex(usize);
+pub struct RawContentIndex(usize);
 
 impl RawContentIndex {
-    pub(crate) fn new(n: usize) -> Self {
+    pub fn new(n: usize) -> Self {
         Self(n)
     }
     pub(crate) fn inc_from(self, n: usize) -> Self {
         Self(self.0 + n)
     }
+    pub fn get(&self) -> usize {
+        self.0
+    }
+}
+
+/// 1-based line and column, column is counted in graphemes
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    std::hash::Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct SourcePos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Display for SourcePos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
 }
 
 impl std::ops::Index<UcContentIndex> for UcSourceFile {
@@ -79,26 +132,66 @@ impl std::ops::Index<UcContentIndex> for UcSourceFile {
     }
 }
 
+/// index of a token of a source file, one for each grapheme
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
-pub(crate) struct UcContentIndex(usize);
+pub struct UcContentIndex(usize);
 
 impl UcContentIndex {
-    pub(crate) fn new(n: usize) -> Self {
+    pub fn new(n: usize) -> Self {
         Self(n)
     }
     pub(crate) fn inc_from(self, n: usize) -> Self {
         Self(self.0 + n)
     }
-    pub(crate) fn get(&self) -> usize {
+    pub fn get(&self) -> usize {
         self.0
     }
 }
 
+/// which commands besides the standard eight are understood
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum Dialect {
+    /// everything else is comment
+    #[default]
+    Standard,
+    /// `#` dumps the tape through the debug sink of the machine, and everything after the
+    /// first `!` is the input of the program rather than code
+    Extended,
+}
+
+impl std::fmt::Display for Dialect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Standard => f.write_str("standard"),
+            Self::Extended => f.write_str("extended"),
+        }
+    }
+}
+
+impl std::str::FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(Self::Standard),
+            "extended" => Ok(Self::Extended),
+            _ => Err(format!(
+                "unknown dialect `{}`, expecting standard or extended",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
 pub struct UcSourceFile {
     filename: PathBuf,
     raw_content: String,
+    /// tokens of the code only, the embedded input of `Dialect::Extended` is not lexed
     uc_content: UcTokens,
+    /// raw indices where each line starts
+    line_starts: Vec<usize>,
+    dialect: Dialect,
 }
 #[cfg(test)]
 pub(crate) fn make_mock_src_file() -> UcSourceFile {
@@ -106,26 +199,97 @@ pub(crate) fn make_mock_src_file() -> UcSourceFile {
         filename: std::path::PathBuf::new(),
         raw_content: String::new(),
         uc_content: UcTokens::new(),
+        line_starts: vec![0],
+        dialect: Dialect::Standard,
     }
 }
 
 impl UcSourceFile {
     pub fn new<'src_file, P: AsRef<Path>>(path: P) -> Result<Self, UcSourceFileError<'src_file>> {
+        Self::new_with_dialect(path, Dialect::Standard)
+    }
+    pub fn new_with_dialect<'src_file, P: AsRef<Path>>(
+        path: P,
+        dialect: Dialect,
+    ) -> Result<Self, UcSourceFileError<'src_file>> {
         let raw =
             std::fs::read_to_string(&path).map_err(|e| UcSourceFileError::FileFailToRead {
                 path: path.as_ref().to_path_buf(),
                 reason: e.to_string(),
             })?;
-        Ok(Self::from_str(raw, path))
+        Ok(Self::from_str_with_dialect(raw, path, dialect))
     }
-    pub(crate) fn from_str<S: AsRef<str>, P: AsRef<Path>>(s: S, pseudo_filename: P) -> Self {
+    pub fn from_str<S: AsRef<str>, P: AsRef<Path>>(s: S, pseudo_filename: P) -> Self {
+        Self::from_str_with_dialect(s, pseudo_filename, Dialect::Standard)
+    }
+    pub fn from_str_with_dialect<S: AsRef<str>, P: AsRef<Path>>(
+        s: S,
+        pseudo_filename: P,
+        dialect: Dialect,
+    ) -> Self {
+        let raw = s.as_ref();
+        let code = match (dialect, raw.find('!')) {
+            (Dialect::Extended, Some(bang)) => &raw[..bang],
+            _ => raw,
+        };
         Self {
             filename: pseudo_filename.as_ref().to_path_buf(),
-            raw_content: s.as_ref().to_owned(),
-            uc_content: Self::lex(s.as_ref()),
+            raw_content: raw.to_owned(),
+            uc_content: Self::lex(code),
+            line_starts: Self::line_starts(raw),
+            dialect,
         }
     }
 
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// everything after the first `!` in `Dialect::Extended`
+    pub fn embedded_input(&self) -> Option<&str> {
+        match self.dialect {
+            Dialect::Standard => None,
+            Dialect::Extended => self
+                .raw_content
+                .find('!')
+                .map(|bang| &self.raw_content[bang + 1..]),
+        }
+    }
+
+    fn line_starts(raw: &str) -> Vec<usize> {
+        std::iter::once(0)
+            .chain(raw.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect()
+    }
+
+    /// line and column of the grapheme starts at `idx_in_raw`
+    pub fn pos_of(&self, idx_in_raw: RawContentIndex) -> SourcePos {
+        let idx_in_raw = idx_in_raw.get().min(self.raw_content.len());
+        let line = self.line_starts.partition_point(|&e| e <= idx_in_raw);
+        let line_start = self.line_starts[line - 1];
+        let col = UnicodeSegmentation::graphemes(&self.raw_content[line_start..idx_in_raw], true)
+            .count()
+            + 1;
+        SourcePos { line, col }
+    }
+    /// text of the 1-based `line`, without the line break
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.raw_content.len(), |&next| next - 1);
+        Some(self.raw_content[start..end].trim_end_matches('\r'))
+    }
+
+    pub fn filename(&self) -> &Path {
+        &self.filename
+    }
+
+    pub fn raw_content(&self) -> &str {
+        &self.raw_content
+    }
+
     fn lex<S: AsRef<str>>(raw: S) -> UcTokens {
         UnicodeSegmentation::grapheme_indices(raw.as_ref(), true)
             .map(|(idx, uc)| UcToken {
@@ -142,7 +306,26 @@ impl UcSourceFile {
             assert_eq!(end_offset, start_offset + 1); // only one char token in bf
         }
     */
-    pub fn to_byte_codes(&self) -> Result<Vec<ByteCode>, UcSourceFileError> {
+    /// report all unmatched parens, with indices into the unicode tokens
+    pub fn check_parens(&self) -> Result<(), UcSourceFileError<'_>> {
+        populate_loop_boundaries(self.iter()).map_err(|e| UcSourceFileError::UnmatchedParen {
+            src_file: self,
+            details: e,
+        })?;
+        Ok(())
+    }
+
+    pub fn to_byte_codes(&self) -> Result<Vec<ByteCode>, UcSourceFileError<'_>> {
+        self.to_byte_codes_with(OptLevel::default())
+    }
+
+    pub fn to_byte_codes_with(
+        &self,
+        opt_level: OptLevel,
+    ) -> Result<Vec<ByteCode>, UcSourceFileError<'_>> {
+        // check on tokens rather than byte codes, so errors refer to `UcContentIndex`
+        self.check_parens()?;
+
         let mut byte_codes = Vec::with_capacity(self.len());
         let symbols = std::collections::HashMap::from([
             ("+", ByteCodeKind::IncData),
@@ -159,10 +342,13 @@ impl UcSourceFile {
             let idx_in_ucs_fwd = match self[idx_in_ucs].uc.as_str() {
                 s if symbols.contains_key(s) => {
                     // TODO: not happy with exposing uc_content
-                    let arg = self.uc_content[idx_in_ucs.0..]
-                        .iter()
-                        .position(|e| e.uc != s)
-                        .unwrap_or(1);
+                    let arg = match opt_level {
+                        OptLevel::O0 => 1,
+                        OptLevel::O1 => self.uc_content[idx_in_ucs.0..]
+                            .iter()
+                            .position(|e| e.uc != s)
+                            .unwrap_or(self.len() - idx_in_ucs.get()),
+                    };
                     byte_codes.push(ByteCode::make_non_jump_code(
                         *symbols.get(s).unwrap(),
                         idx_in_raw,
@@ -184,17 +370,22 @@ impl UcSourceFile {
                     ));
                     1
                 }
+                // never merged, each one is a dump of its own
+                "#" if self.dialect == Dialect::Extended => {
+                    byte_codes.push(ByteCode::make_non_jump_code(
+                        ByteCodeKind::DebugDump,
+                        idx_in_raw,
+                        1,
+                    ));
+                    1
+                }
                 _ => 1,
             };
             idx_in_ucs = UcContentIndex::inc_from(idx_in_ucs, idx_in_ucs_fwd);
         }
 
-        let loop_matches = populate_loop_boundaries(byte_codes.iter()).map_err(|e| {
-            UcSourceFileError::UnmatchedParen {
-                src_file: self,
-                details: e,
-            }
-        })?;
+        let loop_matches = populate_loop_boundaries(byte_codes.iter())
+            .expect("parens have been checked on tokens");
 
         for (idx_in_ucs, bc) in byte_codes.iter_mut().enumerate() {
             match bc.kind {
@@ -253,6 +444,7 @@ mod test {
         is_big_value_struct_but_no_default(&make_mock_raw_token());
 
         is_small_value_struct_but_no_default(&RawContentIndex::new(0));
+        is_small_value_struct_but_no_default(&SourcePos { line: 1, col: 1 });
         is_small_value_struct_but_no_default(&UcContentIndex::new(0));
     }
 
@@ -276,6 +468,128 @@ mod test {
         assert_eq!(s, content);
     }
 
+    #[test]
+    fn src_file_to_byte_codes_reports_all_unmatched_parens() {
+        let src_file = &UcSourceFile::from_str("a]+[[-]b]]\n[", "");
+        let err = src_file.to_byte_codes().unwrap_err();
+        assert_eq!(
+            err,
+            UcSourceFileError::UnmatchedParen {
+                src_file,
+                details: vec![
+                    ExtraParen::Close { idx: 1 },
+                    ExtraParen::Close { idx: 9 },
+                    ExtraParen::Open { idx: 11 },
+                ],
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "3 unmatched paren(s): `]` at 1:2 `]` at 1:10 `[` at 2:1"
+        );
+    }
+
+    #[test]
+    fn dialect_round_trip() {
+        for dialect in [Dialect::Standard, Dialect::Extended] {
+            assert_eq!(dialect.to_string().parse(), Ok(dialect));
+        }
+        assert!("gnu".parse::<Dialect>().is_err());
+    }
+
+    #[test]
+    fn extended_dialect() {
+        let src = "+#[-]!#,\n!";
+        let src_file = UcSourceFile::from_str(src, "");
+        assert_eq!(src_file.dialect(), Dialect::Standard);
+        assert_eq!(src_file.embedded_input(), None);
+        assert_eq!(src_file.len(), 10);
+        assert_eq!(src_file.to_byte_codes().unwrap().len(), 5);
+
+        let src_file = UcSourceFile::from_str_with_dialect(src, "", Dialect::Extended);
+        assert_eq!(src_file.embedded_input(), Some("#,\n!"));
+        assert_eq!(src_file.len(), 5);
+        let byte_codes = src_file.to_byte_codes().unwrap();
+        assert_eq!(byte_codes.len(), 5);
+        assert_eq!(byte_codes[1].kind, ByteCodeKind::DebugDump);
+        assert_eq!(byte_codes[1].range_in_raw.0.get(), 1);
+
+        // no `!`, no input
+        let src_file = UcSourceFile::from_str_with_dialect("##", "", Dialect::Extended);
+        assert_eq!(src_file.embedded_input(), None);
+        assert_eq!(src_file.to_byte_codes_with(OptLevel::O1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn lines_of_source() {
+        let src_file = UcSourceFile::from_str("+é\r\n\n[-]", "");
+        assert_eq!(src_file.line(0), None);
+        assert_eq!(src_file.line(1), Some("+é"));
+        assert_eq!(src_file.line(2), Some(""));
+        assert_eq!(src_file.line(3), Some("[-]"));
+        assert_eq!(src_file.line(4), None);
+    }
+
+    #[test]
+    fn pos_of_raw_index() {
+        let src_file = UcSourceFile::from_str("+a\u{310}\u{e9}\n\n.\r\n-]", "");
+        let test_data = [
+            (0, (1, 1)),
+            (1, (1, 2)),
+            (4, (1, 3)),
+            (6, (1, 4)),
+            (7, (2, 1)),
+            (8, (3, 1)),
+            (9, (3, 2)),
+            (11, (4, 1)),
+            (12, (4, 2)),
+        ];
+        for (idx, (line, col)) in test_data {
+            assert_eq!(
+                src_file.pos_of(RawContentIndex::new(idx)),
+                SourcePos { line, col },
+                "idx: {}",
+                idx
+            );
+        }
+    }
+
+    #[test]
+    fn src_file_to_byte_codes_without_merging() {
+        let src_file = &UcSourceFile::from_str("++>>", "");
+        let byte_code = src_file.to_byte_codes_with(OptLevel::O0).unwrap();
+        assert_eq!(
+            byte_code,
+            vec![
+                ByteCode::make_non_jump_code(ByteCodeKind::IncData, RawContentIndex::new(0), 1),
+                ByteCode::make_non_jump_code(ByteCodeKind::IncData, RawContentIndex::new(1), 1),
+                ByteCode::make_non_jump_code(ByteCodeKind::IncPtr, RawContentIndex::new(2), 1),
+                ByteCode::make_non_jump_code(ByteCodeKind::IncPtr, RawContentIndex::new(3), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn src_file_to_byte_codes_merges_a_run_at_the_end() {
+        let src_file = &UcSourceFile::from_str("+++", "");
+        assert_eq!(
+            src_file.to_byte_codes().unwrap(),
+            vec![ByteCode::make_non_jump_code(
+                ByteCodeKind::IncData,
+                RawContentIndex::new(0),
+                3
+            )]
+        );
+
+        let src_file = &UcSourceFile::from_str("-[-]>>", "");
+        let byte_codes = src_file.to_byte_codes().unwrap();
+        assert_eq!(byte_codes.len(), 5);
+        assert_eq!(
+            byte_codes[4],
+            ByteCode::make_non_jump_code(ByteCodeKind::IncPtr, RawContentIndex::new(4), 2)
+        );
+    }
+
     #[test]
     fn src_file_to_byte_codes() {
         use pretty_assertions_sorted::assert_eq;
@@ -0,0 +1,137 @@
+use crate::{
+    byte_code::ByteCode,
+    source_file::{SourcePos, UcSourceFile},
+};
+use serde::{Deserialize, Serialize};
+
+/// maps positions in the output of a compile step back to the original source file
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "source": "hello.bf",
+///   "target": "byte_codes",
+///   "mappings": [
+///     { "output": 0, "start": { "line": 1, "col": 1 }, "end": { "line": 1, "col": 9 },
+///       "raw_start": 0, "raw_end": 8 }
+///   ]
+/// }
+/// ```
+///
+/// `output` is the index of the generated item, e.g. byte code index for `byte_codes`,
+/// `end` and `raw_end` are exclusive
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash, Serialize, Deserialize)]
+pub struct SourceMap {
+    pub version: u32,
+    pub source: String,
+    pub target: String,
+    pub mappings: Vec<Mapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash, Serialize, Deserialize)]
+pub struct Mapping {
+    pub output: usize,
+    pub start: SourcePos,
+    pub end: SourcePos,
+    /// byte offset in the source file
+    pub raw_start: usize,
+    /// byte offset in the source file
+    pub raw_end: usize,
+}
+
+impl SourceMap {
+    pub const VERSION: u32 = 1;
+
+    /// `byte_codes` must be generated from `src_file`
+    pub fn from_byte_codes(src_file: &UcSourceFile, byte_codes: &[ByteCode]) -> Self {
+        let mappings = byte_codes
+            .iter()
+            .enumerate()
+            .map(|(output, bc)| Mapping {
+                output,
+                start: src_file.pos_of(bc.range_in_raw.0),
+                end: src_file.pos_of(bc.range_in_raw.1),
+                raw_start: bc.range_in_raw.0.get(),
+                raw_end: bc.range_in_raw.1.get(),
+            })
+            .collect();
+        Self {
+            version: Self::VERSION,
+            source: src_file.filename().display().to_string(),
+            target: String::from("byte_codes"),
+            mappings,
+        }
+    }
+
+    /// the mapping of the `output`th generated item
+    pub fn lookup(&self, output: usize) -> Option<&Mapping> {
+        self.mappings
+            .binary_search_by_key(&output, |m| m.output)
+            .ok()
+            .map(|idx| &self.mappings[idx])
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        let src_file = UcSourceFile::from_str("", "");
+        is_big_value_struct_but_no_default(&SourceMap::from_byte_codes(&src_file, &[]));
+        is_big_value_struct_but_no_default(&Mapping {
+            output: 0,
+            start: SourcePos { line: 1, col: 1 },
+            end: SourcePos { line: 1, col: 1 },
+            raw_start: 0,
+            raw_end: 0,
+        });
+    }
+
+    #[test]
+    fn byte_codes_map_back_to_source() {
+        let src_file = UcSourceFile::from_str("é ++\n  [->]", "a.bf");
+        let byte_codes = src_file.to_byte_codes().unwrap();
+        let source_map = SourceMap::from_byte_codes(&src_file, &byte_codes);
+
+        assert_eq!(source_map.source, "a.bf");
+        assert_eq!(source_map.mappings.len(), 5);
+        let oracles = [
+            (0, (1, 3), (1, 5), (3, 5)),
+            (1, (2, 3), (2, 4), (8, 9)),
+            (2, (2, 4), (2, 5), (9, 10)),
+            (3, (2, 5), (2, 6), (10, 11)),
+            (4, (2, 6), (2, 7), (11, 12)),
+        ];
+        for (output, start, end, raw) in oracles {
+            let m = source_map.lookup(output).unwrap();
+            assert_eq!((m.start.line, m.start.col), start);
+            assert_eq!((m.end.line, m.end.col), end);
+            assert_eq!((m.raw_start, m.raw_end), raw);
+        }
+        assert!(source_map.lookup(5).is_none());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let src_file = UcSourceFile::from_str("+[-]>.", "");
+        let byte_codes = src_file.to_byte_codes().unwrap();
+        let source_map = SourceMap::from_byte_codes(&src_file, &byte_codes);
+
+        let json = source_map.to_json();
+        assert!(json.contains("\"target\": \"byte_codes\""));
+        assert_eq!(SourceMap::from_json(&json).unwrap(), source_map);
+        assert!(SourceMap::from_json("{}").is_err());
+    }
+}
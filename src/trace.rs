@@ -0,0 +1,802 @@
+//! records every executed instruction to a compact binary trace, and replays it forwards
+//! and backwards without running the program again
+//!
+//! a trace starts with a header
+//!
+//! ```text
+//! "BFTR" version:u8 tape_size:varint cell_width:u8 eof:u8 engine:u8 opt_level:u8 dialect:u8
+//!     source_crc32:u32
+//! ```
+//!
+//! followed by one record per step until the end of the file, a record is a tag byte
+//! followed by the fields the tag says are there
+//!
+//! ```text
+//! tag instr_ptr:varint? data_ptr_delta:zigzag? writes:(count:varint? cell_delta:zigzag*)?
+//!     input:(count:varint char:varint*)? output:(char:varint count:varint)?
+//! ```
+//!
+//! numbers are LEB128 varints, `instr_ptr` is left out when it is the previous one plus 1
+//! and `data_ptr_delta` when the data pointer does not move, a write is stored as the
+//! difference to the old value, and its count only if there is more than one, so most
+//! records of a running program are 1 or 2 bytes
+
+use crate::{
+    byte_code::OptLevel,
+    machine::{CellDataType, CellWidth, Engine, EofBehavior, MachineConfig, TapeWindow},
+    observer::{Observer, Op},
+    source_file::{Dialect, SourcePos, UcSourceFile, UcSourceFileError},
+    utility::{crc32, write_varint, DecodeError, Reader},
+};
+use std::io::Write;
+
+const MAGIC: &[u8; 4] = b"BFTR";
+
+/// instruction pointer is not the previous one plus 1
+const TAG_JUMP: u8 = 1;
+const TAG_MOVE: u8 = 1 << 1;
+const TAG_WRITES: u8 = 1 << 2;
+const TAG_INPUT: u8 = 1 << 3;
+const TAG_OUTPUT: u8 = 1 << 4;
+/// more than one write, e.g. `,,` as one byte code
+const TAG_MANY_WRITES: u8 = 1 << 5;
+
+/// the most chars a replay keeps as output
+const MAX_OUTPUT: usize = 1 << 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub enum TraceError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// the trace ends in the middle of a header or record
+    Truncated,
+    /// something decoded is out of its range, at this byte offset
+    Corrupted(usize),
+    /// the source file given to replay is not the traced one
+    SourceMismatch,
+}
+
+impl std::fmt::Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadMagic => f.write_str("not an execution trace"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported trace version {}, expecting {}",
+                v,
+                TraceHeader::VERSION
+            ),
+            Self::Truncated => f.write_str("trace is truncated"),
+            Self::Corrupted(offset) => write!(f, "trace is corrupted at byte {}", offset),
+            Self::SourceMismatch => f.write_str("source file is not the traced program"),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<DecodeError> for TraceError {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Truncated => Self::Truncated,
+            DecodeError::Corrupted(offset) => Self::Corrupted(offset),
+        }
+    }
+}
+
+/// how the traced program was run, needed to map instruction pointers back to source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct TraceHeader {
+    pub config: MachineConfig,
+    pub engine: Engine,
+    /// ignored by `Engine::Source`
+    pub opt_level: OptLevel,
+    /// of the traced source file, which decides what its instructions are
+    pub dialect: Dialect,
+    /// `TraceHeader::source_crc` of the traced source file
+    pub source_crc: u32,
+}
+
+impl TraceHeader {
+    pub const VERSION: u8 = 3;
+
+    /// crc32 of the raw content of `src_file`
+    pub fn source_crc(src_file: &UcSourceFile) -> u32 {
+        crc32(src_file.raw_content().as_bytes())
+    }
+
+    /// errors if `src_file` is not the traced program
+    pub fn check_source(&self, src_file: &UcSourceFile) -> Result<(), TraceError> {
+        if Self::source_crc(src_file) != self.source_crc {
+            return Err(TraceError::SourceMismatch);
+        }
+        Ok(())
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(MAGIC);
+        buf.push(Self::VERSION);
+        write_varint(buf, self.config.tape_size as u64);
+        buf.push(self.config.cell_width.bits() as u8);
+        buf.push(match self.config.eof {
+            EofBehavior::Zero => 0,
+            EofBehavior::Unchanged => 1,
+            EofBehavior::MaxValue => 2,
+        });
+        buf.push(match self.engine {
+            Engine::Source => 0,
+            Engine::ByteCode => 1,
+            Engine::Closure => 2,
+        });
+        buf.push(match self.opt_level {
+            OptLevel::O0 => 0,
+            OptLevel::O1 => 1,
+        });
+        buf.push(match self.dialect {
+            Dialect::Standard => 0,
+            Dialect::Extended => 1,
+        });
+        buf.extend_from_slice(&self.source_crc.to_le_bytes());
+    }
+
+    fn decode(r: &mut Reader<'_>) -> Result<Self, TraceError> {
+        if r.bytes(MAGIC.len()).map_err(|_| TraceError::BadMagic)? != MAGIC {
+            return Err(TraceError::BadMagic);
+        }
+        let version = r.byte()?;
+        if version != Self::VERSION {
+            return Err(TraceError::UnsupportedVersion(version));
+        }
+        let tape_size = r.usize()?;
+        if tape_size > MachineConfig::MAX_TAPE_SIZE {
+            return Err(r.corrupted().into());
+        }
+        let cell_width = match r.byte()? {
+            8 => CellWidth::U8,
+            16 => CellWidth::U16,
+            32 => CellWidth::U32,
+            _ => return Err(r.corrupted().into()),
+        };
+        let eof = match r.byte()? {
+            0 => EofBehavior::Zero,
+            1 => EofBehavior::Unchanged,
+            2 => EofBehavior::MaxValue,
+            _ => return Err(r.corrupted().into()),
+        };
+        let engine = match r.byte()? {
+            0 => Engine::Source,
+            1 => Engine::ByteCode,
+            2 => Engine::Closure,
+            _ => return Err(r.corrupted().into()),
+        };
+        let opt_level = match r.byte()? {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            _ => return Err(r.corrupted().into()),
+        };
+        let dialect = match r.byte()? {
+            0 => Dialect::Standard,
+            1 => Dialect::Extended,
+            _ => return Err(r.corrupted().into()),
+        };
+        let source_crc = u32::from_le_bytes(r.bytes(4)?.try_into().unwrap());
+        Ok(Self {
+            config: MachineConfig {
+                tape_size,
+                cell_width,
+                eof,
+            },
+            engine,
+            opt_level,
+            dialect,
+            source_crc,
+        })
+    }
+}
+
+/// one executed instruction
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Step {
+    pub instr_ptr: usize,
+    /// after the instruction
+    pub data_ptr: usize,
+    /// old and new values of the cell at `data_ptr`, in the order of writing
+    pub writes: Vec<(CellDataType, CellDataType)>,
+    /// `None` for end of input
+    pub input: Vec<Option<char>>,
+    /// a char written some times
+    pub output: Option<(char, usize)>,
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+/// `None` end of input is 0, chars are shifted by 1
+fn encode_input(c: Option<char>) -> u64 {
+    c.map_or(0, |c| u64::from(c) + 1)
+}
+
+fn read_input(r: &mut Reader<'_>) -> Result<Option<char>, TraceError> {
+    match r.varint()? {
+        0 => Ok(None),
+        n => u32::try_from(n - 1)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Some)
+            .ok_or_else(|| r.corrupted().into()),
+    }
+}
+
+/// an `Observer` writing a trace to `out` while the machine runs
+///
+/// the data pointer is followed through the `>` and `<` it sees, so the machine must
+/// start from a reset, as `Machine::eval` does
+#[derive(Debug)]
+pub struct TraceRecorder<W: Write> {
+    out: W,
+    buf: Vec<u8>,
+    /// of the previous record
+    instr_ptr: usize,
+    data_ptr: usize,
+    /// the instruction being executed
+    step: Option<(Op, usize, Step)>,
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write> TraceRecorder<W> {
+    pub fn new(mut out: W, header: &TraceHeader) -> Self {
+        let mut buf = Vec::new();
+        header.encode(&mut buf);
+        let error = out.write_all(&buf).err();
+        buf.clear();
+        Self {
+            out,
+            buf,
+            // so the first instruction at 0 is the previous plus 1
+            instr_ptr: usize::MAX,
+            data_ptr: header.config.initial_data_ptr(),
+            step: None,
+            error,
+        }
+    }
+
+    /// writes the instruction still being executed, if the machine stopped in the middle
+    /// of one, and returns the first io error if there was any
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.end_step();
+        self.flush_buf(0);
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush().map(|_| self.out),
+        }
+    }
+
+    fn flush_buf(&mut self, threshold: usize) {
+        if self.buf.len() > threshold {
+            if self.error.is_none() {
+                self.error = self.out.write_all(&self.buf).err();
+            }
+            self.buf.clear();
+        }
+    }
+
+    fn end_step(&mut self) {
+        let Some((op, arg, mut step)) = self.step.take() else {
+            return;
+        };
+        step.data_ptr = match op {
+            Op::IncPtr => self.data_ptr.wrapping_add(arg),
+            Op::DecPtr => self.data_ptr.wrapping_sub(arg),
+            _ => self.data_ptr,
+        };
+
+        let mut tag = 0;
+        if step.instr_ptr != self.instr_ptr.wrapping_add(1) {
+            tag |= TAG_JUMP;
+        }
+        if step.data_ptr != self.data_ptr {
+            tag |= TAG_MOVE;
+        }
+        if !step.writes.is_empty() {
+            tag |= TAG_WRITES;
+        }
+        if step.writes.len() > 1 {
+            tag |= TAG_MANY_WRITES;
+        }
+        if !step.input.is_empty() {
+            tag |= TAG_INPUT;
+        }
+        if step.output.is_some() {
+            tag |= TAG_OUTPUT;
+        }
+
+        let buf = &mut self.buf;
+        buf.push(tag);
+        if tag & TAG_JUMP != 0 {
+            write_varint(buf, step.instr_ptr as u64);
+        }
+        if tag & TAG_MOVE != 0 {
+            write_varint(buf, zigzag(step.data_ptr as i64 - self.data_ptr as i64));
+        }
+        if tag & TAG_MANY_WRITES != 0 {
+            write_varint(buf, step.writes.len() as u64);
+        }
+        for &(old, new) in &step.writes {
+            write_varint(buf, zigzag(i64::from(new) - i64::from(old)));
+        }
+        if tag & TAG_INPUT != 0 {
+            write_varint(buf, step.input.len() as u64);
+            for &c in &step.input {
+                write_varint(buf, encode_input(c));
+            }
+        }
+        if let Some((c, n)) = step.output {
+            write_varint(buf, u64::from(c));
+            write_varint(buf, n as u64);
+        }
+
+        self.instr_ptr = step.instr_ptr;
+        self.data_ptr = step.data_ptr;
+        self.flush_buf(64 * 1024);
+    }
+}
+
+impl<W: Write + std::fmt::Debug> Observer for TraceRecorder<W> {
+    fn before(&mut self, op: Op, arg: usize, instr_ptr: usize) {
+        self.end_step();
+        let step = Step {
+            instr_ptr,
+            ..Step::default()
+        };
+        self.step = Some((op, arg, step));
+    }
+
+    fn after(&mut self, _op: Op) {
+        self.end_step();
+    }
+
+    fn on_cell_write(&mut self, _data_ptr: usize, old: CellDataType, new: CellDataType) {
+        if let Some((_, _, step)) = &mut self.step {
+            step.writes.push((old, new));
+        }
+    }
+
+    fn on_output(&mut self, c: char, n: usize) {
+        if let Some((_, _, step)) = &mut self.step {
+            step.output = Some((c, n));
+        }
+    }
+
+    fn on_input(&mut self, c: Option<char>) {
+        if let Some((_, _, step)) = &mut self.step {
+            step.input.push(c);
+        }
+    }
+}
+
+/// machine state rebuilt from a trace, at any step
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub struct Replay {
+    header: TraceHeader,
+    steps: Vec<Step>,
+    /// number of steps applied
+    pos: usize,
+    cells: Vec<CellDataType>,
+    data_ptr: usize,
+    output: String,
+}
+
+impl Replay {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TraceError> {
+        let mut r = Reader::new(bytes);
+        let header = TraceHeader::decode(&mut r)?;
+
+        let mut steps = Vec::new();
+        // old values of writes are not in the trace
+        let mut cells = vec![0; header.config.tape_size];
+        let mut instr_ptr = usize::MAX;
+        let mut data_ptr = header.config.initial_data_ptr();
+        let mut output_len = 0_usize;
+        while !r.is_empty() {
+            let tag = r.byte()?;
+            if tag >= TAG_MANY_WRITES << 1
+                || tag & (TAG_WRITES | TAG_MANY_WRITES) == TAG_MANY_WRITES
+            {
+                return Err(TraceError::Corrupted(r.offset - 1));
+            }
+            let mut step = Step::default();
+            instr_ptr = if tag & TAG_JUMP != 0 {
+                r.usize()?
+            } else {
+                instr_ptr.wrapping_add(1)
+            };
+            step.instr_ptr = instr_ptr;
+            if tag & TAG_MOVE != 0 {
+                data_ptr = (data_ptr as i64).wrapping_add(unzigzag(r.varint()?)) as usize;
+            }
+            step.data_ptr = data_ptr;
+            let writes = match tag & (TAG_WRITES | TAG_MANY_WRITES) {
+                0 => 0,
+                TAG_WRITES => 1,
+                _ => r.varint()?,
+            };
+            for _ in 0..writes {
+                let offset = r.offset;
+                let cell = cells
+                    .get_mut(data_ptr)
+                    .ok_or(TraceError::Corrupted(offset))?;
+                let new = i64::from(*cell) + unzigzag(r.varint()?);
+                let new = CellDataType::try_from(new).map_err(|_| TraceError::Corrupted(offset))?;
+                step.writes.push((std::mem::replace(cell, new), new));
+            }
+            if tag & TAG_INPUT != 0 {
+                for _ in 0..r.varint()? {
+                    step.input.push(read_input(&mut r)?);
+                }
+            }
+            if tag & TAG_OUTPUT != 0 {
+                let c = r.char()?;
+                let offset = r.offset;
+                let n = r.usize()?;
+                output_len = output_len
+                    .checked_add(n)
+                    .filter(|&len| len <= MAX_OUTPUT)
+                    .ok_or(TraceError::Corrupted(offset))?;
+                step.output = Some((c, n));
+            }
+            steps.push(step);
+        }
+
+        Ok(Self {
+            header,
+            steps,
+            pos: 0,
+            cells: vec![0; header.config.tape_size],
+            data_ptr: header.config.initial_data_ptr(),
+            output: String::new(),
+        })
+    }
+
+    pub fn header(&self) -> &TraceHeader {
+        &self.header
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// number of steps applied, 0 before the first instruction
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.steps.len()
+    }
+
+    /// the instruction executed last, `None` at the start
+    pub fn last_step(&self) -> Option<&Step> {
+        self.pos.checked_sub(1).map(|pos| &self.steps[pos])
+    }
+
+    pub fn cells(&self) -> &[CellDataType] {
+        &self.cells
+    }
+
+    pub fn data_ptr(&self) -> usize {
+        self.data_ptr
+    }
+
+    /// cells within `radius` of the data pointer, clamped to the tape
+    pub fn tape_window(&self, radius: usize) -> TapeWindow {
+        let data_ptr = self.data_ptr.min(self.cells.len().saturating_sub(1));
+        let end = data_ptr.saturating_add(radius + 1).min(self.cells.len());
+        let start = data_ptr.saturating_sub(radius);
+        TapeWindow {
+            start,
+            cells: self.cells[start..end].to_vec(),
+            data_ptr: self.data_ptr,
+        }
+    }
+
+    /// everything written so far
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// every char the program read, what to feed it to run it the same way again
+    pub fn input(&self) -> String {
+        self.steps
+            .iter()
+            .flat_map(|step| step.input.iter().flatten())
+            .collect()
+    }
+
+    /// applies the next step, `false` at the end
+    pub fn step_forward(&mut self) -> bool {
+        let Some(step) = self.steps.get(self.pos) else {
+            return false;
+        };
+        if let Some(&(_, new)) = step.writes.last() {
+            self.cells[step.data_ptr] = new;
+        }
+        if let Some((c, n)) = step.output {
+            self.output.extend(std::iter::repeat_n(c, n));
+        }
+        self.data_ptr = step.data_ptr;
+        self.pos += 1;
+        true
+    }
+
+    /// undoes the last step, `false` at the start
+    pub fn step_back(&mut self) -> bool {
+        let Some(pos) = self.pos.checked_sub(1) else {
+            return false;
+        };
+        let step = &self.steps[pos];
+        if let Some(&(old, _)) = step.writes.first() {
+            self.cells[step.data_ptr] = old;
+        }
+        if let Some((_, n)) = step.output {
+            for _ in 0..n {
+                self.output.pop();
+            }
+        }
+        self.data_ptr = match pos.checked_sub(1) {
+            Some(prev) => self.steps[prev].data_ptr,
+            None => self.header.config.initial_data_ptr(),
+        };
+        self.pos = pos;
+        true
+    }
+
+    /// moves forwards or backwards until `pos` steps are applied, clamped to the trace
+    pub fn seek(&mut self, pos: usize) {
+        while self.pos < pos && self.step_forward() {}
+        while self.pos > pos && self.step_back() {}
+    }
+
+    /// source positions of instruction pointers, `src_file` must be the traced program,
+    /// see `TraceHeader::check_source`, loaded with `TraceHeader::dialect`
+    pub fn locations<'src_file>(
+        &self,
+        src_file: &'src_file UcSourceFile,
+    ) -> Result<Vec<SourcePos>, UcSourceFileError<'src_file>> {
+        self.header
+            .engine
+            .locations(src_file, self.header.opt_level)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::machine::Machine;
+    use crate::machine_io::BufferedMachineIO;
+
+    fn header(engine: Engine) -> TraceHeader {
+        TraceHeader {
+            config: MachineConfig {
+                tape_size: 16,
+                ..MachineConfig::default()
+            },
+            engine,
+            opt_level: OptLevel::O1,
+            dialect: Dialect::Standard,
+            source_crc: 0,
+        }
+    }
+
+    /// returns the trace and the machine after running
+    fn record(src: &str, input: &str, engine: Engine) -> (Vec<u8>, Machine<BufferedMachineIO>) {
+        let header = header(engine);
+        let src_file = UcSourceFile::from_str(src, "");
+        let recorder = TraceRecorder::new(Vec::new(), &header);
+        let mut machine =
+            Machine::with_observer(header.config, BufferedMachineIO::new(input), recorder);
+        let _ = machine.eval(&src_file, engine, header.opt_level);
+
+        let mut plain = Machine::with_config(header.config, BufferedMachineIO::new(input));
+        let _ = plain.eval(&src_file, engine, header.opt_level);
+        let trace = machine.into_observer().finish().unwrap();
+        (trace, plain)
+    }
+
+    #[test]
+    fn traits() {
+        use crate::utility::traits::*;
+
+        is_big_error(&TraceError::Corrupted(0));
+        is_small_value_struct_but_no_default(&header(Engine::Source));
+        is_default_debug(&Step::default());
+    }
+
+    #[test]
+    fn zigzags() {
+        for n in [0, 1, -1, 63, -64, i64::MAX, i64::MIN] {
+            assert_eq!(unzigzag(zigzag(n)), n);
+        }
+    }
+
+    #[test]
+    fn replay_to_the_end_and_back() {
+        let src = ",[>+++.<-],.";
+        for engine in Engine::all() {
+            let (trace, machine) = record(src, "\u{3}", *engine);
+            let mut replay = Replay::from_bytes(&trace).unwrap();
+            assert_eq!(replay.header(), &header(*engine));
+            assert_eq!(replay.input(), "\u{3}");
+
+            replay.seek(usize::MAX);
+            assert!(replay.is_at_end());
+            assert_eq!(replay.cells(), machine.cells());
+            assert_eq!(replay.data_ptr(), machine.data_ptr());
+            assert_eq!(replay.tape_window(2), machine.tape_window(2));
+            assert_eq!(replay.output(), machine.io().output());
+
+            // EOF on the second `,`
+            let last_read = &replay.steps()[replay.steps().len() - 2];
+            assert_eq!(last_read.input, vec![None]);
+            assert_eq!(last_read.writes, vec![(0, 0)]);
+
+            while replay.step_back() {}
+            assert_eq!(replay.pos(), 0);
+            assert!(replay.cells().iter().all(|&c| c == 0));
+            assert_eq!(replay.data_ptr(), 8);
+            assert_eq!(replay.output(), "");
+        }
+    }
+
+    #[test]
+    fn stepping_backwards_restores_every_state() {
+        let (trace, _) = record("++[>++[>+<-]<-]>>.", "", Engine::ByteCode);
+        let mut replay = Replay::from_bytes(&trace).unwrap();
+        let mut states = vec![(replay.cells().to_vec(), replay.data_ptr())];
+        while replay.step_forward() {
+            states.push((replay.cells().to_vec(), replay.data_ptr()));
+        }
+        assert_eq!(replay.output(), "\u{4}");
+        for pos in (0..states.len()).rev() {
+            assert_eq!((replay.cells().to_vec(), replay.data_ptr()), states[pos]);
+            replay.step_back();
+        }
+
+        replay.seek(3);
+        assert_eq!(replay.last_step().unwrap().instr_ptr, 2);
+    }
+
+    #[test]
+    fn traces_are_compact() {
+        let src = "++++++++[>++++++++<-]>[<++++>-]<[>+>+<<-]";
+        let (trace, machine) = record(src, "", Engine::Source);
+        let steps = Replay::from_bytes(&trace).unwrap().steps().len();
+        assert_eq!(steps as u64, machine.executed());
+        // header aside, each step takes less than 2 bytes on average
+        assert!(trace.len() < 14 + steps * 2, "{} bytes", trace.len());
+    }
+
+    #[test]
+    fn failing_instructions_are_recorded() {
+        let (trace, _) = record("<<<<<<<<<+", "", Engine::Source);
+        let replay = Replay::from_bytes(&trace).unwrap();
+        assert_eq!(replay.steps().len(), 10);
+        assert_eq!(replay.steps()[9].writes, vec![]);
+    }
+
+    #[test]
+    fn locations_of_both_engines() {
+        let src_file = UcSourceFile::from_str("++\n[-]", "");
+        let (trace, _) = record(src_file.raw_content(), "", Engine::Source);
+        let locations = Replay::from_bytes(&trace)
+            .unwrap()
+            .locations(&src_file)
+            .unwrap();
+        // newlines are tokens as well
+        assert_eq!(locations[3], SourcePos { line: 2, col: 1 });
+
+        let (trace, _) = record(src_file.raw_content(), "", Engine::ByteCode);
+        let locations = Replay::from_bytes(&trace)
+            .unwrap()
+            .locations(&src_file)
+            .unwrap();
+        assert_eq!(locations.len(), 4);
+        assert_eq!(locations[1], SourcePos { line: 2, col: 1 });
+    }
+
+    #[test]
+    fn dialect_is_recorded() {
+        let src_file = UcSourceFile::from_str_with_dialect("+#\n-!x", "", Dialect::Extended);
+        let header = TraceHeader {
+            dialect: Dialect::Extended,
+            ..header(Engine::ByteCode)
+        };
+        let recorder = TraceRecorder::new(Vec::new(), &header);
+        let mut machine =
+            Machine::with_observer(header.config, BufferedMachineIO::new(""), recorder);
+        machine
+            .eval(&src_file, header.engine, header.opt_level)
+            .unwrap();
+        let trace = machine.into_observer().finish().unwrap();
+
+        let replay = Replay::from_bytes(&trace).unwrap();
+        assert_eq!(replay.header().dialect, Dialect::Extended);
+        let locations = replay.locations(&src_file).unwrap();
+        assert_eq!(locations.len(), 3);
+        assert_eq!(locations[2], SourcePos { line: 2, col: 1 });
+    }
+
+    #[test]
+    fn source_is_checked() {
+        let src_file = UcSourceFile::from_str("+.", "");
+        let header = TraceHeader {
+            source_crc: TraceHeader::source_crc(&src_file),
+            ..header(Engine::Source)
+        };
+        let mut trace = Vec::new();
+        header.encode(&mut trace);
+        let replay = Replay::from_bytes(&trace).unwrap();
+        assert_eq!(replay.header().check_source(&src_file), Ok(()));
+        let other = UcSourceFile::from_str("+. ", "");
+        assert_eq!(
+            replay.header().check_source(&other),
+            Err(TraceError::SourceMismatch)
+        );
+        assert_eq!(
+            TraceError::SourceMismatch.to_string(),
+            "source file is not the traced program"
+        );
+    }
+
+    #[test]
+    fn sizes_in_traces_are_bounded() {
+        let mut huge_tape = header(Engine::Source);
+        huge_tape.config.tape_size = MachineConfig::MAX_TAPE_SIZE + 1;
+        let mut trace = Vec::new();
+        huge_tape.encode(&mut trace);
+        assert!(matches!(
+            Replay::from_bytes(&trace),
+            Err(TraceError::Corrupted(_))
+        ));
+
+        let mut trace = Vec::new();
+        header(Engine::Source).encode(&mut trace);
+        let header_len = trace.len();
+        trace.extend_from_slice(&[TAG_OUTPUT, b'a']);
+        write_varint(&mut trace, MAX_OUTPUT as u64 + 1);
+        assert_eq!(
+            Replay::from_bytes(&trace),
+            Err(TraceError::Corrupted(header_len + 2))
+        );
+    }
+
+    #[test]
+    fn bad_traces() {
+        let (trace, _) = record("+.", "", Engine::Source);
+        assert_eq!(Replay::from_bytes(b"BF"), Err(TraceError::BadMagic));
+        assert_eq!(Replay::from_bytes(b"ELF\0\x01"), Err(TraceError::BadMagic));
+
+        let mut bad = trace.clone();
+        bad[4] = 9;
+        assert_eq!(
+            Replay::from_bytes(&bad),
+            Err(TraceError::UnsupportedVersion(9))
+        );
+
+        assert_eq!(
+            Replay::from_bytes(&trace[..trace.len() - 1]),
+            Err(TraceError::Truncated)
+        );
+
+        let mut bad = trace.clone();
+        bad.push(0xff);
+        assert_eq!(
+            Replay::from_bytes(&bad),
+            Err(TraceError::Corrupted(trace.len()))
+        );
+    }
+}
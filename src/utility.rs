@@ -8,7 +8,7 @@ pub(crate) trait LoopCode {
     fn is_loop_end(&self) -> bool;
 }
 
-impl<'src_file> LoopCode for &'src_file ByteCode {
+impl LoopCode for &ByteCode {
     fn is_loop_start(&self) -> bool {
         self.kind == ByteCodeKind::LoopStartJumpIfDataZero
     }
@@ -18,7 +18,17 @@ impl<'src_file> LoopCode for &'src_file ByteCode {
     }
 }
 
-impl<'src_file> LoopCode for &'src_file UcToken {
+impl LoopCode for ByteCodeKind {
+    fn is_loop_start(&self) -> bool {
+        *self == ByteCodeKind::LoopStartJumpIfDataZero
+    }
+
+    fn is_loop_end(&self) -> bool {
+        *self == ByteCodeKind::LoopEndJumpIfDataNotZero
+    }
+}
+
+impl LoopCode for &UcToken {
     fn is_loop_start(&self) -> bool {
         self.uc == "["
     }
@@ -28,15 +38,22 @@ impl<'src_file> LoopCode for &'src_file UcToken {
     }
 }
 
-/// `idx` is
-/// - `RawContentIndex` when being returned from `Machine::eval_src_file`
-/// - `UcContentIndex`  when being returned from `UcSourceFile::to_byte_codes`
+/// `idx` is the `UcContentIndex` of the unmatched paren, both when being returned from
+/// `Machine::eval_source_file` and from `UcSourceFile::to_byte_codes`
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
 pub enum ExtraParen {
     Open { idx: usize },
     Close { idx: usize },
 }
 
+impl ExtraParen {
+    pub fn idx(&self) -> usize {
+        match self {
+            Self::Open { idx } | Self::Close { idx } => *idx,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) struct LoopMatches {
     start_to_end: std::collections::HashMap<usize, usize>,
@@ -61,10 +78,12 @@ impl LoopMatches {
 
 /// find the matching parens
 ///
-/// error can only occur during `UcSourceFile::to_byte_codes` and `Machine::eval_src_file`,
+/// error can only occur during `UcSourceFile::to_byte_codes` and `Machine::eval_source_file`,
 /// once byte codes generated, all parens are matched, so there won't be error generated
 /// from `Machine::eval_byte_codes`
-pub(crate) fn populate_loop_boundaries<I>(codes: I) -> Result<LoopMatches, ExtraParen>
+///
+/// all unmatched parens are reported at once, ordered by their positions
+pub(crate) fn populate_loop_boundaries<I>(codes: I) -> Result<LoopMatches, Vec<ExtraParen>>
 where
     I: Iterator,
     <I as Iterator>::Item: LoopCode,
@@ -75,12 +94,16 @@ where
     let mut end_to_start = HashMap::with_capacity(start_to_end.len());
 
     let mut starts = Vec::with_capacity(10);
+    let mut extra_parens = Vec::new();
 
     for (idx, code) in codes.enumerate() {
         if code.is_loop_start() {
             starts.push(idx);
         } else if code.is_loop_end() {
-            let start_idx = starts.pop().ok_or(ExtraParen::Close { idx })?;
+            let Some(start_idx) = starts.pop() else {
+                extra_parens.push(ExtraParen::Close { idx });
+                continue;
+            };
             let existed = start_to_end.insert(start_idx, idx);
             assert!(existed.is_none());
             let existed = end_to_start.insert(idx, start_idx);
@@ -88,9 +111,10 @@ where
         }
     }
 
-    if !starts.is_empty() {
-        let &idx = starts.last().unwrap();
-        return Err(ExtraParen::Open { idx });
+    if !starts.is_empty() || !extra_parens.is_empty() {
+        extra_parens.extend(starts.into_iter().map(|idx| ExtraParen::Open { idx }));
+        extra_parens.sort_by_key(ExtraParen::idx);
+        return Err(extra_parens);
     }
 
     Ok(LoopMatches {
@@ -99,6 +123,109 @@ where
     })
 }
 
+/// bytes being decoded end early or hold something out of its range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, std::hash::Hash)]
+pub(crate) enum DecodeError {
+    Truncated,
+    /// at this byte offset
+    Corrupted(usize),
+}
+
+/// LEB128, 7 bits a byte, the lowest first
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// reads what `write_varint` and alike write, from the start of `bytes`
+#[derive(Debug)]
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pub(crate) offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offset == self.bytes.len()
+    }
+
+    pub(crate) fn corrupted(&self) -> DecodeError {
+        DecodeError::Corrupted(self.offset)
+    }
+
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset.saturating_add(n))
+            .ok_or(DecodeError::Truncated)?;
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(self.corrupted())
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize, DecodeError> {
+        let n = self.varint()?;
+        usize::try_from(n).map_err(|_| self.corrupted())
+    }
+
+    pub(crate) fn char(&mut self) -> Result<char, DecodeError> {
+        let n = self.varint()?;
+        u32::try_from(n)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.corrupted())
+    }
+}
+
+/// CRC-32 of zlib and png, reflected with polynomial `0xEDB88320`
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                c = if c & 1 != 0 {
+                    0xEDB8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                bit += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+
+    !bytes.iter().fold(!0u32, |crc, &b| {
+        TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -109,42 +236,106 @@ mod test {
     fn traits() {
         is_small_value_enum(&ExtraParen::Open { idx: 3 });
         is_debug(&make_mock_loop_matches());
+        is_small_value_enum(&DecodeError::Truncated);
+        is_debug(&Reader::new(&[]));
+    }
+
+    #[test]
+    fn varints() {
+        for n in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, n);
+            let mut r = Reader::new(&buf);
+            assert_eq!(r.varint(), Ok(n));
+            assert!(r.is_empty());
+            assert_eq!(r.byte(), Err(DecodeError::Truncated));
+        }
+        assert_eq!(
+            Reader::new(&[0xff; 11]).varint(),
+            Err(DecodeError::Corrupted(10))
+        );
+    }
+
+    #[test]
+    fn crc32_of_known_inputs() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
     }
 
     #[test]
     fn extra_left_parens() {
         let test_data = [
-            ("[[]", 0),
-            ("aa[[]", 2),
-            ("+[[]", 1),
-            ("[]++[.[]", 4),
-            ("[][[]][", 6),
-            ("[][[[]][]", 2),
+            ("[[]", vec![0]),
+            ("aa[[]", vec![2]),
+            ("+[[]", vec![1]),
+            ("[]++[.[]", vec![4]),
+            ("[][[]][", vec![6]),
+            ("[][[[]][]", vec![2]),
+            ("[[[", vec![0, 1, 2]),
+            ("[+[]-[", vec![0, 5]),
         ];
 
         for td in test_data {
             let src_file = UcSourceFile::from_str(td.0, "");
             let err = populate_loop_boundaries(src_file.iter());
-            assert_eq!(err, Err(ExtraParen::Open { idx: td.1 }), "src: {}", td.0);
+            let oracle = td.1.into_iter().map(|idx| ExtraParen::Open { idx });
+            assert_eq!(err, Err(oracle.collect()), "src: {}", td.0);
         }
     }
 
     #[test]
     fn extra_right_parens() {
         let test_data = [
-            ("]", 0),
-            ("][", 0),
-            ("aa][", 2),
-            (".[[]]]", 5),
-            (".[[]]]]", 5),
-            ("[].][][]", 3),
-            ("[][][]][]", 6),
+            ("]", vec![0]),
+            ("aa]", vec![2]),
+            (".[[]]]", vec![5]),
+            (".[[]]]]", vec![5, 6]),
+            ("[].][][]", vec![3]),
+            ("[][][]][]", vec![6]),
+            ("]+]-[]]", vec![0, 2, 6]),
+        ];
+
+        for td in test_data {
+            let src_file = UcSourceFile::from_str(td.0, "");
+            let err = populate_loop_boundaries(src_file.iter());
+            let oracle = td.1.into_iter().map(|idx| ExtraParen::Close { idx });
+            assert_eq!(err, Err(oracle.collect()), "src: {}", td.0);
+        }
+    }
+
+    #[test]
+    fn extra_left_and_right_parens() {
+        let test_data = [
+            (
+                "][",
+                vec![ExtraParen::Close { idx: 0 }, ExtraParen::Open { idx: 1 }],
+            ),
+            (
+                "aa][",
+                vec![ExtraParen::Close { idx: 2 }, ExtraParen::Open { idx: 3 }],
+            ),
+            (
+                "[[]]][[]",
+                vec![ExtraParen::Close { idx: 4 }, ExtraParen::Open { idx: 5 }],
+            ),
+            (
+                "[]][[[]][",
+                vec![
+                    ExtraParen::Close { idx: 2 },
+                    ExtraParen::Open { idx: 3 },
+                    ExtraParen::Open { idx: 8 },
+                ],
+            ),
         ];
 
         for td in test_data {
             let src_file = UcSourceFile::from_str(td.0, "");
             let err = populate_loop_boundaries(src_file.iter());
-            assert_eq!(err, Err(ExtraParen::Close { idx: td.1 }), "src: {}", td.0);
+            assert_eq!(err, Err(td.1), "src: {}", td.0);
         }
     }
 
@@ -183,428 +374,6 @@ mod test {
     }
 }
 
-#[cfg(feature = "instr_tracing")]
-pub(crate) mod tracing {
-    use smol_str::SmolStr;
-    use thousands::Separable;
-
-    #[derive(Debug)]
-    pub(crate) struct InstructionTracingCollector {
-        c: std::collections::HashMap<SmolStr, u128>,
-    }
-
-    #[derive(Debug)]
-    struct TracingData {
-        name: SmolStr,
-        count: u128,
-    }
-
-    impl std::fmt::Display for TracingData {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            writeln!(
-                f,
-                "{:10}: {:>15}",
-                self.name,
-                self.count.separate_with_commas()
-            )
-        }
-    }
-
-    impl InstructionTracingCollector {
-        pub(crate) fn new(codes: &[&str]) -> Self {
-            let mut c = std::collections::HashMap::with_capacity(20);
-            for &n in codes {
-                c.insert(SmolStr::from(n), 0);
-            }
-            Self { c }
-        }
-
-        pub(crate) fn add(&mut self, name: &str) {
-            let name = SmolStr::from(name);
-            if !self.c.contains_key(&name) {
-                panic!("`{}` doesn't exist when constructing this collector", name);
-            }
-            self.c.entry(name).and_modify(|e| *e += 1);
-        }
-
-        pub(crate) fn finalize_to_string(&mut self) -> String {
-            self.finalize(|vec| {
-                let mut s = String::new();
-                let mut total = 0;
-                vec.iter()
-                    .fold((&mut s, &mut total), |(acc_s, acc_total), (k, v)| {
-                        acc_s.push_str(&format!("{:10}: {:>15}\n", k, v.separate_with_commas()));
-                        *acc_total += *v;
-                        (acc_s, acc_total)
-                    });
-                s.push_str(&format!("{:-<1$}\n", "", 27));
-                s.push_str(&format!(
-                    "{:10}: {:>15}\n",
-                    "TOTAL",
-                    total.separate_with_commas()
-                ));
-                s
-            })
-        }
-
-        fn finalize<F, R>(&mut self, f: F) -> R
-        where
-            F: Fn(&[(&SmolStr, &u128)]) -> R,
-        {
-            let mut vec = self.c.iter().filter(|(_, &v)| v > 0).collect::<Vec<_>>();
-            vec.sort_by_key(|(_, &v)| std::cmp::Reverse(v));
-
-            let r = f(&vec);
-
-            // reset
-            self.c.values_mut().for_each(|v| *v = 0);
-
-            r
-        }
-    }
-
-    #[cfg(test)]
-    mod test {
-        use super::*;
-        use crate::utility::traits::is_debug;
-
-        #[test]
-        fn traits() {
-            is_debug(&InstructionTracingCollector {
-                c: std::collections::HashMap::new(),
-            });
-            is_debug(&TracingData {
-                name: smol_str::SmolStr::default(),
-                count: 0,
-            });
-        }
-
-        #[test]
-        fn no_data_then_collect_nothing() {
-            let mut collector = InstructionTracingCollector::new(&["a", "b"]);
-            collector.finalize(|s| {
-                assert_eq!(s.len(), 0);
-            });
-        }
-
-        #[test]
-        fn only_collect_one_then_show_one() {
-            let mut collector = InstructionTracingCollector::new(&["a", "b"]);
-            collector.add("b");
-            collector.add("b");
-            collector.finalize(|s| {
-                assert_eq!(s.len(), 1);
-                assert_eq!(s[0].0, "b");
-                assert_eq!(*s[0].1, 2);
-            });
-        }
-
-        #[test]
-        fn name_not_in_codes_should_panic() {
-            let result = std::panic::catch_unwind(|| {
-                let mut collector = InstructionTracingCollector::new(&["a", "b"]);
-                collector.add("c");
-            });
-            assert!(result.is_err());
-        }
-    }
-}
-
-#[cfg(feature = "instr_timing")]
-pub(crate) mod timing {
-    use smol_str::SmolStr;
-    use thousands::Separable;
-
-    /// data point for each instruction execution time
-    ///
-    /// since there could be billions of data points for one instruction,
-    /// put all data points in one vector will cause oom-kill
-    /// `pt` stores the latest N data points, once it exceeds certain limits,
-    /// the its mean will be stored in `pt_agg`, which will be treated as the
-    /// "real" data point at the data analyses phase.
-    ///
-    /// # Notes
-    ///
-    /// For collecting the residue of the data point in `pt` when analyzing data,
-    /// `collect_pt` must be called first
-    #[derive(Debug)]
-    struct Points {
-        pt: Vec<f32>,
-        pt_agg: Vec<f32>,
-    }
-
-    impl Points {
-        fn new() -> Self {
-            Self {
-                pt: Vec::with_capacity(Self::threshold()),
-                pt_agg: Vec::with_capacity(5_000_000_000 / Self::threshold()),
-            }
-        }
-        /// add a data point
-        ///
-        /// if `pt` threshold reaches, then data in `pt` will be collected
-        /// and "moved" to `pt_agg`,
-        fn add(&mut self, u: u128) {
-            self.pt.push(u as f32);
-            if self.pt.len() > Self::threshold() {
-                self.collect_pt();
-            }
-        }
-        /// collect all data in `pt` to `pt_agg` and clear `pt`
-        fn collect_pt(&mut self) {
-            if !self.pt.is_empty() {
-                let mean = self.pt.iter().sum::<f32>() / self.pt.len() as f32;
-                self.pt.clear();
-                self.pt_agg.push(mean);
-            }
-        }
-        fn threshold() -> usize {
-            5_000
-        }
-    }
-
-    #[derive(Debug)]
-    struct Stat {
-        name: SmolStr,
-        mean: f32,
-        min: u128,
-        max: u128,
-        std_deviation: f32,
-    }
-
-    /// collecting execution times for given instructions
-    #[derive(Debug)]
-    pub(crate) struct InstructionTimingCollector {
-        c: std::collections::HashMap<SmolStr, Points>,
-    }
-
-    impl InstructionTimingCollector {
-        /// `codes` are all "instructions" you want to collect for.
-        ///
-        /// If an instruction name is not given in `code`, and later used by `start` will cause
-        /// assertion failure. A `BASELINE` "instruction" will be added as well, it indications
-        /// the time consumption of no-op
-        pub(crate) fn new(codes: &[&str]) -> Self {
-            let mut c = std::collections::HashMap::with_capacity(20);
-            for &n in codes.iter().chain(std::iter::once(&Self::baseline())) {
-                c.insert(SmolStr::from(n), Points::new());
-            }
-            Self { c }
-        }
-
-        /// `name` is the instruction name given when `new` is called
-        ///
-        /// # Panics
-        ///
-        /// If the `name` does not in the `codes` during collector destruction
-        pub(crate) fn start(&mut self, name: &str) -> InstructionTiming {
-            let name = SmolStr::from(name);
-            if !self.c.contains_key(&name) {
-                panic!("`{}` doesn't exist when constructing this collector", name);
-            }
-
-            InstructionTiming {
-                c: self,
-                a: std::time::Instant::now(),
-                name,
-            }
-        }
-
-        /// this must be called before starting collection, otherwise, data points will mix up
-        pub(crate) fn finalize_to_string(&mut self) -> String {
-            self.finalize(|v| {
-                let mut s = String::new();
-                for Stat {
-                    name,
-                    mean,
-                    min,
-                    max,
-                    std_deviation,
-                } in v.iter()
-                {
-                    let cv = std_deviation / mean;
-                    let cv_s = {
-                        let cv_s = format!("{:>6.2}", cv);
-                        if cv > 1.0 && console::Term::stdout().features().colors_supported() {
-                            console::style(cv_s).red().to_string()
-                        } else {
-                            cv_s
-                        }
-                    };
-                    s.push_str(&format!(
-                        "{:10}: mean ={:>7} std ={:>8.2} cv ={} | min ={:7}, max ={:7}\n",
-                        name,
-                        (*mean as u128).separate_with_commas(),
-                        std_deviation,
-                        cv_s,
-                        min,
-                        max
-                    ));
-                }
-                s
-            })
-        }
-
-        fn finalize<F, R>(&mut self, f: F) -> R
-        where
-            F: Fn(&[Stat]) -> R,
-        {
-            (0..5_000_000).for_each(|_| {
-                let _a = self.start(Self::baseline());
-            });
-            self.c.values_mut().for_each(Points::collect_pt);
-
-            let r = f(&self.collect());
-
-            // reset
-            self.reset();
-
-            r
-        }
-
-        /// reset collector for reuse
-        ///
-        /// # Panics
-        ///
-        /// If there are still data left
-        fn reset(&mut self) {
-            for (k, v) in self.c.iter_mut() {
-                if !v.pt.is_empty() {
-                    panic!(
-                        "{} data points of `{}` have not been collected",
-                        v.pt.len(),
-                        k
-                    );
-                }
-
-                v.pt.clear();
-                v.pt_agg.clear();
-            }
-        }
-
-        const fn baseline() -> &'static str {
-            "BASELINE"
-        }
-
-        fn to_stats(name: &SmolStr, points: &[f32]) -> Option<Stat> {
-            if points.is_empty() {
-                return None;
-            }
-
-            let (min, max) = (
-                points.iter().map(|&e| e as u128).min().unwrap(),
-                points.iter().map(|&e| e as u128).max().unwrap(),
-            );
-            let mean = points.iter().sum::<f32>() / points.len() as f32;
-            let std_deviation = {
-                let variance = points
-                    .iter()
-                    .map(|e| {
-                        let diff = mean - *e;
-                        diff * diff
-                    })
-                    .sum::<f32>()
-                    / points.len() as f32;
-                variance.sqrt()
-            };
-            Some(Stat {
-                name: name.clone(),
-                mean,
-                min,
-                max,
-                std_deviation,
-            })
-        }
-
-        fn collect(&self) -> Vec<Stat> {
-            self.c
-                .iter()
-                .filter_map(|(k, v)| Self::to_stats(k, &v.pt_agg))
-                .collect()
-        }
-    }
-
-    /// collects all data points for one instruction in RAII style
-    #[derive(Debug)]
-    pub(crate) struct InstructionTiming<'c> {
-        c: &'c mut InstructionTimingCollector,
-        a: std::time::Instant,
-        name: SmolStr,
-    }
-
-    /// data point added during `drop`
-    impl<'c> Drop for InstructionTiming<'c> {
-        fn drop(&mut self) {
-            self.c
-                .c
-                .get_mut(&self.name)
-                .unwrap()
-                .add(self.a.elapsed().as_nanos());
-        }
-    }
-
-    #[cfg(test)]
-    mod test {
-        use super::*;
-        use crate::utility::traits::is_debug;
-
-        #[test]
-        fn traits() {
-            is_debug(&Points {
-                pt: Vec::new(),
-                pt_agg: Vec::new(),
-            });
-            is_debug(&Stat {
-                name: smol_str::SmolStr::default(),
-                mean: 0.0,
-                min: 0,
-                max: 0,
-                std_deviation: 0.0,
-            });
-            is_debug(&InstructionTimingCollector {
-                c: std::collections::HashMap::new(),
-            });
-        }
-
-        #[test]
-        fn start_never_called_then_only_baseline_collected() {
-            let mut collector = InstructionTimingCollector::new(&["a", "b"]);
-            collector.finalize(|s| {
-                assert_eq!(s.len(), 1); // BASELINE
-            });
-        }
-
-        #[test]
-        fn only_collect_one_then_show_one() {
-            let mut collector = InstructionTimingCollector::new(&["a", "b"]);
-            {
-                let _t = collector.start("b");
-            }
-            collector.finalize(|s| {
-                assert_eq!(s.len(), 2); // BASELINE and b
-                if s[0].name == "BASELINE" {
-                    assert!(s[1].name == "b");
-                } else if s[0].name == "b" {
-                    assert!(s[1].name == "BASELINE");
-                } else {
-                    panic!("{:?} doesn't have correct values", s);
-                }
-            });
-        }
-
-        #[test]
-        fn name_not_in_codes_should_panic() {
-            let result = std::panic::catch_unwind(|| {
-                let mut collector = InstructionTimingCollector::new(&["a", "b"]);
-                {
-                    let _t = collector.start("c");
-                }
-            });
-            assert!(result.is_err());
-        }
-    }
-}
-
 #[cfg(test)]
 pub(crate) mod traits {
     /// have everything a value type should have, but no meaningful default
@@ -0,0 +1,2 @@
+never stops
++[]
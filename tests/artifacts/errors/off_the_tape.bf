@@ -0,0 +1,2 @@
+walks off the left end of a short tape
++[<+]
@@ -0,0 +1,2 @@
+one too many
++[[-]
@@ -1,62 +1,246 @@
-use brainfuck::{machine::Machine, machine_io::MachineIO, source_file::UcSourceFile};
-use serde::Deserialize;
+use brainfuck::{
+    byte_code::{validate, ByteCode, ByteCodeKind, OptLevel, ProgramBuilder},
+    differential::compare,
+    golden::{exit_status, Manifest},
+    machine::{Machine, MachineConfig},
+    machine_io::BufferedMachineIO,
+    minifier::{minify, MinifyOptions},
+    object::ObjectFile,
+    source_file::UcSourceFile,
+};
 
-#[derive(Debug)]
-struct DebugMachineIO {
-    out_buf: std::cell::RefCell<String>,
+#[test]
+fn it_works() {
+    let manifest = Manifest::load("tests/artifacts/oracles.json").unwrap();
+    for t in manifest.tests() {
+        let report = manifest.run(t, None);
+        assert!(report.passed(), "{}", report);
+    }
 }
 
-impl MachineIO for &DebugMachineIO {
-    fn out_char_n_times(&mut self, c: char, n: usize) {
-        self.out_buf.borrow_mut().push_str(&c.to_string().repeat(n));
+#[test]
+fn minified_programs_output_the_same() {
+    let manifest = Manifest::load("tests/artifacts/oracles.json").unwrap();
+    let options = MinifyOptions {
+        cancel_pairs: true,
+        dead_loops: true,
+        ..MinifyOptions::default()
+    };
+    for t in manifest.tests() {
+        let src_file = UcSourceFile::new(manifest.path_of(&t.src_file)).unwrap();
+        let minified = match minify(&src_file, &options) {
+            Ok(minified) => minified,
+            Err(_) => {
+                assert_eq!(t.exit_status, exit_status::SOURCE_ERROR);
+                continue;
+            }
+        };
+        assert!(minified.minified_size <= minified.original_size);
+
+        let src_file = UcSourceFile::from_str(&minified.code, &t.src_file);
+        let report = manifest.run_source(t, &src_file, None);
+        assert!(report.passed(), "minified {}", report);
     }
+}
 
-    fn in_char(&mut self) -> char {
-        todo!()
+#[test]
+fn engines_agree_on_every_oracle() {
+    let manifest = Manifest::load("tests/artifacts/oracles.json").unwrap();
+    for t in manifest.tests() {
+        let src_file = UcSourceFile::new(manifest.path_of(&t.src_file)).unwrap();
+        let input = t
+            .input
+            .as_ref()
+            .map(|path| std::fs::read_to_string(manifest.path_of(path)).unwrap())
+            .unwrap_or_default();
+        let Ok(comparison) = compare(&src_file, t.config().unwrap(), &input, t.step_limit) else {
+            assert_eq!(t.exit_status, exit_status::SOURCE_ERROR);
+            continue;
+        };
+        assert_eq!(comparison.divergences(), vec![], "{}", t.name());
     }
+}
+
+#[test]
+fn compiled_programs_output_the_same() {
+    let manifest = Manifest::load("tests/artifacts/oracles.json").unwrap();
+    for t in manifest.tests() {
+        let src_file = manifest.source_file(t).unwrap();
+        let input = manifest.input(t, &src_file).unwrap();
+        let Ok(object) = ObjectFile::compile(&src_file, t.config().unwrap(), OptLevel::O1, true)
+        else {
+            assert_eq!(t.exit_status, exit_status::SOURCE_ERROR);
+            continue;
+        };
+        let object = ObjectFile::from_bytes(&object.to_bytes()).unwrap();
 
-    fn flush_all(&mut self) {
-        self.out_buf.borrow_mut().clear();
+        let mut machine = Machine::with_config(object.config, BufferedMachineIO::new(&input));
+        machine.set_step_limit(t.step_limit);
+        let result = machine.eval_dense_byte_codes(&object.byte_codes);
+        assert_eq!(result.is_ok(), t.exit_status == 0, "{}", t.name());
+        if let Some(expected) = manifest.expected_output(t).unwrap() {
+            assert_eq!(machine.io().output(), expected, "{}", t.name());
+        }
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct Test {
-    src_file: String,
-    output: String,
+#[test]
+fn programs_can_be_built_and_rewritten_outside_the_crate() {
+    // 'A' from 8 * 8 + 1
+    let mut builder = ProgramBuilder::new();
+    builder
+        .push(ByteCodeKind::IncData, 8)
+        .unwrap()
+        .push(ByteCodeKind::LoopStartJumpIfDataZero, 1)
+        .unwrap()
+        .push(ByteCodeKind::IncPtr, 1)
+        .unwrap()
+        .push(ByteCodeKind::IncData, 8)
+        .unwrap()
+        .push(ByteCodeKind::DecPtr, 1)
+        .unwrap()
+        .push(ByteCodeKind::DecData, 1)
+        .unwrap()
+        .push(ByteCodeKind::LoopEndJumpIfDataNotZero, 1)
+        .unwrap()
+        .push(ByteCodeKind::IncPtr, 1)
+        .unwrap()
+        .push(ByteCodeKind::IncData, 1)
+        .unwrap()
+        .push(ByteCodeKind::Write, 1)
+        .unwrap();
+    let byte_codes = builder.build().unwrap();
+    let mut machine = Machine::with_config(MachineConfig::default(), BufferedMachineIO::new(""));
+    machine.eval_byte_codes(&byte_codes).unwrap();
+    assert_eq!(machine.io().output(), "A");
+
+    // a pass writing every cell twice as often
+    let src_file = UcSourceFile::from_str("+++.", "");
+    let doubled = src_file
+        .to_byte_codes()
+        .unwrap()
+        .iter()
+        .map(|bc| match bc.kind() {
+            ByteCodeKind::Write => ByteCode::new(bc.kind(), bc.arg() * 2, bc.range_in_raw()),
+            _ => Ok(bc.clone()),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(validate(&doubled), Ok(()));
+    let pos = src_file.pos_of(doubled[1].range_in_raw().0);
+    assert_eq!((pos.line, pos.col), (1, 4));
+    let mut machine = Machine::with_config(MachineConfig::default(), BufferedMachineIO::new(""));
+    machine.eval_byte_codes(&doubled).unwrap();
+    assert_eq!(machine.io().output(), "\u{3}\u{3}");
+}
+
+/// runs a binary of the crate with `stdin` piped in, panics if it does not exit in time
+fn run_bin(bin: &str, args: &[&str], stdin: &str) -> std::process::Output {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(bin)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let start = std::time::Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > std::time::Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("{} {:?} hangs", bin, args);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    child.wait_with_output().unwrap()
 }
 
 #[test]
-fn it_works() {
-    use std::path::Path;
+fn repl_reads_program_input_from_stdin_as_well() {
+    let output = run_bin(env!("CARGO_BIN_EXE_bfi"), &["repl"], "+++++,.\nabc\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // `,` takes the first char of the next line
+    assert!(stdout.starts_with("bf> a\n"), "{}", stdout);
+    assert!(stdout.contains("    97"), "{}", stdout);
+}
+
+#[test]
+fn debugged_programs_read_nothing_without_an_input_file() {
+    let path = std::env::temp_dir().join(format!("bfdb_input_{}.bf", std::process::id()));
+    std::fs::write(&path, ",.").unwrap();
+    let output = run_bin(
+        env!("CARGO_BIN_EXE_bfdb"),
+        &[path.to_str().unwrap()],
+        "c\nq\n",
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("program finished, 2 commands executed"),
+        "{}",
+        stdout
+    );
+}
 
-    let test_base_dir = Path::new("tests/artifacts");
+#[test]
+fn traces_only_replay_with_the_traced_source() {
+    let dir = std::env::temp_dir();
+    let id = std::process::id();
+    let src = dir.join(format!("bfi_replay_{}.bf", id));
+    let other = dir.join(format!("bfi_replay_other_{}.bf", id));
+    let trace = dir.join(format!("bfi_replay_{}.trace", id));
+    std::fs::write(&src, "+++.").unwrap();
+    std::fs::write(&other, "+.").unwrap();
+    let bfi = env!("CARGO_BIN_EXE_bfi");
+    let (src, other, trace) = (
+        src.to_str().unwrap(),
+        other.to_str().unwrap(),
+        trace.to_str().unwrap(),
+    );
 
-    let json = std::fs::read_to_string(test_base_dir.join("oracles.json")).unwrap();
-    let tests: Vec<Test> = serde_json::from_str(&json).unwrap();
-    let io = &DebugMachineIO {
-        out_buf: std::cell::RefCell::new(String::new()),
-    };
-    for t in &tests {
-        let mut machine = Machine::with_io(30_000, io);
-        let src_file = UcSourceFile::new(test_base_dir.join(&t.src_file)).unwrap();
-        machine.eval_source_file(&src_file);
-
-        let output = std::fs::read_to_string(test_base_dir.join(&t.output)).unwrap();
-        assert_eq!(
-            output,
-            io.out_buf.borrow().as_ref(),
-            "source file eval failed on {}",
-            t.src_file
-        );
-
-        let byte_codes = src_file.to_byte_codes().unwrap();
-        machine.eval_byte_codes(&byte_codes);
-        assert_eq!(
-            output,
-            io.out_buf.borrow().as_ref(),
-            "byte codes eval failed on {}",
-            t.src_file
-        );
+    let recorded = run_bin(bfi, &["run", "--record", trace, src], "");
+    assert!(recorded.status.success());
+    let replayed = run_bin(bfi, &["replay", trace, src], "end\n");
+    assert!(replayed.status.success());
+    let stdout = String::from_utf8(replayed.stdout).unwrap();
+    assert!(stdout.contains("step 2/2, after 1:4"), "{}", stdout);
+
+    let mismatched = run_bin(bfi, &["replay", trace, other], "");
+    assert_eq!(
+        mismatched.status.code(),
+        Some(i32::from(exit_status::SOURCE_ERROR))
+    );
+    let stderr = String::from_utf8(mismatched.stderr).unwrap();
+    assert!(stderr.contains("not the traced program"), "{}", stderr);
+
+    for path in [src, other, trace] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn profiling_runs_only_byte_codes() {
+    let bfi = env!("CARGO_BIN_EXE_bfi");
+    let src = "tests/artifacts/hello_world_1.bf";
+    let profiled = run_bin(bfi, &["run", "--profile", "--time", src], "");
+    assert!(profiled.status.success());
+    let stderr = String::from_utf8(profiled.stderr).unwrap();
+    assert!(stderr.contains("eval (byte-code)"), "{}", stderr);
+
+    let explicit = run_bin(bfi, &["run", "--engine", "byte-code", "--profile", src], "");
+    assert!(explicit.status.success());
+    for engine in ["source", "closure"] {
+        let output = run_bin(bfi, &["run", "--engine", engine, "--profile", src], "");
+        assert_eq!(output.status.code(), Some(2), "{}", engine);
     }
 }